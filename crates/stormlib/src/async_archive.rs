@@ -0,0 +1,248 @@
+use std::future::Future;
+use std::io;
+use std::path::PathBuf;
+use std::pin::Pin;
+use std::sync::mpsc;
+use std::task::{Context, Poll};
+use std::thread;
+
+use tokio::io::{AsyncRead, ReadBuf};
+use tokio::sync::oneshot;
+
+use crate::error::*;
+use crate::{Archive, FileEntry, OpenArchiveFlags};
+
+type Job = Box<dyn FnOnce(&mut Archive) + Send>;
+
+/// Handle to an archive owned by a dedicated worker thread
+///
+/// StormLib handles are neither thread safe nor cheap to use, so every call is
+/// queued to the thread that opened the archive and awaited from there. Clones
+/// share the same worker, which exits once the last clone is dropped.
+#[derive(Debug, Clone)]
+pub struct AsyncArchive {
+    jobs: mpsc::Sender<Job>,
+}
+
+impl AsyncArchive {
+    /// Opens a MPQ archive on a new worker thread
+    pub async fn open<P: Into<PathBuf>>(path: P, flags: OpenArchiveFlags) -> Result<Self> {
+        let path = path.into();
+        Self::spawn(move || Archive::open(path, flags)).await
+    }
+
+    /// Creates a MPQ archive on a new worker thread
+    pub async fn create<P: Into<PathBuf>>(
+        path: P,
+        filecount: usize,
+        use_filelist: bool,
+    ) -> Result<Self> {
+        let path = path.into();
+        Self::spawn(move || Archive::create(path, filecount, use_filelist)).await
+    }
+
+    async fn spawn<F>(open: F) -> Result<Self>
+    where
+        F: FnOnce() -> Result<Archive> + Send + 'static,
+    {
+        let (jobs, queue) = mpsc::channel::<Job>();
+        let (opened, result) = oneshot::channel();
+        thread::Builder::new()
+            .name("stormlib".to_string())
+            .spawn(move || {
+                let mut archive = match open() {
+                    Ok(archive) => {
+                        opened.send(Ok(())).ok();
+                        archive
+                    }
+                    Err(err) => {
+                        opened.send(Err(err)).ok();
+                        return;
+                    }
+                };
+                for job in queue {
+                    job(&mut archive);
+                }
+            })?;
+        result.await.map_err(|_| StormError::WorkerStopped)??;
+        Ok(AsyncArchive { jobs })
+    }
+
+    fn queue<T, F>(&self, f: F) -> oneshot::Receiver<Result<T>>
+    where
+        T: Send + 'static,
+        F: FnOnce(&mut Archive) -> Result<T> + Send + 'static,
+    {
+        let (tx, rx) = oneshot::channel();
+        // a closed queue drops `tx`, which the receiver reports as `WorkerStopped`
+        self.jobs
+            .send(Box::new(move |archive| {
+                tx.send(f(archive)).ok();
+            }))
+            .ok();
+        rx
+    }
+
+    pub async fn has_file(&self, path: &str) -> Result<bool> {
+        let path = path.to_string();
+        self.with(move |archive| archive.has_file(&path)).await
+    }
+
+    /// Reads a whole file into memory
+    pub async fn read_file(&self, path: &str) -> Result<Vec<u8>> {
+        let path = path.to_string();
+        self.with(move |archive| archive.open_file(&path)?.read_all())
+            .await
+    }
+
+    /// Opens a file for reading through `AsyncRead`
+    ///
+    /// The content is read on the worker thread while the returned file is
+    /// polled, opening fails early if the file doesn't exist.
+    pub async fn open_file(&self, path: &str) -> Result<AsyncFile> {
+        let path = path.to_string();
+        let size = {
+            let path = path.clone();
+            self.with(move |archive| archive.open_file(&path)?.get_size())
+                .await?
+        };
+        let content = self.queue(move |archive| archive.open_file(&path)?.read_all());
+        Ok(AsyncFile {
+            size,
+            state: FileState::Reading(content),
+        })
+    }
+
+    pub async fn write_file(&self, file_name: &str, data: Vec<u8>) -> Result<bool> {
+        let file_name = file_name.to_string();
+        self.with(move |archive| archive.write_file(&file_name, &data))
+            .await
+    }
+
+    pub async fn add_file(&self, path: &str, local_path: &str) -> Result<()> {
+        let path = path.to_string();
+        let local_path = local_path.to_string();
+        self.with(move |archive| archive.add_file(&path, &local_path))
+            .await
+    }
+
+    pub async fn remove_file(&self, path: &str) -> Result<bool> {
+        let path = path.to_string();
+        self.with(move |archive| archive.remove_file(&path)).await
+    }
+
+    pub async fn find(&self, mask: &str) -> Result<Vec<FileEntry>> {
+        let mask = mask.to_string();
+        self.with(move |archive| archive.find(&mask)).await
+    }
+
+    pub async fn list(&self) -> Result<Vec<FileEntry>> {
+        self.with(|archive| archive.list()).await
+    }
+
+    pub async fn compact(&self) -> Result<()> {
+        self.with(|archive| archive.compact()).await
+    }
+
+    /// Runs `f` on the worker thread, for calls without an async counterpart
+    pub async fn with<T, F>(&self, f: F) -> Result<T>
+    where
+        T: Send + 'static,
+        F: FnOnce(&mut Archive) -> Result<T> + Send + 'static,
+    {
+        self.queue(f).await.map_err(|_| StormError::WorkerStopped)?
+    }
+}
+
+/// File of an [`AsyncArchive`], read through `AsyncRead`
+#[derive(Debug)]
+pub struct AsyncFile {
+    size:  u64,
+    state: FileState,
+}
+
+#[derive(Debug)]
+enum FileState {
+    Reading(oneshot::Receiver<Result<Vec<u8>>>),
+    Ready(io::Cursor<Vec<u8>>),
+}
+
+impl AsyncFile {
+    /// Uncompressed size of the file
+    pub fn size(&self) -> u64 {
+        self.size
+    }
+}
+
+impl AsyncRead for AsyncFile {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        loop {
+            match &mut self.state {
+                FileState::Reading(content) => {
+                    let content = match Pin::new(content).poll(cx) {
+                        Poll::Pending => return Poll::Pending,
+                        Poll::Ready(content) => content.map_err(|_| StormError::WorkerStopped),
+                    };
+                    match content.and_then(|content| content) {
+                        Ok(content) => self.state = FileState::Ready(io::Cursor::new(content)),
+                        Err(StormError::Io(err)) => return Poll::Ready(Err(err)),
+                        Err(err) => return Poll::Ready(Err(io::Error::other(err))),
+                    }
+                }
+                FileState::Ready(cursor) => {
+                    let pos = cursor.position() as usize;
+                    let rest = &cursor.get_ref()[pos..];
+                    let len = rest.len().min(buf.remaining());
+                    buf.put_slice(&rest[..len]);
+                    cursor.set_position((pos + len) as u64);
+                    return Poll::Ready(Ok(()));
+                }
+            }
+        }
+    }
+}
+
+#[test]
+fn test_async_archive() {
+    use crate::native::testing::TempDir;
+    use crate::native::{ArchiveBuilder, FileOptions};
+    use tokio::io::AsyncReadExt;
+
+    let script = b"function main takes nothing returns nothing\nendfunction\n".repeat(200);
+    let mut builder = ArchiveBuilder::new();
+    builder.add("war3map.j", script.clone(), FileOptions::default());
+    let dir = TempDir::new("stormlib-async");
+    let path = dir.join("map.w3x");
+    builder.write(&path).unwrap();
+
+    let runtime = tokio::runtime::Builder::new_current_thread()
+        .build()
+        .unwrap();
+    runtime.block_on(async {
+        let archive = AsyncArchive::open(&path, OpenArchiveFlags::MPQ_OPEN_NO_FLAG)
+            .await
+            .unwrap();
+        assert!(archive.has_file("war3map.j").await.unwrap());
+        assert_eq!(archive.read_file("war3map.j").await.unwrap(), script);
+
+        let mut file = archive.open_file("war3map.j").await.unwrap();
+        assert_eq!(file.size(), script.len() as u64);
+        let mut content = Vec::new();
+        file.read_to_end(&mut content).await.unwrap();
+        assert_eq!(content, script);
+
+        assert!(archive.open_file("missing.txt").await.is_err());
+        let names: Vec<String> = archive
+            .list()
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|e| e.name)
+            .collect();
+        assert!(names.contains(&"war3map.j".to_string()));
+    });
+}
@@ -1,27 +1,46 @@
 use bitflags::bitflags;
-use stormlib_sys;
+
+#[cfg(not(feature = "pure-rust"))]
+use stormlib_sys as sys;
+#[cfg(feature = "pure-rust")]
+use crate::native::sys;
 
 bitflags! {
   pub struct OpenArchiveFlags: u32 {
-    const STREAM_PROVIDER_PARTIAL   = stormlib_sys::STREAM_PROVIDER_PARTIAL;
-    const STREAM_PROVIDER_MPQE      = stormlib_sys::STREAM_PROVIDER_MPQE;
-    const STREAM_PROVIDER_BLOCK4    = stormlib_sys::STREAM_PROVIDER_BLOCK4;
-    const STREAM_PROVIDER_MASK      = stormlib_sys::STREAM_PROVIDER_MASK;
+    const STREAM_PROVIDER_PARTIAL   = sys::STREAM_PROVIDER_PARTIAL;
+    const STREAM_PROVIDER_MPQE      = sys::STREAM_PROVIDER_MPQE;
+    const STREAM_PROVIDER_BLOCK4    = sys::STREAM_PROVIDER_BLOCK4;
+    const STREAM_PROVIDER_MASK      = sys::STREAM_PROVIDER_MASK;
 
-    const BASE_PROVIDER_FILE        = stormlib_sys::BASE_PROVIDER_FILE;
-    const BASE_PROVIDER_MAP         = stormlib_sys::BASE_PROVIDER_MAP ;
-    const BASE_PROVIDER_HTTP        = stormlib_sys::BASE_PROVIDER_HTTP;
-    const BASE_PROVIDER_MASK        = stormlib_sys::BASE_PROVIDER_MASK;
+    const BASE_PROVIDER_FILE        = sys::BASE_PROVIDER_FILE;
+    const BASE_PROVIDER_MAP         = sys::BASE_PROVIDER_MAP ;
+    const BASE_PROVIDER_HTTP        = sys::BASE_PROVIDER_HTTP;
+    const BASE_PROVIDER_MASK        = sys::BASE_PROVIDER_MASK;
 
-    const STREAM_FLAG_READ_ONLY     = stormlib_sys::STREAM_FLAG_READ_ONLY;
-    const STREAM_FLAG_WRITE_SHARE   = stormlib_sys::STREAM_FLAG_WRITE_SHARE;
+    const STREAM_FLAG_READ_ONLY     = sys::STREAM_FLAG_READ_ONLY;
+    const STREAM_FLAG_WRITE_SHARE   = sys::STREAM_FLAG_WRITE_SHARE;
 
     const MPQ_OPEN_NO_FLAG          = 0;
-    const MPQ_OPEN_NO_LISTFILE      = stormlib_sys::MPQ_OPEN_NO_LISTFILE;
-    const MPQ_OPEN_NO_ATTRIBUTES    = stormlib_sys::MPQ_OPEN_NO_ATTRIBUTES;
-    const MPQ_OPEN_NO_HEADER_SEARCH = stormlib_sys::MPQ_OPEN_NO_HEADER_SEARCH;
-    const MPQ_OPEN_FORCE_MPQ_V1     = stormlib_sys::MPQ_OPEN_FORCE_MPQ_V1;
-    const MPQ_OPEN_CHECK_SECTOR_CRC = stormlib_sys::MPQ_OPEN_CHECK_SECTOR_CRC;
-    const MPQ_OPEN_READ_ONLY        = stormlib_sys::MPQ_OPEN_READ_ONLY;
+    const MPQ_OPEN_NO_LISTFILE      = sys::MPQ_OPEN_NO_LISTFILE;
+    const MPQ_OPEN_NO_ATTRIBUTES    = sys::MPQ_OPEN_NO_ATTRIBUTES;
+    const MPQ_OPEN_NO_HEADER_SEARCH = sys::MPQ_OPEN_NO_HEADER_SEARCH;
+    const MPQ_OPEN_FORCE_MPQ_V1     = sys::MPQ_OPEN_FORCE_MPQ_V1;
+    const MPQ_OPEN_CHECK_SECTOR_CRC = sys::MPQ_OPEN_CHECK_SECTOR_CRC;
+    const MPQ_OPEN_READ_ONLY        = sys::MPQ_OPEN_READ_ONLY;
+  }
+}
+
+bitflags! {
+  pub struct FileFlags: u32 {
+    const IMPLODE       = sys::MPQ_FILE_IMPLODE;
+    const COMPRESS      = sys::MPQ_FILE_COMPRESS;
+    const ENCRYPTED     = sys::MPQ_FILE_ENCRYPTED;
+    const FIX_KEY       = sys::MPQ_FILE_FIX_KEY;
+    const PATCH_FILE    = sys::MPQ_FILE_PATCH_FILE;
+    const SINGLE_UNIT   = sys::MPQ_FILE_SINGLE_UNIT;
+    const DELETE_MARKER = sys::MPQ_FILE_DELETE_MARKER;
+    const SECTOR_CRC    = sys::MPQ_FILE_SECTOR_CRC;
+    const SIGNATURE     = sys::MPQ_FILE_SIGNATURE;
+    const EXISTS        = sys::MPQ_FILE_EXISTS;
   }
 }
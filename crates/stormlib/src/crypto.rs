@@ -0,0 +1,469 @@
+//! MPQ hashing and encryption, implemented without StormLib
+//!
+//! Useful for computing hash table entries offline, e.g. to test listfile
+//! candidates against a dumped hash table without opening the archive.
+
+use crate::FileFlags;
+
+use std::convert::TryInto;
+
+/// Hash table entry that was never used
+pub const HASH_ENTRY_FREE: u32 = 0xFFFF_FFFF;
+
+/// Hash table entry whose file has been deleted
+pub const HASH_ENTRY_DELETED: u32 = 0xFFFF_FFFE;
+
+/// Key of the hash table, `hash_string("(hash table)", HashType::FileKey)`
+pub const HASH_TABLE_KEY: u32 = 0xC3AF_3770;
+
+/// Key of the block table, `hash_string("(block table)", HashType::FileKey)`
+pub const BLOCK_TABLE_KEY: u32 = 0xEC83_B3A3;
+
+/// Selects which part of the crypt table `hash_string` uses
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HashType {
+    /// Index of the name in the hash table
+    TableOffset = 0x000,
+    /// First name check stored in the hash table entry
+    NameA       = 0x100,
+    /// Second name check stored in the hash table entry
+    NameB       = 0x200,
+    /// Encryption key of a file
+    FileKey     = 0x300,
+}
+
+static CRYPT_TABLE: [u32; 0x500] = build_crypt_table();
+
+const fn build_crypt_table() -> [u32; 0x500] {
+    let mut table = [0u32; 0x500];
+    let mut seed: u32 = 0x0010_0001;
+    let mut index1 = 0;
+    while index1 < 0x100 {
+        let mut index2 = index1;
+        let mut i = 0;
+        while i < 5 {
+            seed = (seed * 125 + 3) % 0x2A_AAAB;
+            let temp1 = (seed & 0xFFFF) << 0x10;
+            seed = (seed * 125 + 3) % 0x2A_AAAB;
+            let temp2 = seed & 0xFFFF;
+            table[index2] = temp1 | temp2;
+            index2 += 0x100;
+            i += 1;
+        }
+        index1 += 1;
+    }
+    table
+}
+
+/// Normalizes a name character the way Blizzard does: upper case, `/` as `\`
+fn normalize(ch: u8) -> u8 {
+    match ch {
+        b'/' => b'\\',
+        _ => ch.to_ascii_uppercase(),
+    }
+}
+
+/// Hashes a file name, case-insensitively and treating `/` as `\`
+pub fn hash_string(name: &str, hash_type: HashType) -> u32 {
+    let mut seed1: u32 = 0x7FED_7FED;
+    let mut seed2: u32 = 0xEEEE_EEEE;
+    for ch in name.bytes().map(normalize) {
+        seed1 = CRYPT_TABLE[hash_type as usize + ch as usize] ^ seed1.wrapping_add(seed2);
+        seed2 = (ch as u32)
+            .wrapping_add(seed1)
+            .wrapping_add(seed2)
+            .wrapping_add(seed2 << 5)
+            .wrapping_add(3);
+    }
+    seed1
+}
+
+/// Part of the name after the last path separator
+pub fn plain_name(name: &str) -> &str {
+    name.rsplit(['\\', '/']).next().unwrap_or(name)
+}
+
+/// Derives the encryption key of a file
+///
+/// `block_offset` is the position of the file data relative to the MPQ
+/// header; it only matters for files flagged with `FileFlags::FIX_KEY`.
+pub fn file_key(name: &str, block_offset: u64, file_size: u32, flags: FileFlags) -> u32 {
+    let key = hash_string(plain_name(name), HashType::FileKey);
+    if flags.contains(FileFlags::FIX_KEY) {
+        key.wrapping_add(block_offset as u32) ^ file_size
+    } else {
+        key
+    }
+}
+
+/// Encrypts a block of dwords in place
+pub fn encrypt_block(data: &mut [u32], mut key: u32) {
+    let mut seed: u32 = 0xEEEE_EEEE;
+    for value in data.iter_mut() {
+        seed = seed.wrapping_add(CRYPT_TABLE[0x400 + (key & 0xFF) as usize]);
+        let plain = *value;
+        *value = plain ^ key.wrapping_add(seed);
+        key = ((!key << 0x15).wrapping_add(0x1111_1111)) | (key >> 0x0B);
+        seed = plain
+            .wrapping_add(seed)
+            .wrapping_add(seed << 5)
+            .wrapping_add(3);
+    }
+}
+
+/// Decrypts a block of dwords in place
+pub fn decrypt_block(data: &mut [u32], mut key: u32) {
+    let mut seed: u32 = 0xEEEE_EEEE;
+    for value in data.iter_mut() {
+        seed = seed.wrapping_add(CRYPT_TABLE[0x400 + (key & 0xFF) as usize]);
+        let plain = *value ^ key.wrapping_add(seed);
+        *value = plain;
+        key = ((!key << 0x15).wrapping_add(0x1111_1111)) | (key >> 0x0B);
+        seed = plain
+            .wrapping_add(seed)
+            .wrapping_add(seed << 5)
+            .wrapping_add(3);
+    }
+}
+
+fn with_dwords(data: &mut [u8], f: impl FnOnce(&mut [u32])) {
+    let len = data.len() / 4 * 4;
+    let mut dwords: Vec<u32> = data[..len]
+        .chunks_exact(4)
+        .map(|b| u32::from_le_bytes(b.try_into().unwrap()))
+        .collect();
+    f(&mut dwords);
+    for (chunk, value) in data[..len].chunks_exact_mut(4).zip(dwords) {
+        chunk.copy_from_slice(&value.to_le_bytes());
+    }
+}
+
+/// Encrypts little-endian data in place, trailing bytes past the last whole dword are left as is
+pub fn encrypt_bytes(data: &mut [u8], key: u32) {
+    with_dwords(data, |dwords| encrypt_block(dwords, key));
+}
+
+/// Decrypts little-endian data in place, trailing bytes past the last whole dword are left as is
+pub fn decrypt_bytes(data: &mut [u8], key: u32) {
+    with_dwords(data, |dwords| decrypt_block(dwords, key));
+}
+
+/// Recovers the key of an encrypted block from the known value of its first
+/// dword, the second dword must decrypt to at most `max_second`
+///
+/// This is how StormLib finds the key of files whose name is unknown, using
+/// the size of their sector offset table as the known plain text.
+pub fn detect_key(encrypted: [u32; 2], plain_first: u32, max_second: u32) -> Option<u32> {
+    let key_plus_seed = (encrypted[0] ^ plain_first).wrapping_sub(0xEEEE_EEEE);
+    (0..0x100).find_map(|i| {
+        let key = key_plus_seed.wrapping_sub(CRYPT_TABLE[0x400 + i]);
+        if key & 0xFF != i as u32 {
+            return None;
+        }
+        let mut data = encrypted;
+        decrypt_block(&mut data, key);
+        if data[0] == plain_first && data[1] <= max_second {
+            Some(key)
+        } else {
+            None
+        }
+    })
+}
+
+/// Bob Jenkins' `hashlittle2` from lookup3.c, returning the primary and secondary hash
+pub fn hashlittle2(key: &[u8], primary: u32, secondary: u32) -> (u32, u32) {
+    fn mix(a: &mut u32, b: &mut u32, c: &mut u32) {
+        *a = a.wrapping_sub(*c) ^ c.rotate_left(4);
+        *c = c.wrapping_add(*b);
+        *b = b.wrapping_sub(*a) ^ a.rotate_left(6);
+        *a = a.wrapping_add(*c);
+        *c = c.wrapping_sub(*b) ^ b.rotate_left(8);
+        *b = b.wrapping_add(*a);
+        *a = a.wrapping_sub(*c) ^ c.rotate_left(16);
+        *c = c.wrapping_add(*b);
+        *b = b.wrapping_sub(*a) ^ a.rotate_left(19);
+        *a = a.wrapping_add(*c);
+        *c = c.wrapping_sub(*b) ^ b.rotate_left(4);
+        *b = b.wrapping_add(*a);
+    }
+
+    let dword = |bytes: &[u8]| {
+        let mut buf = [0u8; 4];
+        buf[..bytes.len()].copy_from_slice(bytes);
+        u32::from_le_bytes(buf)
+    };
+
+    let init = 0xDEAD_BEEFu32
+        .wrapping_add(key.len() as u32)
+        .wrapping_add(primary);
+    let (mut a, mut b, mut c) = (init, init, init.wrapping_add(secondary));
+    let mut rest = key;
+    while rest.len() > 12 {
+        a = a.wrapping_add(dword(&rest[0..4]));
+        b = b.wrapping_add(dword(&rest[4..8]));
+        c = c.wrapping_add(dword(&rest[8..12]));
+        mix(&mut a, &mut b, &mut c);
+        rest = &rest[12..];
+    }
+    if rest.is_empty() {
+        return (c, b);
+    }
+    a = a.wrapping_add(dword(&rest[..rest.len().min(4)]));
+    if rest.len() > 4 {
+        b = b.wrapping_add(dword(&rest[4..rest.len().min(8)]));
+    }
+    if rest.len() > 8 {
+        c = c.wrapping_add(dword(&rest[8..]));
+    }
+
+    c = (c ^ b).wrapping_sub(b.rotate_left(14));
+    a = (a ^ c).wrapping_sub(c.rotate_left(11));
+    b = (b ^ a).wrapping_sub(a.rotate_left(25));
+    c = (c ^ b).wrapping_sub(b.rotate_left(16));
+    a = (a ^ c).wrapping_sub(c.rotate_left(4));
+    b = (b ^ a).wrapping_sub(a.rotate_left(14));
+    c = (c ^ b).wrapping_sub(b.rotate_left(24));
+    (c, b)
+}
+
+/// 64-bit name hash used by the HET table of MPQ v3 and later archives,
+/// lower case and treating `/` as `\`
+pub fn hash_string_jenkins(name: &str) -> u64 {
+    let normalized: Vec<u8> = name
+        .bytes()
+        .map(|ch| match ch {
+            b'/' => b'\\',
+            _ => ch.to_ascii_lowercase(),
+        })
+        .collect();
+    let (primary, secondary) = hashlittle2(&normalized, 0, 0);
+    (primary as u64) << 32 | secondary as u64
+}
+
+/// Entry of the classic MPQ hash table
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct HashEntry {
+    pub name_a:      u32,
+    pub name_b:      u32,
+    pub locale:      u16,
+    pub platform:    u16,
+    pub block_index: u32,
+}
+
+impl HashEntry {
+    /// Size of an entry on disk
+    pub const SIZE: usize = 16;
+
+    /// Parses a hash table as stored in the archive, decrypting it first
+    pub fn read_table(raw: &[u8]) -> Vec<HashEntry> {
+        let mut data = raw[..raw.len() / Self::SIZE * Self::SIZE].to_vec();
+        decrypt_bytes(&mut data, HASH_TABLE_KEY);
+        Self::parse_table(&data)
+    }
+
+    /// Parses an already decrypted hash table
+    pub fn parse_table(data: &[u8]) -> Vec<HashEntry> {
+        data.chunks_exact(Self::SIZE)
+            .map(|b| {
+                let dword = |i: usize| u32::from_le_bytes(b[i..i + 4].try_into().unwrap());
+                HashEntry {
+                    name_a:      dword(0),
+                    name_b:      dword(4),
+                    locale:      u16::from_le_bytes([b[8], b[9]]),
+                    platform:    u16::from_le_bytes([b[10], b[11]]),
+                    block_index: dword(12),
+                }
+            })
+            .collect()
+    }
+
+    /// Serializes and encrypts a hash table
+    pub fn write_table(entries: &[HashEntry]) -> Vec<u8> {
+        let mut data = Vec::with_capacity(entries.len() * Self::SIZE);
+        for entry in entries {
+            data.extend_from_slice(&entry.name_a.to_le_bytes());
+            data.extend_from_slice(&entry.name_b.to_le_bytes());
+            data.extend_from_slice(&entry.locale.to_le_bytes());
+            data.extend_from_slice(&entry.platform.to_le_bytes());
+            data.extend_from_slice(&entry.block_index.to_le_bytes());
+        }
+        encrypt_bytes(&mut data, HASH_TABLE_KEY);
+        data
+    }
+
+    /// An unused entry
+    pub fn free() -> Self {
+        HashEntry {
+            name_a:      HASH_ENTRY_FREE,
+            name_b:      HASH_ENTRY_FREE,
+            locale:      0xFFFF,
+            platform:    0xFFFF,
+            block_index: HASH_ENTRY_FREE,
+        }
+    }
+
+    pub fn is_free(&self) -> bool {
+        self.block_index == HASH_ENTRY_FREE
+    }
+
+    pub fn is_deleted(&self) -> bool {
+        self.block_index == HASH_ENTRY_DELETED
+    }
+}
+
+/// Hashes of a file name, as used to place it in the hash table
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct NameHash {
+    pub table_offset: u32,
+    pub name_a:       u32,
+    pub name_b:       u32,
+}
+
+impl NameHash {
+    pub fn new(name: &str) -> Self {
+        NameHash {
+            table_offset: hash_string(name, HashType::TableOffset),
+            name_a:       hash_string(name, HashType::NameA),
+            name_b:       hash_string(name, HashType::NameB),
+        }
+    }
+
+    /// Finds all entries of the name (one per locale) in a decrypted hash table
+    /// whose size is a power of two
+    pub fn lookup<'a>(&self, table: &'a [HashEntry]) -> Vec<&'a HashEntry> {
+        let mut found = Vec::new();
+        if table.is_empty() {
+            return found;
+        }
+        let mask = table.len() - 1;
+        let start = self.table_offset as usize & mask;
+        for i in 0..table.len() {
+            let entry = &table[(start + i) & mask];
+            if entry.is_free() {
+                break;
+            }
+            if !entry.is_deleted() && entry.name_a == self.name_a && entry.name_b == self.name_b {
+                found.push(entry);
+            }
+        }
+        found
+    }
+}
+
+#[cfg(not(feature = "pure-rust"))]
+#[test]
+fn test_table_keys_match_stormlib() {
+    assert_eq!(
+        hash_string("(hash table)", HashType::FileKey),
+        stormlib_sys::MPQ_KEY_HASH_TABLE
+    );
+    assert_eq!(
+        hash_string("(block table)", HashType::FileKey),
+        stormlib_sys::MPQ_KEY_BLOCK_TABLE
+    );
+    assert_eq!(HASH_TABLE_KEY, stormlib_sys::MPQ_KEY_HASH_TABLE);
+    assert_eq!(BLOCK_TABLE_KEY, stormlib_sys::MPQ_KEY_BLOCK_TABLE);
+    assert_eq!(HASH_ENTRY_FREE, stormlib_sys::HASH_ENTRY_FREE);
+    assert_eq!(HASH_ENTRY_DELETED, stormlib_sys::HASH_ENTRY_DELETED);
+}
+
+#[test]
+fn test_hash_normalizes_names() {
+    let a = hash_string("war3mapImported\\fx.mdx", HashType::NameA);
+    assert_eq!(a, hash_string("WAR3MAPIMPORTED/FX.MDX", HashType::NameA));
+    assert_ne!(a, hash_string("war3mapImported\\fx.mdx", HashType::NameB));
+    assert_eq!(plain_name("war3mapImported\\fx.mdx"), "fx.mdx");
+}
+
+#[test]
+fn test_encrypt_roundtrip() {
+    let plain: Vec<u8> = (0..=38).collect();
+    let mut data = plain.clone();
+    let key = file_key("war3map.j", 0x1000, 39, FileFlags::FIX_KEY);
+    assert_ne!(key, file_key("war3map.j", 0x1000, 39, FileFlags::empty()));
+
+    encrypt_bytes(&mut data, key);
+    assert_ne!(data[..36], plain[..36]);
+    assert_eq!(data[36..], plain[36..]);
+    decrypt_bytes(&mut data, key);
+    assert_eq!(data, plain);
+}
+
+#[test]
+fn test_hash_table_lookup() {
+    let mut table = vec![HashEntry::free(); 16];
+    let hash = NameHash::new("war3map.j");
+    // force a collision so the lookup has to probe past an occupied slot
+    let start = hash.table_offset as usize & 15;
+    table[start] = HashEntry {
+        name_a:      1,
+        name_b:      2,
+        locale:      0,
+        platform:    0,
+        block_index: 0,
+    };
+    table[(start + 1) & 15] = HashEntry {
+        name_a:      hash.name_a,
+        name_b:      hash.name_b,
+        locale:      0,
+        platform:    0,
+        block_index: 7,
+    };
+    let table = HashEntry::read_table(&HashEntry::write_table(&table));
+    assert_eq!(hash.lookup(&table)[0].block_index, 7);
+    assert!(NameHash::new("war3map.lua").lookup(&table).is_empty());
+}
+
+#[test]
+fn test_detect_key() {
+    let key = file_key("war3map.j", 0, 0, FileFlags::empty()).wrapping_sub(1);
+    let mut table = [0x14, 0x200];
+    encrypt_block(&mut table, key);
+    assert_eq!(detect_key(table, 0x14, 0x14 + 0x1000), Some(key));
+}
+
+#[test]
+fn test_hashlittle2() {
+    // test vectors from lookup3.c
+    assert_eq!(hashlittle2(b"", 0, 0), (0xDEAD_BEEF, 0xDEAD_BEEF));
+    assert_eq!(hashlittle2(b"", 0, 0xDEAD_BEEF), (0xBD5B_7DDE, 0xDEAD_BEEF));
+    assert_eq!(
+        hashlittle2(b"Four score and seven years ago", 0, 0),
+        (0x1777_0551, 0xCE72_26E6)
+    );
+    assert_eq!(
+        hash_string_jenkins("Units/Footman.MDX"),
+        hash_string_jenkins("units\\footman.mdx")
+    );
+}
+
+#[cfg(not(feature = "pure-rust"))]
+#[test]
+fn test_hashes_match_stormlib() {
+    use crate::ffi::query_info;
+    use crate::{Archive, OpenArchiveFlags};
+    use stormlib_sys::*;
+
+    let mut archive = Archive::open(
+        "../../samples/test_tft.w3x",
+        OpenArchiveFlags::MPQ_OPEN_NO_LISTFILE | OpenArchiveFlags::MPQ_OPEN_NO_ATTRIBUTES,
+    )
+    .unwrap();
+    let file = archive.open_file("war3map.j").unwrap();
+    let info = |class| {
+        let mut bytes = query_info(file.file_handle, class).unwrap().unwrap();
+        bytes.resize(8, 0);
+        u64::from_le_bytes(bytes[..].try_into().unwrap())
+    };
+
+    let hash = NameHash::new("war3map.j");
+    assert_eq!(info(_SFileInfoClass_SFileInfoNameHash1) as u32, hash.name_a);
+    assert_eq!(info(_SFileInfoClass_SFileInfoNameHash2) as u32, hash.name_b);
+    let flags = FileFlags::from_bits_truncate(info(_SFileInfoClass_SFileInfoFlags) as u32);
+    let offset = info(_SFileInfoClass_SFileInfoByteOffset);
+    let size = info(_SFileInfoClass_SFileInfoFileSize) as u32;
+    assert_eq!(
+        info(_SFileInfoClass_SFileInfoEncryptionKey) as u32,
+        file_key("war3map.j", offset, size, flags)
+    );
+}
@@ -37,6 +37,10 @@ pub enum StormError {
   CanNotComplete,
   #[error("FileCorrupt")]
   FileCorrupt,
+  #[error("UnknownFileKey")]
+  UnknownFileKey,
+  #[error("ChecksumError")]
+  ChecksumError,
   #[error("UnknownCode({0:?})")]
   UnknownCode(ErrorCode),
   #[cfg(not(target_os = "windows"))]
@@ -44,10 +48,16 @@ pub enum StormError {
   NonUtf8,
   #[error("an interior nul byte was found")]
   InteriorNul,
+  #[cfg(feature = "tokio")]
+  #[error("the archive worker thread has stopped")]
+  WorkerStopped,
+  #[error("IoError({0})")]
+  Io(#[from] std::io::Error),
 }
 
 pub type Result<T, E = StormError> = std::result::Result<T, E>;
 
+#[cfg(not(feature = "pure-rust"))]
 impl From<ErrorCode> for StormError {
   fn from(ErrorCode(code): ErrorCode) -> Self {
     use StormError::*;
@@ -68,6 +78,8 @@ impl From<ErrorCode> for StormError {
       stormlib_sys::ERROR_HANDLE_EOF => HandleEof,
       stormlib_sys::ERROR_CAN_NOT_COMPLETE => CanNotComplete,
       stormlib_sys::ERROR_FILE_CORRUPT => FileCorrupt,
+      stormlib_sys::ERROR_UNKNOWN_FILE_KEY => UnknownFileKey,
+      stormlib_sys::ERROR_CHECKSUM_ERROR => ChecksumError,
       other => UnknownCode(ErrorCode(other)),
     }
   }
@@ -0,0 +1,787 @@
+//! StormLib backed implementation of `Archive` and `File`
+
+use std::ffi::*;
+use std::fs;
+use std::io::{self, Read, Write};
+use std::marker::PhantomData;
+use std::path::{Path, PathBuf};
+use std::ptr;
+use stormlib_sys::*;
+
+use crate::error::*;
+use crate::{
+    Compression, ConflictPolicy, FileEntry, FileFlags, FileOptions, Import, OpenArchiveFlags,
+    UserData,
+};
+
+/// Converts a local path to the `TCHAR` string expected by StormLib
+#[cfg(not(target_os = "windows"))]
+fn to_native_path<P: AsRef<Path>>(path: P) -> Result<CString> {
+    let pathstr = path.as_ref().to_str().ok_or(StormError::NonUtf8)?;
+    Ok(CString::new(pathstr)?)
+}
+
+#[cfg(target_os = "windows")]
+fn to_native_path<P: AsRef<Path>>(path: P) -> Result<Vec<u16>> {
+    use widestring::U16CString;
+    Ok(U16CString::from_os_str(path.as_ref())
+        .map_err(|_| StormError::InteriorNul)?
+        .into_vec())
+}
+
+/// Absolute path of a file that may not exist yet, to compare it with others
+fn absolute_target(path: &Path) -> Result<PathBuf> {
+    if let Ok(path) = path.canonicalize() {
+        return Ok(path);
+    }
+    let name = path.file_name().ok_or(StormError::InvalidParameter)?;
+    let parent = match path.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => parent,
+        _ => Path::new("."),
+    };
+    Ok(parent.canonicalize()?.join(name))
+}
+
+/// Creates an empty file next to `path`, named after it and this process so
+/// it never clobbers an existing one
+fn create_temp_next_to(path: &Path) -> Result<PathBuf> {
+    let name = path.file_name().ok_or(StormError::InvalidParameter)?;
+    let mut temp_name = OsString::from(".");
+    temp_name.push(name);
+    temp_name.push(format!(".{}.tmp", std::process::id()));
+    let temp = path.with_file_name(temp_name);
+    fs::OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .open(&temp)?;
+    Ok(temp)
+}
+
+/// `SFileCreateFile`/`SFileAddFileEx` flags storing a file as described by `options`
+fn file_flags(options: &FileOptions) -> u32 {
+    let mut flags = MPQ_FILE_REPLACEEXISTING;
+    if options.compression != Compression::None {
+        flags |= MPQ_FILE_COMPRESS;
+    }
+    if options.encrypt {
+        flags |= MPQ_FILE_ENCRYPTED;
+        if options.fix_key {
+            flags |= MPQ_FILE_FIX_KEY;
+        }
+    }
+    if options.single_unit {
+        flags |= MPQ_FILE_SINGLE_UNIT;
+    }
+    flags
+}
+
+/// Compression of the first sector and of the rest of a file
+///
+/// The first sector of a WAVE file holds its header, which must not go
+/// through the lossy ADPCM compression.
+fn compression_masks(compression: Compression) -> (u32, u32) {
+    let mask = compression.mask() as u32;
+    match compression {
+        Compression::AdpcmMono | Compression::AdpcmStereo => (MPQ_COMPRESSION_ZLIB, mask),
+        _ => (mask, mask),
+    }
+}
+
+/// Queries raw information about an archive or file handle, `None` if StormLib has none
+pub(crate) fn query_info(handle: HANDLE, class: SFileInfoClass) -> Result<Option<Vec<u8>>> {
+    let mut needed: DWORD = 0;
+    unsafe {
+        if !SFileGetFileInfo(handle, class, ptr::null_mut(), 0, &mut needed) {
+            match GetLastError() {
+                ERROR_INSUFFICIENT_BUFFER => {}
+                ERROR_FILE_NOT_FOUND => return Ok(None),
+                err => return Err(From::from(ErrorCode(err))),
+            }
+        }
+    }
+    let mut buf = vec![0u8; needed as usize];
+    unsafe_try_call!(SFileGetFileInfo(
+        handle,
+        class,
+        buf.as_mut_ptr() as *mut c_void,
+        needed,
+        &mut needed
+    ));
+    buf.truncate(needed as usize);
+    Ok(Some(buf))
+}
+
+/// MPQ archive
+#[derive(Debug)]
+pub struct Archive {
+    handle: HANDLE,
+}
+
+impl Archive {
+    /// Opens a MPQ archive
+    pub fn open<P: AsRef<Path>>(path: P, flags: OpenArchiveFlags) -> Result<Self> {
+        let cpath = to_native_path(path)?;
+        let mut handle: HANDLE = ptr::null_mut();
+        unsafe_try_call!(SFileOpenArchive(
+            cpath.as_ptr(),
+            0,
+            flags.bits(),
+            &mut handle as *mut HANDLE,
+        ));
+        Ok(Archive { handle })
+    }
+
+    pub fn create<P: AsRef<Path>>(path: P, filecount: usize, use_filelist: bool) -> Result<Self> {
+        let cpath = to_native_path(path)?;
+        let mut handle: HANDLE = ptr::null_mut();
+        let flags = 0;
+        let dwMpqVersion = (flags & MPQ_CREATE_ARCHIVE_VMASK) >> 24;
+        let dwStreamFlags = 0;
+        let mut dwFileFlags1 = if flags & MPQ_CREATE_LISTFILE != 0 {
+            MPQ_FILE_DEFAULT_INTERNAL
+        } else {
+            0
+        };
+        let dwFileFlags2 = if flags & MPQ_CREATE_ATTRIBUTES != 0 {
+            MPQ_FILE_DEFAULT_INTERNAL
+        } else {
+            0
+        };
+        let dwFileFlags3 = if flags & MPQ_CREATE_SIGNATURE != 0 {
+            MPQ_FILE_DEFAULT_INTERNAL
+        } else {
+            0
+        };
+        let mut dwAttrFlags = if flags & MPQ_CREATE_ATTRIBUTES != 0 {
+            MPQ_ATTRIBUTE_CRC32 | MPQ_ATTRIBUTE_FILETIME | MPQ_ATTRIBUTE_MD5
+        } else {
+            0
+        };
+        let dwSectorSize: u32 = if dwMpqVersion >= MPQ_FORMAT_VERSION_3 {
+            0x4000
+        } else {
+            0x1000
+        };
+        let dwRawChunkSize = if dwMpqVersion >= MPQ_FORMAT_VERSION_4 {
+            0x4000
+        } else {
+            0
+        };
+        let dwMaxFileCount = filecount;
+
+        if dwMpqVersion >= MPQ_FORMAT_VERSION_3 && flags & MPQ_CREATE_ATTRIBUTES != 0 {
+            dwAttrFlags |= MPQ_ATTRIBUTE_PATCH_BIT;
+        }
+
+        if use_filelist {
+            dwFileFlags1 = MPQ_FILE_DEFAULT_INTERNAL;
+        }
+
+        let cbSize = ::std::mem::size_of::<_SFILE_CREATE_MPQ>() as u32;
+        let mut ci = Box::new(_SFILE_CREATE_MPQ {
+            cbSize,
+            dwMpqVersion,
+            pvUserData: ptr::null_mut(),
+            cbUserData: 0,
+            dwStreamFlags,
+            dwFileFlags1,
+            dwFileFlags2,
+            dwFileFlags3,
+            dwAttrFlags,
+            dwSectorSize,
+            dwRawChunkSize,
+            dwMaxFileCount: dwMaxFileCount as u32,
+        });
+
+        unsafe_try_call!(SFileCreateArchive2(
+            cpath.as_ptr(),
+            &mut *ci,
+            &mut handle as *mut HANDLE
+        ));
+
+        Ok(Archive { handle })
+    }
+
+    /// Creates a MPQ archive preceded by a user data block holding `content`
+    ///
+    /// StormLib reserves the user data fields of `SFILE_CREATE_MPQ`, so the
+    /// block is written first and the archive is appended after it.
+    pub fn create_with_user_data<P: AsRef<Path>>(
+        path: P,
+        filecount: usize,
+        use_filelist: bool,
+        content: &[u8],
+    ) -> Result<Self> {
+        fs::write(path.as_ref(), UserData::new(content).to_bytes())?;
+        Archive::create(path, filecount, use_filelist)
+    }
+
+    /// Reads the user data block preceding the MPQ header, if any
+    pub fn user_data(&self) -> Result<Option<UserData>> {
+        let header = match query_info(self.handle, _SFileInfoClass_SFileMpqUserDataHeader)? {
+            Some(header) => header,
+            None => return Ok(None),
+        };
+        let content =
+            query_info(self.handle, _SFileInfoClass_SFileMpqUserData)?.unwrap_or_default();
+        Ok(UserData::parse(&[header, content].concat()))
+    }
+
+    /// Quick check if the file exists within MPQ archive, without opening it
+    pub fn has_file(&mut self, path: &str) -> Result<bool> {
+        let cpath = CString::new(path)?;
+        unsafe {
+            let r = SFileHasFile(self.handle, cpath.as_ptr());
+            let err = GetLastError();
+            if !r && err != ERROR_FILE_NOT_FOUND {
+                return Err(From::from(ErrorCode(err)));
+            }
+            Ok(r)
+        }
+    }
+
+    /// Opens a file from MPQ archive
+    pub fn open_file<'a>(&'a mut self, path: &str) -> Result<File<'a>> {
+        let mut file_handle: HANDLE = ptr::null_mut();
+        let cpath = CString::new(path)?;
+        unsafe_try_call!(SFileOpenFileEx(
+            self.handle,
+            cpath.as_ptr(),
+            0,
+            &mut file_handle as *mut HANDLE
+        ));
+        Ok(File {
+            archive: self,
+            file_handle,
+            size: None,
+            need_reset: false,
+        })
+    }
+
+    pub fn write_file(&self, file_name: &str, data: &[u8]) -> Result<bool> {
+        let cpath = CString::new(file_name)?;
+        let mut handle = ptr::null_mut();
+        unsafe_try_call!(SFileCreateFile(
+            self.handle,
+            cpath.as_ptr(),
+            0,
+            data.len() as u32,
+            0,
+            MPQ_FILE_REPLACEEXISTING,
+            &mut handle,
+        ));
+        unsafe_try_call!(SFileWriteFile(
+            handle,
+            data.as_ptr() as *const c_void,
+            data.len() as u32,
+            0
+        ));
+        unsafe_try_call!(SFileFinishFile(handle));
+        Ok(true)
+    }
+
+    /// Writes a file stored as described by `options`, replacing an existing one
+    pub fn write_file_with(
+        &self,
+        file_name: &str,
+        data: &[u8],
+        options: FileOptions,
+    ) -> Result<bool> {
+        let mut file = self.create_file(file_name, data.len() as u64, options)?;
+        file.write_data(data)?;
+        file.finish()?;
+        Ok(true)
+    }
+
+    /// Creates a file of `size` bytes to be written piece by piece, replacing
+    /// an existing one once finished
+    pub fn create_file(
+        &self,
+        file_name: &str,
+        size: u64,
+        options: FileOptions,
+    ) -> Result<FileWriter<'_>> {
+        if size > u32::MAX as u64 {
+            return Err(StormError::InvalidParameter);
+        }
+        let sector_size = query_info(self.handle, _SFileInfoClass_SFileMpqSectorSize)?
+            .and_then(|b| {
+                b.get(..4)
+                    .map(|b| u32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+            })
+            .ok_or(StormError::InvalidHandle)?;
+        let cpath = CString::new(file_name)?;
+        let mut handle = ptr::null_mut();
+        unsafe_try_call!(SFileCreateFile(
+            self.handle,
+            cpath.as_ptr(),
+            0,
+            size as u32,
+            options.locale as LCID,
+            file_flags(&options),
+            &mut handle,
+        ));
+        Ok(FileWriter {
+            archive: PhantomData,
+            handle,
+            compression: compression_masks(options.compression),
+            sector_size: sector_size as u64,
+            written: 0,
+            finished: false,
+        })
+    }
+
+    /// Imports the files of `other` accepted by `filter`, in their own locale
+    ///
+    /// StormLib can't write stored sectors, so files are decompressed and
+    /// stored again with the flags they had, see `FileOptions::from_flags`;
+    /// `ArchiveBuilder::import_from` copies them as stored. Internal files
+    /// like `(listfile)` are left out, as are files without a known name.
+    pub fn import_from<F>(
+        &mut self,
+        other: &mut Archive,
+        mut filter: F,
+        policy: ConflictPolicy,
+    ) -> Result<Import>
+    where
+        F: FnMut(&FileEntry) -> bool,
+    {
+        let entries: Vec<FileEntry> = other
+            .list()?
+            .into_iter()
+            .filter(|entry| !entry.is_internal() && filter(entry))
+            .collect();
+        let mut existing = Vec::with_capacity(entries.len());
+        for entry in &entries {
+            let exists = self
+                .find(&entry.name)?
+                .iter()
+                .any(|e| e.locale == entry.locale);
+            existing.push(exists);
+        }
+        if policy == ConflictPolicy::Fail && existing.contains(&true) {
+            return Err(StormError::AlreadyExists);
+        }
+        let needed = self.list()?.len() as u32 + entries.len() as u32;
+        if needed >= self.get_max_files()? {
+            self.set_max_files((needed + 1).next_power_of_two())?;
+        }
+
+        let mut import = Import::default();
+        for (entry, exists) in entries.into_iter().zip(existing) {
+            if entry.is_unnamed() {
+                import.unnamed.push(entry.name);
+                continue;
+            }
+            if policy == ConflictPolicy::Keep && exists {
+                import.kept.push(entry.name);
+                continue;
+            }
+            let data = other
+                .open_file_locale(&entry.name, entry.locale)?
+                .read_all()?;
+            let options = FileOptions::from_flags(entry.flags, entry.locale as u16);
+            self.write_file_with(&entry.name, &data, options)?;
+            import.recompressed.push(entry.name);
+        }
+        Ok(import)
+    }
+
+    /// Opens the file of a given locale, or the neutral one if there is none
+    pub fn open_file_locale(&mut self, path: &str, locale: u32) -> Result<File<'_>> {
+        let mut file_handle: HANDLE = ptr::null_mut();
+        let cpath = CString::new(path)?;
+        let (opened, err) = unsafe {
+            // StormLib takes the locale from a global setting
+            let previous = SFileGetLocale();
+            SFileSetLocale(locale as LCID);
+            let opened = SFileOpenFileEx(self.handle, cpath.as_ptr(), 0, &mut file_handle);
+            let err = GetLastError();
+            SFileSetLocale(previous);
+            (opened, err)
+        };
+        if !opened {
+            return Err(From::from(ErrorCode(err)));
+        }
+        Ok(File {
+            archive: self,
+            file_handle,
+            size: None,
+            need_reset: false,
+        })
+    }
+
+    pub fn add_file(&mut self, path: &str, local_path: &str) -> Result<()> {
+        let clocal_path = to_native_path(local_path)?;
+        let _ = self.remove_file(path);
+        let cpath = CString::new(path)?;
+        unsafe_try_call!(SFileAddFileEx(
+            self.handle,
+            clocal_path.as_ptr(),
+            cpath.as_ptr(),
+            MPQ_FILE_COMPRESS | MPQ_FILE_ENCRYPTED,
+            MPQ_COMPRESSION_ZLIB,
+            MPQ_COMPRESSION_NEXT_SAME,
+        ));
+        Ok(())
+    }
+
+    /// Adds a local file stored as described by `options`, replacing an existing one
+    pub fn add_file_with(
+        &mut self,
+        path: &str,
+        local_path: &str,
+        options: FileOptions,
+    ) -> Result<()> {
+        let clocal_path = to_native_path(local_path)?;
+        let cpath = CString::new(path)?;
+        let (first, next) = compression_masks(options.compression);
+        // StormLib takes the locale of new files from a global setting
+        let (added, err) = unsafe {
+            let locale = SFileGetLocale();
+            SFileSetLocale(options.locale as LCID);
+            let added = SFileAddFileEx(
+                self.handle,
+                clocal_path.as_ptr(),
+                cpath.as_ptr(),
+                file_flags(&options),
+                first,
+                next,
+            );
+            let err = GetLastError();
+            SFileSetLocale(locale);
+            (added, err)
+        };
+        if !added {
+            return Err(From::from(ErrorCode(err)));
+        }
+        Ok(())
+    }
+
+    pub fn remove_file(&mut self, path: &str) -> Result<bool> {
+        let cpath = CString::new(path)?;
+        unsafe {
+            let r = SFileRemoveFile(self.handle, cpath.as_ptr(), 0);
+            let err = GetLastError();
+            if !r && err != ERROR_FILE_NOT_FOUND {
+                return Err(From::from(ErrorCode(err)));
+            }
+            Ok(r)
+        }
+    }
+
+    /// Removes the file of a given locale, `remove_file` only removes the
+    /// one of the current locale
+    pub fn remove_file_locale(&mut self, path: &str, locale: u32) -> Result<bool> {
+        let cpath = CString::new(path)?;
+        let (removed, err) = unsafe {
+            // StormLib takes the locale from a global setting
+            let previous = SFileGetLocale();
+            SFileSetLocale(locale as LCID);
+            let removed = SFileRemoveFile(self.handle, cpath.as_ptr(), 0);
+            let err = GetLastError();
+            SFileSetLocale(previous);
+            (removed, err)
+        };
+        if !removed && err != ERROR_FILE_NOT_FOUND {
+            return Err(From::from(ErrorCode(err)));
+        }
+        Ok(removed)
+    }
+
+    /// Names of the files `remove_matching` removes for `mask`, which may
+    /// contain `*` and `?` wildcards
+    pub fn matching(&mut self, mask: &str) -> Result<Vec<String>> {
+        let mask = mask.replace('/', "\\");
+        Ok(crate::removable_names(self.find(&mask)?, &mask))
+    }
+
+    /// Removes every file matching `mask` in all its locales and returns their
+    /// names; internal files like `(listfile)` are only removed when named exactly
+    pub fn remove_matching(&mut self, mask: &str) -> Result<Vec<String>> {
+        let names = self.matching(mask)?;
+        for entry in self.find(&mask.replace('/', "\\"))? {
+            if names.iter().any(|n| n.eq_ignore_ascii_case(&entry.name)) {
+                self.remove_file_locale(&entry.name, entry.locale)?;
+            }
+        }
+        Ok(names)
+    }
+
+    pub fn compact(&mut self) -> Result<()> {
+        unsafe_try_call!(SFileCompactArchive(self.handle, ptr::null_mut(), false));
+        Ok(())
+    }
+
+    /// Loads names from an external listfile, so entries missing from `(listfile)` can be resolved
+    pub fn add_listfile<P: AsRef<Path>>(&mut self, path: P) -> Result<()> {
+        let cpath = to_native_path(path)?;
+        let code = unsafe { SFileAddListFile(self.handle, cpath.as_ptr()) };
+        if code as u32 != ERROR_SUCCESS {
+            return Err(From::from(ErrorCode(code as u32)));
+        }
+        Ok(())
+    }
+
+    /// Compacts the archive after loading names from the given external listfiles
+    pub fn compact_with_listfiles<P: AsRef<Path>>(&mut self, listfiles: &[P]) -> Result<()> {
+        for listfile in listfiles {
+            self.add_listfile(listfile)?;
+        }
+        self.compact()
+    }
+
+    /// Writes a freshly compacted copy of the archive at `path` to `output`,
+    /// leaving the original untouched
+    ///
+    /// The copy is compacted next to `output` and only replaces it once done,
+    /// so a failed rebuild leaves `output` as it was.
+    pub fn rebuild<P, Q, L>(path: P, output: Q, listfiles: &[L]) -> Result<Self>
+    where
+        P: AsRef<Path>,
+        Q: AsRef<Path>,
+        L: AsRef<Path>,
+    {
+        let output = output.as_ref();
+        if absolute_target(path.as_ref())? == absolute_target(output)? {
+            return Err(StormError::InvalidParameter);
+        }
+        let temp = create_temp_next_to(output)?;
+        let rebuilt = fs::copy(path, &temp)
+            .map_err(StormError::from)
+            .and_then(|_| {
+                let mut archive = Archive::open(&temp, OpenArchiveFlags::MPQ_OPEN_NO_FLAG)?;
+                archive.compact_with_listfiles(listfiles)
+            })
+            .and_then(|_| Ok(fs::rename(&temp, output)?));
+        if let Err(e) = rebuilt {
+            let _ = fs::remove_file(&temp);
+            return Err(e);
+        }
+        Archive::open(output, OpenArchiveFlags::MPQ_OPEN_NO_FLAG)
+    }
+
+    /// Lists entries whose names match `mask`, which may contain `*` and `?` wildcards
+    pub fn find(&mut self, mask: &str) -> Result<Vec<FileEntry>> {
+        let cmask = CString::new(mask)?;
+        let mut data: SFILE_FIND_DATA = unsafe { std::mem::zeroed() };
+        let find =
+            unsafe { SFileFindFirstFile(self.handle, cmask.as_ptr(), &mut data, ptr::null()) };
+        if find.is_null() {
+            let err = unsafe { GetLastError() };
+            if err == ERROR_NO_MORE_FILES {
+                return Ok(Vec::new());
+            }
+            return Err(From::from(ErrorCode(err)));
+        }
+        let mut entries = vec![FileEntry::from(&data)];
+        unsafe {
+            while SFileFindNextFile(find, &mut data) {
+                entries.push(FileEntry::from(&data));
+            }
+            SFileFindClose(find);
+        }
+        Ok(entries)
+    }
+
+    /// Lists all entries of the archive
+    pub fn list(&mut self) -> Result<Vec<FileEntry>> {
+        self.find("*")
+    }
+
+    pub fn get_max_files(&mut self) -> Result<u32> {
+        unsafe {
+            let count = SFileGetMaxFileCount(self.handle);
+            Ok(count)
+        }
+    }
+
+    pub fn set_max_files(&mut self, count: u32) -> Result<()> {
+        unsafe_try_call!(SFileSetMaxFileCount(self.handle, count));
+        Ok(())
+    }
+}
+
+impl std::ops::Drop for Archive {
+    fn drop(&mut self) {
+        unsafe {
+            SFileCloseArchive(self.handle);
+        }
+    }
+}
+
+impl From<&SFILE_FIND_DATA> for FileEntry {
+    fn from(data: &SFILE_FIND_DATA) -> Self {
+        let name = unsafe { CStr::from_ptr(data.cFileName.as_ptr()) };
+        FileEntry {
+            name:            name.to_string_lossy().into_owned(),
+            hash_index:      data.dwHashIndex,
+            block_index:     data.dwBlockIndex,
+            file_size:       data.dwFileSize,
+            compressed_size: data.dwCompSize,
+            flags:           FileFlags::from_bits_truncate(data.dwFileFlags),
+            file_time:       (data.dwFileTimeHi as u64) << 32 | data.dwFileTimeLo as u64,
+            locale:          data.lcLocale,
+        }
+    }
+}
+
+/// Opened file
+#[derive(Debug)]
+pub struct File<'a> {
+    archive:                &'a Archive,
+    pub(crate) file_handle: HANDLE,
+    size:                   Option<u64>,
+    need_reset:             bool,
+}
+
+impl<'a> File<'a> {
+    /// Retrieves a size of the file within archive
+    pub fn get_size(&mut self) -> Result<u64> {
+        if let Some(size) = self.size.clone() {
+            Ok(size)
+        } else {
+            let mut high: DWORD = 0;
+            let low = unsafe { SFileGetFileSize(self.file_handle, &mut high as *mut DWORD) };
+            if low == SFILE_INVALID_SIZE {
+                return Err(From::from(ErrorCode(unsafe { GetLastError() })));
+            }
+            let high = (high as u64) << 32;
+            let size = high | (low as u64);
+            self.size = Some(size);
+            return Ok(size);
+        }
+    }
+
+    /// Reads all data from the file
+    pub fn read_all(&mut self) -> Result<Vec<u8>> {
+        if self.need_reset {
+            unsafe {
+                if SFileSetFilePointer(self.file_handle, 0, ptr::null_mut(), 0)
+                    == SFILE_INVALID_SIZE
+                {
+                    return Err(From::from(ErrorCode(GetLastError())));
+                }
+            }
+        }
+
+        let size = self.get_size()?;
+        let mut buf = Vec::<u8>::with_capacity(size as usize);
+        buf.resize(buf.capacity(), 0);
+        let mut read: DWORD = 0;
+        self.need_reset = true;
+        unsafe_try_call!(SFileReadFile(
+            self.file_handle,
+            std::mem::transmute(buf.as_mut_ptr()),
+            size as u32,
+            &mut read as *mut DWORD,
+            ptr::null_mut(),
+        ));
+        if (read as u64) < size {
+            buf.truncate(read as usize);
+        }
+        Ok(buf)
+    }
+}
+
+/// Reads continue from the current position, while `read_all` always starts
+/// from the beginning of the file
+impl<'a> Read for File<'a> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let len = buf.len().min(u32::MAX as usize) as DWORD;
+        let mut read: DWORD = 0;
+        let ok = unsafe {
+            SFileReadFile(
+                self.file_handle,
+                buf.as_mut_ptr() as *mut c_void,
+                len,
+                &mut read as *mut DWORD,
+                ptr::null_mut(),
+            )
+        };
+        if !ok {
+            let err = unsafe { GetLastError() };
+            if err != ERROR_HANDLE_EOF {
+                return Err(io::Error::other(StormError::from(ErrorCode(err))));
+            }
+        }
+        self.need_reset = true;
+        Ok(read as usize)
+    }
+}
+
+impl<'a> std::ops::Drop for File<'a> {
+    fn drop(&mut self) {
+        unsafe {
+            SFileCloseFile(self.file_handle);
+        }
+    }
+}
+
+/// File being written, created by `Archive::create_file`
+///
+/// The file is added to the archive by `finish` once all of its bytes have
+/// been written; dropping it unfinished discards it.
+#[derive(Debug)]
+pub struct FileWriter<'a> {
+    archive:     PhantomData<&'a Archive>,
+    handle:      HANDLE,
+    compression: (u32, u32),
+    sector_size: u64,
+    written:     u64,
+    finished:    bool,
+}
+
+impl<'a> FileWriter<'a> {
+    fn write_data(&mut self, data: &[u8]) -> Result<()> {
+        let (first, next) = self.compression;
+        let mut rest = data;
+        while !rest.is_empty() {
+            // the first sector may use a different compression, see `compression_masks`
+            let (len, compression) = if self.written < self.sector_size {
+                let left = (self.sector_size - self.written) as usize;
+                (rest.len().min(left), first)
+            } else {
+                (rest.len(), next)
+            };
+            let (chunk, tail) = rest.split_at(len);
+            unsafe_try_call!(SFileWriteFile(
+                self.handle,
+                chunk.as_ptr() as *const c_void,
+                chunk.len() as u32,
+                compression
+            ));
+            self.written += len as u64;
+            rest = tail;
+        }
+        Ok(())
+    }
+
+    /// Completes the file, failing if fewer bytes than announced were written
+    pub fn finish(mut self) -> Result<()> {
+        self.finished = true;
+        unsafe_try_call!(SFileFinishFile(self.handle));
+        Ok(())
+    }
+}
+
+impl<'a> Write for FileWriter<'a> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.write_data(buf).map_err(io::Error::other)?;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl<'a> std::ops::Drop for FileWriter<'a> {
+    fn drop(&mut self) {
+        if !self.finished {
+            unsafe {
+                SFileFinishFile(self.handle);
+            }
+        }
+    }
+}
@@ -1,8 +1,7 @@
-use std::ffi::*;
-use std::path::Path;
-use std::ptr;
-use stormlib_sys::*;
+#[cfg(not(any(feature = "stormlib-sys", feature = "pure-rust")))]
+compile_error!("enable either the default `stormlib-sys` feature or the `pure-rust` feature");
 
+#[cfg(not(feature = "pure-rust"))]
 #[macro_use]
 mod util;
 
@@ -10,305 +9,124 @@ mod constants;
 pub use constants::*;
 
 pub mod error;
-use error::*;
 
-/// MPQ archive
-#[derive(Debug)]
-pub struct Archive {
-    handle: HANDLE,
+pub mod crypto;
+
+mod user_data;
+pub use user_data::*;
+
+pub mod native;
+pub use native::{Compression, FileOptions};
+
+#[cfg(not(feature = "pure-rust"))]
+mod ffi;
+#[cfg(not(feature = "pure-rust"))]
+pub use ffi::{Archive, File, FileWriter};
+
+#[cfg(feature = "pure-rust")]
+pub use native::{Archive, File, FileWriter};
+
+#[cfg(feature = "tokio")]
+mod async_archive;
+#[cfg(feature = "tokio")]
+pub use async_archive::{AsyncArchive, AsyncFile};
+
+/// Entry found while enumerating an archive
+#[derive(Debug, Clone)]
+pub struct FileEntry {
+    pub name:            String,
+    pub hash_index:      u32,
+    pub block_index:     u32,
+    pub file_size:       u32,
+    pub compressed_size: u32,
+    pub flags:           FileFlags,
+    pub file_time:       u64,
+    pub locale:          u32,
 }
 
-impl Archive {
-    /// Opens a MPQ archive
-    pub fn open<P: AsRef<Path>>(path: P, flags: OpenArchiveFlags) -> Result<Self> {
-        #[cfg(not(target_os = "windows"))]
-        let cpath = {
-            let pathstr = path.as_ref().to_str().ok_or_else(|| StormError::NonUtf8)?;
-            CString::new(pathstr)?
-        };
-        #[cfg(target_os = "windows")]
-        let cpath = {
-            use widestring::U16CString;
-            U16CString::from_os_str(path.as_ref())
-                .map_err(|_| StormError::InteriorNul)?
-                .into_vec()
-        };
-        let mut handle: HANDLE = ptr::null_mut();
-        unsafe_try_call!(SFileOpenArchive(
-            cpath.as_ptr(),
-            0,
-            flags.bits(),
-            &mut handle as *mut HANDLE,
-        ));
-        Ok(Archive { handle })
+impl FileEntry {
+    /// Whether StormLib made up the name (`File00000012.xxx`) because it is missing from `(listfile)`
+    pub fn is_unnamed(&self) -> bool {
+        let stem = self.name.split('.').next().unwrap_or_default();
+        stem.len() == 12
+            && stem.starts_with("File")
+            && stem[4..].bytes().all(|b| b.is_ascii_digit())
     }
 
-    pub fn create<P: AsRef<Path>>(path: P, filecount: usize, use_filelist: bool) -> Result<Self> {
-        #[cfg(not(target_os = "windows"))]
-        let cpath = {
-            let pathstr = path.as_ref().to_str().ok_or_else(|| StormError::NonUtf8)?;
-            CString::new(pathstr)?
-        };
-        #[cfg(target_os = "windows")]
-        let cpath = {
-            use widestring::U16CString;
-            U16CString::from_os_str(path.as_ref())
-                .map_err(|_| StormError::InteriorNul)?
-                .into_vec()
-        };
-        let mut handle: HANDLE = ptr::null_mut();
-        let flags = 0;
-        let dwMpqVersion = (flags & MPQ_CREATE_ARCHIVE_VMASK) >> 24;
-        let dwStreamFlags = 0;
-        let mut dwFileFlags1 = if flags & MPQ_CREATE_LISTFILE != 0 {
-            MPQ_FILE_DEFAULT_INTERNAL
-        } else {
-            0
-        };
-        let dwFileFlags2 = if flags & MPQ_CREATE_ATTRIBUTES != 0 {
-            MPQ_FILE_DEFAULT_INTERNAL
-        } else {
-            0
-        };
-        let dwFileFlags3 = if flags & MPQ_CREATE_SIGNATURE != 0 {
-            MPQ_FILE_DEFAULT_INTERNAL
-        } else {
-            0
-        };
-        let mut dwAttrFlags = if flags & MPQ_CREATE_ATTRIBUTES != 0 {
-            MPQ_ATTRIBUTE_CRC32 | MPQ_ATTRIBUTE_FILETIME | MPQ_ATTRIBUTE_MD5
-        } else {
-            0
-        };
-        let dwSectorSize: u32 = if dwMpqVersion >= MPQ_FORMAT_VERSION_3 {
-            0x4000
-        } else {
-            0x1000
-        };
-        let dwRawChunkSize = if dwMpqVersion >= MPQ_FORMAT_VERSION_4 {
-            0x4000
-        } else {
-            0
-        };
-        let dwMaxFileCount = filecount;
-
-        if dwMpqVersion >= MPQ_FORMAT_VERSION_3 && flags & MPQ_CREATE_ATTRIBUTES != 0 {
-            dwAttrFlags |= MPQ_ATTRIBUTE_PATCH_BIT;
-        }
-
-        if use_filelist {
-            dwFileFlags1 = MPQ_FILE_DEFAULT_INTERNAL;
-        }
-
-        let cbSize = ::std::mem::size_of::<_SFILE_CREATE_MPQ>() as u32;
-        let mut ci = Box::new(_SFILE_CREATE_MPQ {
-            cbSize,
-            dwMpqVersion,
-            pvUserData: ptr::null_mut(),
-            cbUserData: 0,
-            dwStreamFlags,
-            dwFileFlags1,
-            dwFileFlags2,
-            dwFileFlags3,
-            dwAttrFlags,
-            dwSectorSize,
-            dwRawChunkSize,
-            dwMaxFileCount: dwMaxFileCount as u32,
-        });
-
-        unsafe_try_call!(SFileCreateArchive2(
-            cpath.as_ptr(),
-            &mut *ci,
-            &mut handle as *mut HANDLE
-        ));
-
-        Ok(Archive { handle })
-    }
-
-    /// Quick check if the file exists within MPQ archive, without opening it
-    pub fn has_file(&mut self, path: &str) -> Result<bool> {
-        let cpath = CString::new(path)?;
-        unsafe {
-            let r = SFileHasFile(self.handle, cpath.as_ptr());
-            let err = GetLastError();
-            if !r && err != ERROR_FILE_NOT_FOUND {
-                return Err(From::from(ErrorCode(err)));
-            }
-            Ok(r)
-        }
-    }
-
-    /// Opens a file from MPQ archive
-    pub fn open_file<'a>(&'a mut self, path: &str) -> Result<File<'a>> {
-        let mut file_handle: HANDLE = ptr::null_mut();
-        let cpath = CString::new(path)?;
-        unsafe_try_call!(SFileOpenFileEx(
-            self.handle,
-            cpath.as_ptr(),
-            0,
-            &mut file_handle as *mut HANDLE
-        ));
-        Ok(File {
-            archive: self,
-            file_handle,
-            size: None,
-            need_reset: false,
-        })
-    }
-
-    pub fn write_file(&self, file_name: &str, data: &[u8]) -> Result<bool> {
-        let cpath = CString::new(file_name)?;
-        let mut handle = ptr::null_mut();
-        unsafe_try_call!(SFileCreateFile(
-            self.handle,
-            cpath.as_ptr(),
-            0,
-            data.len() as u32,
-            0,
-            MPQ_FILE_REPLACEEXISTING,
-            &mut handle,
-        ));
-        unsafe_try_call!(SFileWriteFile(
-            handle,
-            data.as_ptr() as *const c_void,
-            data.len() as u32,
-            0
-        ));
-        unsafe_try_call!(SFileFinishFile(handle));
-        Ok(true)
-    }
-
-    pub fn add_file(&mut self, path: &str, local_path: &str) -> Result<()> {
-        #[cfg(not(target_os = "windows"))]
-        let clocal_path = {
-            let pathstr = local_path
-                .as_ref()
-                .to_str()
-                .ok_or_else(|| StormError::NonUtf8)?;
-            CString::new(pathstr)?
-        };
-        #[cfg(target_os = "windows")]
-        let clocal_path = {
-            use widestring::U16CString;
-            U16CString::from_os_str(local_path)
-                .map_err(|_| StormError::InteriorNul)?
-                .into_vec()
-        };
-        let _ = self.remove_file(path);
-        let cpath = CString::new(path)?;
-        unsafe_try_call!(SFileAddFileEx(
-            self.handle,
-            clocal_path.as_ptr(),
-            cpath.as_ptr(),
-            MPQ_FILE_COMPRESS | MPQ_FILE_ENCRYPTED,
-            MPQ_COMPRESSION_ZLIB,
-            MPQ_COMPRESSION_NEXT_SAME,
-        ));
-        Ok(())
-    }
-
-    pub fn remove_file(&mut self, path: &str) -> Result<bool> {
-        let cpath = CString::new(path)?;
-        unsafe {
-            let r = SFileRemoveFile(self.handle, cpath.as_ptr(), 0);
-            let err = GetLastError();
-            if !r && err != ERROR_FILE_NOT_FOUND {
-                return Err(From::from(ErrorCode(err)));
-            }
-            Ok(r)
-        }
-    }
-
-    pub fn compact(&mut self) -> Result<()> {
-        unsafe_try_call!(SFileCompactArchive(self.handle, ptr::null_mut(), false));
-        Ok(())
-    }
-
-    pub fn get_max_files(&mut self) -> Result<u32> {
-        unsafe {
-            let count = SFileGetMaxFileCount(self.handle);
-            Ok(count)
-        }
-    }
-
-    pub fn set_max_files(&mut self, count: u32) -> Result<()> {
-        unsafe_try_call!(SFileSetMaxFileCount(self.handle, count));
-        Ok(())
+    /// Whether the entry is one of the files describing the archive, like `(listfile)`
+    pub fn is_internal(&self) -> bool {
+        self.name.starts_with('(') && self.name.ends_with(')')
     }
 }
 
-impl std::ops::Drop for Archive {
-    fn drop(&mut self) {
-        unsafe {
-            SFileCloseArchive(self.handle);
-        }
-    }
+/// What `import_from` does with a file already in the target archive, in the same locale
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConflictPolicy {
+    /// Keeps the file of the target archive
+    Keep,
+    /// Replaces it with the imported file
+    Overwrite,
+    /// Imports nothing and fails with `StormError::AlreadyExists`
+    Fail,
 }
 
-/// Opened file
-#[derive(Debug)]
-pub struct File<'a> {
-    archive:     &'a Archive,
-    file_handle: HANDLE,
-    size:        Option<u64>,
-    need_reset:  bool,
+/// Names of the files handled by `import_from`
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Import {
+    /// Copied as stored, without recompressing them
+    pub copied:       Vec<String>,
+    /// Decompressed and stored again
+    pub recompressed: Vec<String>,
+    /// Left out because the target archive already had them
+    pub kept:         Vec<String>,
+    /// Left out because their name is unknown
+    pub unnamed:      Vec<String>,
+    /// Left out because they could neither be copied as stored nor decoded
+    pub unreadable:   Vec<String>,
 }
 
-impl<'a> File<'a> {
-    /// Retrieves a size of the file within archive
-    pub fn get_size(&mut self) -> Result<u64> {
-        if let Some(size) = self.size.clone() {
-            Ok(size)
-        } else {
-            let mut high: DWORD = 0;
-            let low = unsafe { SFileGetFileSize(self.file_handle, &mut high as *mut DWORD) };
-            if low == SFILE_INVALID_SIZE {
-                return Err(From::from(ErrorCode(unsafe { GetLastError() })));
-            }
-            let high = (high as u64) << 32;
-            let size = high | (low as u64);
-            self.size = Some(size);
-            return Ok(size);
+/// Names matched by `mask` that `remove_matching` removes, once per name;
+/// internal files are only included when `mask` names them exactly
+fn removable_names(entries: Vec<FileEntry>, mask: &str) -> Vec<String> {
+    let mut names: Vec<String> = Vec::new();
+    for entry in entries {
+        if entry.is_internal() && !entry.name.eq_ignore_ascii_case(mask) {
+            continue;
         }
-    }
-
-    /// Reads all data from the file
-    pub fn read_all(&mut self) -> Result<Vec<u8>> {
-        if self.need_reset {
-            unsafe {
-                if SFileSetFilePointer(self.file_handle, 0, ptr::null_mut(), 0)
-                    == SFILE_INVALID_SIZE
-                {
-                    return Err(From::from(ErrorCode(GetLastError())));
-                }
-            }
+        if !names.iter().any(|n| n.eq_ignore_ascii_case(&entry.name)) {
+            names.push(entry.name);
         }
-
-        let size = self.get_size()?;
-        let mut buf = Vec::<u8>::with_capacity(size as usize);
-        buf.resize(buf.capacity(), 0);
-        let mut read: DWORD = 0;
-        self.need_reset = true;
-        unsafe_try_call!(SFileReadFile(
-            self.file_handle,
-            std::mem::transmute(buf.as_mut_ptr()),
-            size as u32,
-            &mut read as *mut DWORD,
-            ptr::null_mut(),
-        ));
-        if (read as u64) < size {
-            buf.truncate(read as usize);
-        }
-        Ok(buf)
     }
+    names
 }
 
-impl<'a> std::ops::Drop for File<'a> {
-    fn drop(&mut self) {
-        unsafe {
-            SFileCloseFile(self.file_handle);
-        }
-    }
+#[test]
+fn test_removable_names() {
+    let entry = |name: &str| FileEntry {
+        name:            name.to_string(),
+        hash_index:      0,
+        block_index:     0,
+        file_size:       0,
+        compressed_size: 0,
+        flags:           FileFlags::empty(),
+        file_time:       0,
+        locale:          0,
+    };
+    // entries as found for the mask, one per locale
+    let entries = vec![
+        entry("(listfile)"),
+        entry("war3map.j"),
+        entry("war3map.j"),
+        entry("war3mapImported\\a.mdx"),
+    ];
+    assert_eq!(
+        removable_names(entries, "*"),
+        vec!["war3map.j", "war3mapImported\\a.mdx"]
+    );
+    assert_eq!(
+        removable_names(vec![entry("(listfile)")], "(LISTFILE)"),
+        vec!["(listfile)"]
+    );
 }
 
 #[test]
@@ -333,6 +151,7 @@ fn test_read() {
 #[test]
 fn test_read_unicode() {
     use widestring::U16CString;
+    use std::ffi::OsString;
     use std::os::windows::ffi::OsStringExt;
     let mut archive = Archive::open(
         OsString::from_wide(
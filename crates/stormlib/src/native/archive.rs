@@ -0,0 +1,1043 @@
+//! Native implementation of `Archive` and `File`, mirroring the StormLib backed API
+
+use std::collections::HashMap;
+use std::fs;
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::marker::PhantomData;
+use std::path::Path;
+
+use md5::{Digest, Md5};
+
+use super::attributes::Attributes;
+use super::compression;
+use super::header::MpqHeader;
+use super::sys::*;
+use super::tables::{self, BetTable, BlockEntry, HetTable};
+use super::writer::{self, FileOptions, RawFile};
+use crate::crypto::{self, HashEntry, NameHash};
+use crate::error::*;
+use crate::{ConflictPolicy, FileEntry, FileFlags, Import, OpenArchiveFlags, UserData};
+
+/// MPQ archive, read-only: the methods modifying it fail with `NotSupported`
+#[derive(Debug)]
+pub struct Archive {
+    file:             fs::File,
+    header:           MpqHeader,
+    user_data:        Option<UserData>,
+    hash_table:       Vec<HashEntry>,
+    het_table:        Option<(HetTable, BetTable)>,
+    blocks:           Vec<BlockEntry>,
+    names:            HashMap<u32, String>,
+    attributes:       Attributes,
+    check_sector_crc: bool,
+}
+
+impl Archive {
+    /// Opens a MPQ archive
+    pub fn open<P: AsRef<Path>>(path: P, flags: OpenArchiveFlags) -> Result<Self> {
+        let provider =
+            OpenArchiveFlags::STREAM_PROVIDER_MASK | OpenArchiveFlags::BASE_PROVIDER_HTTP;
+        if flags.intersects(provider) {
+            return Err(StormError::NotSupported);
+        }
+
+        let mut file = fs::File::open(path)?;
+        let (header, user_data) = MpqHeader::find(&mut file, flags)?;
+        let hash_table = tables::load_hash_table(&mut file, &header)?;
+        let mut blocks = tables::load_block_table(&mut file, &header)?;
+        let mut het_table = None;
+        if header.het_table_pos != 0 && header.bet_table_pos != 0 {
+            let het = HetTable::load(&mut file, &header)?;
+            let bet = BetTable::load(&mut file, &header)?;
+            if hash_table.is_empty() {
+                blocks = bet.entries.clone();
+            }
+            het_table = Some((het, bet));
+        }
+
+        let mut archive = Archive {
+            file,
+            header,
+            user_data,
+            hash_table,
+            het_table,
+            blocks,
+            names: HashMap::new(),
+            attributes: Attributes::default(),
+            check_sector_crc: flags.contains(OpenArchiveFlags::MPQ_OPEN_CHECK_SECTOR_CRC),
+        };
+        for name in &[LISTFILE_NAME, ATTRIBUTES_NAME, SIGNATURE_NAME] {
+            archive.add_name(name);
+        }
+        if !flags.contains(OpenArchiveFlags::MPQ_OPEN_NO_LISTFILE) {
+            if let Ok(listfile) = archive.read_internal(LISTFILE_NAME) {
+                archive.add_names(&listfile);
+            }
+        }
+        if !flags.contains(OpenArchiveFlags::MPQ_OPEN_NO_ATTRIBUTES) {
+            if let Ok(data) = archive.read_internal(ATTRIBUTES_NAME) {
+                if let Some(attributes) = Attributes::parse(&data, archive.blocks.len()) {
+                    archive.attributes = attributes;
+                }
+            }
+        }
+        Ok(archive)
+    }
+
+    /// Creating archives is not supported by the native backend
+    pub fn create<P: AsRef<Path>>(
+        _path: P,
+        _filecount: usize,
+        _use_filelist: bool,
+    ) -> Result<Self> {
+        Err(StormError::NotSupported)
+    }
+
+    /// Creating archives is not supported by the native backend
+    pub fn create_with_user_data<P: AsRef<Path>>(
+        _path: P,
+        _filecount: usize,
+        _use_filelist: bool,
+        _content: &[u8],
+    ) -> Result<Self> {
+        Err(StormError::NotSupported)
+    }
+
+    /// MPQ header, with the positions of all tables
+    pub fn header(&self) -> &MpqHeader {
+        &self.header
+    }
+
+    /// Reads the user data block preceding the MPQ header, if any
+    pub fn user_data(&self) -> Result<Option<UserData>> {
+        Ok(self.user_data.clone())
+    }
+
+    /// Quick check if the file exists within MPQ archive, without opening it
+    pub fn has_file(&mut self, path: &str) -> Result<bool> {
+        Ok(self.locate(path).is_some())
+    }
+
+    /// Opens a file from MPQ archive
+    ///
+    /// Entries without a known name can be opened by the `File00000012.xxx`
+    /// name they are listed with.
+    pub fn open_file<'a>(&'a mut self, path: &str) -> Result<File<'a>> {
+        let block_index = self.locate(path).ok_or(StormError::FileNotFound)?;
+        Ok(self.open_block(block_index))
+    }
+
+    /// Opens the file of a given locale, or the neutral one if there is none
+    pub fn open_file_locale(&mut self, path: &str, locale: u32) -> Result<File<'_>> {
+        let found = self
+            .find(path)?
+            .into_iter()
+            .find(|entry| entry.locale == locale && entry.name.eq_ignore_ascii_case(path));
+        match found {
+            Some(entry) => Ok(self.open_block(entry.block_index)),
+            None => self.open_file(path),
+        }
+    }
+
+    /// Reads a file as stored, to copy it into an `ArchiveBuilder` without
+    /// recompressing it
+    pub fn read_raw(&mut self, path: &str) -> Result<RawFile> {
+        let block_index = self.locate(path).ok_or(StormError::FileNotFound)?;
+        self.read_raw_block(block_index, true)
+    }
+
+    /// Reads a file as stored like `read_raw`, without decoding it: the
+    /// checksums are the ones `(attributes)` records, zeros without it
+    pub fn read_stored(&mut self, path: &str) -> Result<RawFile> {
+        let block_index = self.locate(path).ok_or(StormError::FileNotFound)?;
+        self.read_raw_block(block_index, false)
+    }
+
+    /// Reads an entry found by `find` or `list` as stored, in its own locale
+    pub fn read_raw_entry(&mut self, entry: &FileEntry) -> Result<RawFile> {
+        self.read_raw_block(entry.block_index, true)
+    }
+
+    pub(super) fn open_block(&self, block_index: u32) -> File<'_> {
+        File {
+            archive:  self,
+            block:    self.blocks[block_index as usize],
+            name:     self.names.get(&block_index).cloned(),
+            sectors:  None,
+            sector:   None,
+            position: 0,
+        }
+    }
+
+    /// Files whose content can't be decoded, as with compression methods
+    /// the native reader doesn't know, keep the checksums `(attributes)`
+    /// records for them; they are only left empty without it
+    fn read_raw_block(&mut self, block_index: u32, decode: bool) -> Result<RawFile> {
+        let block = *self
+            .blocks
+            .get(block_index as usize)
+            .ok_or(StormError::FileNotFound)?;
+        let content = if decode {
+            self.open_block(block_index).read_all()
+        } else {
+            Err(StormError::NotSupported)
+        };
+        let (crc32, md5) = match content {
+            Ok(content) => (writer::crc32(&content), Md5::digest(&content).into()),
+            // files that can't be decoded keep the checksums recorded for them
+            Err(_) => (
+                self.attributes
+                    .crc32
+                    .get(block_index as usize)
+                    .copied()
+                    .unwrap_or(0),
+                self.attributes
+                    .md5
+                    .get(block_index as usize)
+                    .copied()
+                    .unwrap_or([0; 16]),
+            ),
+        };
+        Ok(RawFile {
+            data: self.read_at(block.file_pos, block.compressed_size as u64)?,
+            file_size: block.file_size,
+            flags: block.flags,
+            sector_size: self.header.sector_size(),
+            crc32,
+            md5,
+        })
+    }
+
+    pub fn write_file(&self, _file_name: &str, _data: &[u8]) -> Result<bool> {
+        Err(StormError::NotSupported)
+    }
+
+    pub fn write_file_with(
+        &self,
+        _file_name: &str,
+        _data: &[u8],
+        _options: FileOptions,
+    ) -> Result<bool> {
+        Err(StormError::NotSupported)
+    }
+
+    /// Archives are read-only in the native backend, use
+    /// `ArchiveBuilder::import_from` to merge archives
+    pub fn import_from<F>(
+        &mut self,
+        _other: &mut Archive,
+        _filter: F,
+        _policy: ConflictPolicy,
+    ) -> Result<Import>
+    where
+        F: FnMut(&FileEntry) -> bool,
+    {
+        Err(StormError::NotSupported)
+    }
+
+    pub fn create_file(
+        &self,
+        _file_name: &str,
+        _size: u64,
+        _options: FileOptions,
+    ) -> Result<FileWriter<'_>> {
+        Err(StormError::NotSupported)
+    }
+
+    pub fn add_file(&mut self, _path: &str, _local_path: &str) -> Result<()> {
+        Err(StormError::NotSupported)
+    }
+
+    pub fn add_file_with(
+        &mut self,
+        _path: &str,
+        _local_path: &str,
+        _options: FileOptions,
+    ) -> Result<()> {
+        Err(StormError::NotSupported)
+    }
+
+    pub fn remove_file(&mut self, _path: &str) -> Result<bool> {
+        Err(StormError::NotSupported)
+    }
+
+    pub fn remove_file_locale(&mut self, _path: &str, _locale: u32) -> Result<bool> {
+        Err(StormError::NotSupported)
+    }
+
+    /// Names of the files `remove_matching` removes for `mask`, which may
+    /// contain `*` and `?` wildcards
+    pub fn matching(&mut self, mask: &str) -> Result<Vec<String>> {
+        let mask = mask.replace('/', "\\");
+        Ok(crate::removable_names(self.find(&mask)?, &mask))
+    }
+
+    /// Removes every file matching `mask` in all its locales and returns their
+    /// names; internal files like `(listfile)` are only removed when named exactly
+    pub fn remove_matching(&mut self, mask: &str) -> Result<Vec<String>> {
+        let names = self.matching(mask)?;
+        for entry in self.find(&mask.replace('/', "\\"))? {
+            if names.iter().any(|n| n.eq_ignore_ascii_case(&entry.name)) {
+                self.remove_file_locale(&entry.name, entry.locale)?;
+            }
+        }
+        Ok(names)
+    }
+
+    pub fn compact(&mut self) -> Result<()> {
+        Err(StormError::NotSupported)
+    }
+
+    /// Loads names from an external listfile, so entries missing from `(listfile)` can be resolved
+    pub fn add_listfile<P: AsRef<Path>>(&mut self, path: P) -> Result<()> {
+        let content = fs::read(path)?;
+        self.add_names(&content);
+        Ok(())
+    }
+
+    /// Names files with names found elsewhere, like `add_listfile` does with a file
+    pub fn add_known_names<S: AsRef<str>>(&mut self, names: &[S]) {
+        for name in names {
+            self.add_name(name.as_ref());
+        }
+    }
+
+    /// Compacts the archive after loading names from the given external listfiles
+    pub fn compact_with_listfiles<P: AsRef<Path>>(&mut self, listfiles: &[P]) -> Result<()> {
+        for listfile in listfiles {
+            self.add_listfile(listfile)?;
+        }
+        self.compact()
+    }
+
+    /// Rebuilding archives is not supported by the native backend
+    pub fn rebuild<P, Q, L>(_path: P, _output: Q, _listfiles: &[L]) -> Result<Self>
+    where
+        P: AsRef<Path>,
+        Q: AsRef<Path>,
+        L: AsRef<Path>,
+    {
+        Err(StormError::NotSupported)
+    }
+
+    /// Lists entries whose names match `mask`, which may contain `*` and `?` wildcards
+    pub fn find(&mut self, mask: &str) -> Result<Vec<FileEntry>> {
+        let mut found = Vec::new();
+        for (hash_index, block_index, locale) in self.occupied_slots() {
+            let block = match self.blocks.get(block_index as usize) {
+                Some(block) if block.exists() => block,
+                _ => continue,
+            };
+            let name = match self.names.get(&block_index) {
+                Some(name) => name.clone(),
+                None => format!("File{:08}.xxx", block_index),
+            };
+            if !wildcard_match(mask, &name) {
+                continue;
+            }
+            found.push(FileEntry {
+                name,
+                hash_index,
+                block_index,
+                file_size: block.file_size,
+                compressed_size: block.compressed_size,
+                flags: block.flags,
+                file_time: self
+                    .attributes
+                    .file_times
+                    .get(block_index as usize)
+                    .copied()
+                    .unwrap_or(0),
+                locale,
+            });
+        }
+        Ok(found)
+    }
+
+    /// Lists all entries of the archive
+    pub fn list(&mut self) -> Result<Vec<FileEntry>> {
+        self.find("*")
+    }
+
+    pub fn get_max_files(&mut self) -> Result<u32> {
+        Ok(self.hash_table.len().max(self.blocks.len()) as u32)
+    }
+
+    pub fn set_max_files(&mut self, _count: u32) -> Result<()> {
+        Err(StormError::NotSupported)
+    }
+
+    /// Hash table slots in use, with their block index and locale
+    fn occupied_slots(&self) -> Vec<(u32, u32, u32)> {
+        if !self.hash_table.is_empty() {
+            return self
+                .hash_table
+                .iter()
+                .enumerate()
+                .filter(|(_, entry)| !entry.is_free() && !entry.is_deleted())
+                .map(|(i, entry)| (i as u32, entry.block_index, entry.locale as u32))
+                .collect();
+        }
+        match &self.het_table {
+            Some((het, _)) => het
+                .entries()
+                .into_iter()
+                .map(|(slot, _, index)| (slot as u32, index, 0))
+                .collect(),
+            None => Vec::new(),
+        }
+    }
+
+    /// Finds the block index of a name, preferring the neutral locale
+    fn locate(&self, name: &str) -> Option<u32> {
+        let exists = |index: u32| {
+            self.blocks
+                .get(index as usize)
+                .is_some_and(BlockEntry::exists)
+        };
+        if let Some(index) = pseudo_index(name) {
+            let listed = self
+                .occupied_slots()
+                .iter()
+                .any(|&(_, block_index, _)| block_index == index);
+            return Some(index).filter(|&index| listed && exists(index));
+        }
+
+        if !self.hash_table.is_empty() {
+            let entries = NameHash::new(name).lookup(&self.hash_table);
+            let entry = entries
+                .iter()
+                .find(|e| e.locale == 0 && exists(e.block_index))
+                .or_else(|| entries.iter().find(|e| exists(e.block_index)))?;
+            return Some(entry.block_index);
+        }
+        let (het, bet) = self.het_table.as_ref()?;
+        let (_, index) = het.lookup(name, bet)?;
+        Some(index).filter(|&index| exists(index))
+    }
+
+    fn add_name(&mut self, name: &str) {
+        if pseudo_index(name).is_some() {
+            return;
+        }
+        if !self.hash_table.is_empty() {
+            for entry in NameHash::new(name).lookup(&self.hash_table) {
+                self.names.insert(entry.block_index, name.to_string());
+            }
+        } else if let Some(block_index) = self.locate(name) {
+            self.names.insert(block_index, name.to_string());
+        }
+    }
+
+    /// Adds the names of a listfile, separated by new lines or `;`
+    fn add_names(&mut self, content: &[u8]) {
+        let content = String::from_utf8_lossy(content);
+        for name in content.split(['\r', '\n', ';']) {
+            let name = name.trim();
+            if !name.is_empty() {
+                self.add_name(name);
+            }
+        }
+    }
+
+    fn read_internal(&mut self, name: &str) -> Result<Vec<u8>> {
+        self.open_file(name)?.read_all()
+    }
+
+    fn read_at(&self, pos: u64, size: u64) -> Result<Vec<u8>> {
+        let data = self.read_available(pos, size)?;
+        if (data.len() as u64) < size {
+            return Err(StormError::FileCorrupt);
+        }
+        Ok(data)
+    }
+
+    /// Reads up to `size` bytes, less if the archive ends before
+    fn read_available(&self, pos: u64, size: u64) -> Result<Vec<u8>> {
+        let mut file = &self.file;
+        let mut data = Vec::with_capacity(size as usize);
+        file.seek(SeekFrom::Start(self.header.offset + pos))?;
+        file.take(size).read_to_end(&mut data)?;
+        Ok(data)
+    }
+}
+
+/// Index of the block a `File00000012.xxx` name stands for
+fn pseudo_index(name: &str) -> Option<u32> {
+    let stem = name.strip_prefix("File")?.split('.').next()?;
+    if stem.len() == 8 && stem.bytes().all(|b| b.is_ascii_digit()) {
+        stem.parse().ok()
+    } else {
+        None
+    }
+}
+
+/// Case-insensitive match supporting `*` and `?`, like StormLib's `CheckWildCard`
+fn wildcard_match(mask: &str, name: &str) -> bool {
+    let mask = mask.as_bytes();
+    let name = name.as_bytes();
+    let (mut m, mut n) = (0, 0);
+    let mut backtrack = None;
+    while n < name.len() {
+        if m < mask.len() && mask[m] == b'*' {
+            backtrack = Some((m, n));
+            m += 1;
+        } else if m < mask.len() && (mask[m] == b'?' || mask[m].eq_ignore_ascii_case(&name[n])) {
+            m += 1;
+            n += 1;
+        } else if let Some((star, pos)) = backtrack {
+            m = star + 1;
+            n = pos + 1;
+            backtrack = Some((star, pos + 1));
+        } else {
+            return false;
+        }
+    }
+    mask[m..].iter().all(|&c| c == b'*')
+}
+
+/// Adler-32 as StormLib computes sector checksums, starting from 0 instead of 1
+fn sector_checksum(data: &[u8]) -> u32 {
+    let (mut a, mut b) = (0u32, 0u32);
+    for &byte in data {
+        a = (a + byte as u32) % 65521;
+        b = (b + a) % 65521;
+    }
+    b << 16 | a
+}
+
+/// Opened file
+#[derive(Debug)]
+pub struct File<'a> {
+    archive:  &'a Archive,
+    block:    BlockEntry,
+    name:     Option<String>,
+    /// Sector layout, loaded by the first `read`
+    sectors:  Option<Sectors>,
+    /// Index and content of the sector `read` decoded last
+    sector:   Option<(usize, Vec<u8>)>,
+    position: usize,
+}
+
+/// Where the sectors of a file lie and how to decode them
+#[derive(Debug)]
+struct Sectors {
+    /// Size of every sector but the last one
+    size:      usize,
+    /// Positions relative to the file, one more than there are sectors
+    offsets:   Vec<u32>,
+    key:       Option<u32>,
+    checksums: Option<Vec<u32>>,
+}
+
+impl<'a> File<'a> {
+    /// Retrieves a size of the file within archive
+    pub fn get_size(&mut self) -> Result<u64> {
+        Ok(self.block.file_size as u64)
+    }
+
+    /// Reads all data from the file
+    pub fn read_all(&mut self) -> Result<Vec<u8>> {
+        self.read_sectors(None)
+    }
+
+    /// Reads what can be read of a damaged file: sectors that can't be
+    /// decoded, fail their checksum or lie past the end of the archive are
+    /// filled with zeros and their indices added to `bad_sectors`. Fails if
+    /// the sector offset table is unreadable, or with `NotSupported` if a
+    /// sector uses a compression the native reader can't decode.
+    pub fn read_salvaged(&mut self, bad_sectors: &mut Vec<usize>) -> Result<Vec<u8>> {
+        self.read_sectors(Some(bad_sectors))
+    }
+
+    fn read_sectors(&mut self, mut bad_sectors: Option<&mut Vec<usize>>) -> Result<Vec<u8>> {
+        let block = self.block;
+        if block.flags.contains(FileFlags::PATCH_FILE) {
+            return Err(StormError::NotSupported);
+        }
+        let file_size = self.get_size()? as usize;
+        let compressed = block
+            .flags
+            .intersects(FileFlags::COMPRESS | FileFlags::IMPLODE);
+        let raw = match bad_sectors {
+            Some(_) => self
+                .archive
+                .read_available(block.file_pos, block.compressed_size as u64)?,
+            None => self
+                .archive
+                .read_at(block.file_pos, block.compressed_size as u64)?,
+        };
+
+        let sector_size = self.archive.header.sector_size() as usize;
+        let sector_count = if block.flags.contains(FileFlags::SINGLE_UNIT) {
+            1
+        } else {
+            file_size.div_ceil(sector_size)
+        };
+        if !compressed && !self.encrypted() {
+            let mut data = raw;
+            if data.len() < file_size {
+                let bad_sectors = bad_sectors.ok_or(StormError::FileCorrupt)?;
+                // a stored file cut short keeps the bytes that are there
+                let first_missing = if sector_count == 1 {
+                    0
+                } else {
+                    data.len() / sector_size
+                };
+                bad_sectors.extend(first_missing..sector_count);
+            }
+            data.resize(file_size, 0);
+            return Ok(data);
+        }
+        if block.flags.contains(FileFlags::SINGLE_UNIT) {
+            let mut data = raw;
+            if self.encrypted() {
+                crypto::decrypt_bytes(&mut data, self.key(None)?);
+            }
+            return match (self.decompress_sector(data, file_size), bad_sectors) {
+                (Err(StormError::NotSupported), _) => Err(StormError::NotSupported),
+                (Err(_), Some(bad_sectors)) => {
+                    bad_sectors.push(0);
+                    Ok(vec![0; file_size])
+                }
+                (result, _) => result,
+            };
+        }
+
+        let offsets = if compressed {
+            self.sector_offsets(&raw, sector_count)?
+        } else {
+            (0..=sector_count)
+                .map(|i| (i * sector_size).min(file_size) as u32)
+                .collect()
+        };
+        let key = if self.encrypted() {
+            Some(self.key(Some(&raw))?)
+        } else {
+            None
+        };
+
+        let mut checksums = None;
+        if self.archive.check_sector_crc
+            && block.flags.contains(FileFlags::SECTOR_CRC)
+            && compressed
+        {
+            let (start, end) = (
+                offsets[sector_count] as usize,
+                offsets[sector_count + 1] as usize,
+            );
+            let table = raw.get(start..end).ok_or(StormError::FileCorrupt)?;
+            checksums = self.sector_checksums(table, sector_count)?;
+        }
+
+        let mut data = Vec::with_capacity(file_size);
+        for i in 0..sector_count {
+            let expected_size = sector_size.min(file_size - i * sector_size);
+            let sector = (|| {
+                let (start, end) = (offsets[i] as usize, offsets[i + 1] as usize);
+                let sector = raw.get(start..end).ok_or(StormError::FileCorrupt)?.to_vec();
+                self.decode_sector(sector, i, expected_size, key, checksums.as_deref())
+            })();
+            match (sector, bad_sectors.as_deref_mut()) {
+                (Ok(sector), _) => data.extend(sector),
+                (Err(StormError::NotSupported), _) => return Err(StormError::NotSupported),
+                (Err(_), Some(bad_sectors)) => {
+                    bad_sectors.push(i);
+                    data.resize(data.len() + expected_size, 0);
+                }
+                (Err(e), None) => return Err(e),
+            }
+        }
+        Ok(data)
+    }
+
+    fn encrypted(&self) -> bool {
+        self.block.flags.contains(FileFlags::ENCRYPTED)
+    }
+
+    /// Encryption key of the file, guessed from the sector offset table when the name is unknown
+    fn key(&self, raw: Option<&[u8]>) -> Result<u32> {
+        if let Some(name) = &self.name {
+            return Ok(crypto::file_key(
+                name,
+                self.block.file_pos,
+                self.block.file_size,
+                self.block.flags,
+            ));
+        }
+        let raw = raw.ok_or(StormError::UnknownFileKey)?;
+        let sector_size = self.archive.header.sector_size();
+        let sector_count = (self.block.file_size as usize).div_ceil(sector_size as usize);
+        let table_size = self.sector_table_len(sector_count) as u32 * 4;
+        let dword = |i: usize| {
+            raw.get(i * 4..i * 4 + 4)
+                .map(|b| u32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+        };
+        let encrypted = [
+            dword(0).ok_or(StormError::FileCorrupt)?,
+            dword(1).ok_or(StormError::FileCorrupt)?,
+        ];
+        crypto::detect_key(encrypted, table_size, table_size + sector_size)
+            .map(|key| key.wrapping_add(1))
+            .ok_or(StormError::UnknownFileKey)
+    }
+
+    fn sector_table_len(&self, sector_count: usize) -> usize {
+        if self.block.flags.contains(FileFlags::SECTOR_CRC) {
+            sector_count + 2
+        } else {
+            sector_count + 1
+        }
+    }
+
+    fn sector_offsets(&self, raw: &[u8], sector_count: usize) -> Result<Vec<u32>> {
+        let len = self.sector_table_len(sector_count);
+        let mut table = raw.get(..len * 4).ok_or(StormError::FileCorrupt)?.to_vec();
+        if self.encrypted() {
+            crypto::decrypt_bytes(&mut table, self.key(Some(raw))?.wrapping_sub(1));
+        }
+        let offsets: Vec<u32> = table
+            .chunks_exact(4)
+            .map(|b| u32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+            .collect();
+        let valid = offsets.windows(2).all(|w| w[0] <= w[1])
+            && offsets[sector_count] <= self.block.compressed_size;
+        if !valid {
+            return Err(StormError::FileCorrupt);
+        }
+        Ok(offsets)
+    }
+
+    /// Adler-32 of every sector, stored after the last sector
+    fn sector_checksums(&self, table: &[u8], sector_count: usize) -> Result<Option<Vec<u32>>> {
+        let mut table = table.to_vec();
+        if table.len() < sector_count * 4 {
+            table = compression::decompress(&table, sector_count * 4)?;
+        }
+        if table.len() < sector_count * 4 {
+            return Ok(None);
+        }
+        Ok(Some(
+            table
+                .chunks_exact(4)
+                .map(|b| u32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+                .collect(),
+        ))
+    }
+
+    /// Sector layout for `read`, which only reads the sector offset table
+    /// and the checksums up front
+    fn load_sectors(&self) -> Result<Sectors> {
+        let block = self.block;
+        if block.flags.contains(FileFlags::PATCH_FILE) {
+            return Err(StormError::NotSupported);
+        }
+        let file_size = block.file_size as usize;
+        if block.flags.contains(FileFlags::SINGLE_UNIT) {
+            let key = if self.encrypted() {
+                Some(self.key(None)?)
+            } else {
+                None
+            };
+            return Ok(Sectors {
+                size: file_size,
+                offsets: vec![0, block.compressed_size],
+                key,
+                checksums: None,
+            });
+        }
+
+        let sector_size = self.archive.header.sector_size() as usize;
+        let sector_count = file_size.div_ceil(sector_size);
+        let compressed = block
+            .flags
+            .intersects(FileFlags::COMPRESS | FileFlags::IMPLODE);
+        let head = if compressed || self.encrypted() {
+            let len = (self.sector_table_len(sector_count) * 4).min(block.compressed_size as usize);
+            self.archive.read_at(block.file_pos, len as u64)?
+        } else {
+            Vec::new()
+        };
+        let offsets = if compressed {
+            self.sector_offsets(&head, sector_count)?
+        } else {
+            (0..=sector_count)
+                .map(|i| (i * sector_size).min(file_size) as u32)
+                .collect()
+        };
+        let key = if self.encrypted() {
+            Some(self.key(Some(&head))?)
+        } else {
+            None
+        };
+
+        let mut checksums = None;
+        if self.archive.check_sector_crc
+            && block.flags.contains(FileFlags::SECTOR_CRC)
+            && compressed
+        {
+            let (start, end) = (offsets[sector_count], offsets[sector_count + 1]);
+            let table = self
+                .archive
+                .read_at(block.file_pos + start as u64, (end - start) as u64)?;
+            checksums = self.sector_checksums(&table, sector_count)?;
+        }
+        Ok(Sectors {
+            size: sector_size,
+            offsets,
+            key,
+            checksums,
+        })
+    }
+
+    /// Reads and decodes sector `index` alone
+    fn read_sector(&self, sectors: &Sectors, index: usize) -> Result<Vec<u8>> {
+        let file_size = self.block.file_size as usize;
+        let size = sectors.size.min(file_size - index * sectors.size);
+        let (start, end) = (sectors.offsets[index], sectors.offsets[index + 1]);
+        let sector = self
+            .archive
+            .read_at(self.block.file_pos + start as u64, (end - start) as u64)?;
+        self.decode_sector(
+            sector,
+            index,
+            size,
+            sectors.key,
+            sectors.checksums.as_deref(),
+        )
+    }
+
+    /// Decrypts sector `index`, checks it against its checksum if there is
+    /// one and decompresses it to `size` bytes
+    fn decode_sector(
+        &self,
+        mut sector: Vec<u8>,
+        index: usize,
+        size: usize,
+        key: Option<u32>,
+        checksums: Option<&[u32]>,
+    ) -> Result<Vec<u8>> {
+        if let Some(key) = key {
+            crypto::decrypt_bytes(&mut sector, key.wrapping_add(index as u32));
+        }
+        if let Some(checksums) = checksums {
+            let expected = checksums[index];
+            if expected != 0 && sector_checksum(&sector) != expected {
+                return Err(StormError::ChecksumError);
+            }
+        }
+        self.decompress_sector(sector, size)
+    }
+
+    /// Sectors stored at their full size are not compressed
+    fn decompress_sector(&self, sector: Vec<u8>, size: usize) -> Result<Vec<u8>> {
+        if sector.len() >= size {
+            let mut sector = sector;
+            sector.truncate(size);
+            return Ok(sector);
+        }
+        if self.block.flags.contains(FileFlags::IMPLODE) {
+            compression::explode(&sector, size)
+        } else {
+            compression::decompress(&sector, size)
+        }
+    }
+}
+
+/// Reads continue from the current position, while `read_all` always starts
+/// from the beginning of the file; only the sector at the position is read
+/// and decoded, then kept for the reads that follow
+impl<'a> Read for File<'a> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.position >= self.block.file_size as usize || buf.is_empty() {
+            return Ok(0);
+        }
+        if self.sectors.is_none() {
+            self.sectors = Some(self.load_sectors().map_err(io::Error::other)?);
+        }
+        let sectors = self.sectors.as_ref().unwrap();
+        let index = self.position / sectors.size;
+        if self.sector.as_ref().map(|(i, _)| *i) != Some(index) {
+            let sector = self.read_sector(sectors, index).map_err(io::Error::other)?;
+            self.sector = Some((index, sector));
+        }
+        let size = sectors.size;
+        let sector = self.sector.as_ref().map(|(_, s)| s.as_slice()).unwrap();
+        let rest = &sector[(self.position - index * size).min(sector.len())..];
+        if rest.is_empty() {
+            return Err(io::Error::other(StormError::FileCorrupt));
+        }
+        let len = rest.len().min(buf.len());
+        buf[..len].copy_from_slice(&rest[..len]);
+        self.position += len;
+        Ok(len)
+    }
+}
+
+/// File being written; archives are read-only in the native backend, so
+/// `Archive::create_file` never returns one
+#[derive(Debug)]
+pub struct FileWriter<'a> {
+    archive: PhantomData<&'a Archive>,
+}
+
+impl<'a> FileWriter<'a> {
+    pub fn finish(self) -> Result<()> {
+        Err(StormError::NotSupported)
+    }
+}
+
+impl<'a> Write for FileWriter<'a> {
+    fn write(&mut self, _buf: &[u8]) -> io::Result<usize> {
+        Err(io::Error::other(StormError::NotSupported))
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+#[test]
+fn test_wildcard_match() {
+    assert!(wildcard_match("*", "war3map.j"));
+    assert!(wildcard_match("*.J", "war3map.j"));
+    assert!(wildcard_match("war3map.?", "war3map.j"));
+    assert!(wildcard_match(
+        "*imported\\*.mdx",
+        "war3mapImported\\fx.mdx"
+    ));
+    assert!(!wildcard_match("*.mdx", "war3mapImported\\fx.mdl"));
+    assert!(!wildcard_match("war3map.?", "war3map.lua"));
+}
+
+#[test]
+fn test_pseudo_index() {
+    assert_eq!(pseudo_index("File00000012.xxx"), Some(12));
+    assert_eq!(pseudo_index("File00000012.mdx"), Some(12));
+    assert_eq!(pseudo_index("war3map.j"), None);
+}
+
+#[test]
+fn test_read_stream() {
+    use super::testing::{garble_sector, TempDir};
+
+    let dir = TempDir::new("read-stream");
+    let mut builder = writer::ArchiveBuilder::new();
+    let content: Vec<u8> = (0..10_000u32).map(|i| (i % 251) as u8).collect();
+    builder.add("war3map.j", content.clone(), FileOptions::default());
+    let path = dir.join("stream.mpq");
+    builder.write(&path).unwrap();
+
+    let mut archive = Archive::open(&path, OpenArchiveFlags::MPQ_OPEN_NO_FLAG).unwrap();
+    let mut file = archive.open_file("war3map.j").unwrap();
+    let mut head = [0u8; 100];
+    file.read_exact(&mut head).unwrap();
+    assert_eq!(&head[..], &content[..100]);
+    // only the first sector is decoded so far
+    assert_eq!(
+        file.sector.as_ref().map(|(i, s)| (*i, s.len())),
+        Some((0, 0x1000))
+    );
+    let mut rest = Vec::new();
+    file.read_to_end(&mut rest).unwrap();
+    assert_eq!(rest, &content[100..]);
+    assert_eq!(
+        file.sector.as_ref().map(|(i, s)| (*i, s.len())),
+        Some((2, 10_000 - 0x2000))
+    );
+    assert_eq!(file.read_all().unwrap(), content);
+    drop(archive);
+
+    // a damaged sector only fails the reads that reach it
+    garble_sector(&path, "war3map.j", 2);
+    let mut archive = Archive::open(&path, OpenArchiveFlags::MPQ_OPEN_NO_FLAG).unwrap();
+    let mut file = archive.open_file("war3map.j").unwrap();
+    let mut head = vec![0u8; 0x2000];
+    file.read_exact(&mut head).unwrap();
+    assert_eq!(head, &content[..0x2000]);
+    assert!(file.read_to_end(&mut Vec::new()).is_err());
+}
+
+#[test]
+fn test_read_salvaged() {
+    use super::testing::{garble_sector, TempDir};
+
+    let dir = TempDir::new("read-salvaged");
+    let mut builder = writer::ArchiveBuilder::new();
+    let content: Vec<u8> = (0..10_000u32).map(|i| (i % 251) as u8).collect();
+    builder.add("war3map.j", content.clone(), FileOptions::default());
+    let path = dir.join("salvaged.mpq");
+    builder.write(&path).unwrap();
+    garble_sector(&path, "war3map.j", 1);
+
+    let mut archive = Archive::open(&path, OpenArchiveFlags::MPQ_OPEN_NO_FLAG).unwrap();
+    assert!(archive.open_file("war3map.j").unwrap().read_all().is_err());
+    let mut bad_sectors = Vec::new();
+    let data = archive
+        .open_file("war3map.j")
+        .unwrap()
+        .read_salvaged(&mut bad_sectors)
+        .unwrap();
+    assert_eq!(bad_sectors, vec![1]);
+    assert_eq!(data.len(), content.len());
+    assert_eq!(&data[..4096], &content[..4096]);
+    assert!(data[4096..8192].iter().all(|&b| b == 0));
+    assert_eq!(&data[8192..], &content[8192..]);
+    drop(archive);
+
+    // a stored file cut by the end of the archive keeps what is left of it
+    let stored = FileOptions {
+        compression: crate::Compression::None,
+        ..FileOptions::default()
+    };
+    let mut builder = writer::ArchiveBuilder::new();
+    builder.add("war3map.w3e", content.clone(), stored);
+    builder.write(&path).unwrap();
+    let mut archive = Archive::open(&path, OpenArchiveFlags::MPQ_OPEN_NO_FLAG).unwrap();
+    let block = archive.blocks[archive.locate("war3map.w3e").unwrap() as usize];
+    let cut = archive.header.offset + block.file_pos + 5000;
+    fs::OpenOptions::new()
+        .write(true)
+        .open(&path)
+        .unwrap()
+        .set_len(cut)
+        .unwrap();
+    assert!(archive
+        .open_file("war3map.w3e")
+        .unwrap()
+        .read_all()
+        .is_err());
+    let mut bad_sectors = Vec::new();
+    let data = archive
+        .open_file("war3map.w3e")
+        .unwrap()
+        .read_salvaged(&mut bad_sectors)
+        .unwrap();
+    assert_eq!(bad_sectors, vec![1, 2]);
+    assert_eq!(data.len(), content.len());
+    assert_eq!(&data[..5000], &content[..5000]);
+    assert!(data[5000..].iter().all(|&b| b == 0));
+}
+
+#[test]
+fn test_open_garbled_sector_size() {
+    use super::testing::TempDir;
+
+    let dir = TempDir::new("garbled-sector-size");
+    let mut builder = writer::ArchiveBuilder::new();
+    let content: Vec<u8> = (0..10_000u32).map(|i| (i % 251) as u8).collect();
+    builder.add("war3map.j", content.clone(), FileOptions::default());
+    let path = dir.join("garbled.mpq");
+    builder.write(&path).unwrap();
+
+    for &shift in &[23u16, 40] {
+        let mut bytes = fs::read(&path).unwrap();
+        bytes[14..16].copy_from_slice(&shift.to_le_bytes());
+        fs::write(&path, &bytes).unwrap();
+        let mut archive = Archive::open(&path, OpenArchiveFlags::MPQ_OPEN_NO_FLAG).unwrap();
+        assert!(archive.header.malformed);
+        assert_eq!(archive.header.sector_size(), 0x1000);
+        let data = archive.open_file("war3map.j").unwrap().read_all().unwrap();
+        assert_eq!(data, content);
+    }
+}
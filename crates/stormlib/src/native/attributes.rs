@@ -0,0 +1,114 @@
+use std::convert::TryInto;
+
+use super::sys::*;
+
+/// Content of the `(attributes)` file, one value per block table entry
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Attributes {
+    pub crc32:      Vec<u32>,
+    pub file_times: Vec<u64>,
+    pub md5:        Vec<[u8; 16]>,
+}
+
+impl Attributes {
+    /// Parses `(attributes)` of an archive with `block_count` blocks
+    ///
+    /// Older tools wrote one entry less than the block table has, which is
+    /// accepted as well.
+    pub fn parse(data: &[u8], block_count: usize) -> Option<Self> {
+        let version = u32::from_le_bytes(data.get(0..4)?.try_into().unwrap());
+        let flags = u32::from_le_bytes(data.get(4..8)?.try_into().unwrap());
+        if version != MPQ_ATTRIBUTES_V1 {
+            return None;
+        }
+
+        let mut rest = &data[8..];
+        let entry_size = [
+            (MPQ_ATTRIBUTE_CRC32, 4),
+            (MPQ_ATTRIBUTE_FILETIME, 8),
+            (MPQ_ATTRIBUTE_MD5, 16),
+        ]
+        .iter()
+        .filter(|(flag, _)| flags & flag != 0)
+        .map(|(_, size)| size)
+        .sum::<usize>();
+        let count = if rest.len() >= block_count * entry_size {
+            block_count
+        } else if rest.len() >= block_count.saturating_sub(1) * entry_size {
+            block_count - 1
+        } else {
+            return None;
+        };
+
+        let mut attributes = Attributes::default();
+        let mut take = |item_size: usize| {
+            let (items, tail) = rest.split_at(count * item_size);
+            rest = tail;
+            items
+        };
+        if flags & MPQ_ATTRIBUTE_CRC32 != 0 {
+            attributes.crc32 = take(4)
+                .chunks_exact(4)
+                .map(|b| u32::from_le_bytes(b.try_into().unwrap()))
+                .collect();
+        }
+        if flags & MPQ_ATTRIBUTE_FILETIME != 0 {
+            attributes.file_times = take(8)
+                .chunks_exact(8)
+                .map(|b| u64::from_le_bytes(b.try_into().unwrap()))
+                .collect();
+        }
+        if flags & MPQ_ATTRIBUTE_MD5 != 0 {
+            attributes.md5 = take(16)
+                .chunks_exact(16)
+                .map(|b| b.try_into().unwrap())
+                .collect();
+        }
+        Some(attributes)
+    }
+
+    /// Serializes the attributes, storing only the arrays that are filled in
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut flags = 0;
+        let mut data = Vec::new();
+        if !self.crc32.is_empty() {
+            flags |= MPQ_ATTRIBUTE_CRC32;
+            self.crc32
+                .iter()
+                .for_each(|v| data.extend_from_slice(&v.to_le_bytes()));
+        }
+        if !self.file_times.is_empty() {
+            flags |= MPQ_ATTRIBUTE_FILETIME;
+            self.file_times
+                .iter()
+                .for_each(|v| data.extend_from_slice(&v.to_le_bytes()));
+        }
+        if !self.md5.is_empty() {
+            flags |= MPQ_ATTRIBUTE_MD5;
+            self.md5.iter().for_each(|v| data.extend_from_slice(v));
+        }
+        let mut bytes = Vec::with_capacity(8 + data.len());
+        bytes.extend_from_slice(&MPQ_ATTRIBUTES_V1.to_le_bytes());
+        bytes.extend_from_slice(&flags.to_le_bytes());
+        bytes.extend(data);
+        bytes
+    }
+}
+
+#[test]
+fn test_parse_attributes() {
+    let mut data = Vec::new();
+    data.extend_from_slice(&MPQ_ATTRIBUTES_V1.to_le_bytes());
+    data.extend_from_slice(&(MPQ_ATTRIBUTE_CRC32 | MPQ_ATTRIBUTE_FILETIME).to_le_bytes());
+    data.extend_from_slice(&[1, 0, 0, 0, 2, 0, 0, 0]);
+    data.extend_from_slice(&7u64.to_le_bytes());
+    data.extend_from_slice(&8u64.to_le_bytes());
+
+    let attributes = Attributes::parse(&data, 2).unwrap();
+    assert_eq!(attributes.crc32, vec![1, 2]);
+    assert_eq!(attributes.file_times, vec![7, 8]);
+    assert!(attributes.md5.is_empty());
+    assert_eq!(attributes.to_bytes(), data);
+    assert_eq!(Attributes::parse(&data[4..], 2), None);
+    assert_eq!(Attributes::parse(&data, 5), None);
+}
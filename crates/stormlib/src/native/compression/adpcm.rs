@@ -0,0 +1,104 @@
+//! Decoder of `MPQ_COMPRESSION_ADPCM_MONO` and `MPQ_COMPRESSION_ADPCM_STEREO`, a
+//! port of StormLib's `adpcm.cpp`
+
+use crate::error::*;
+
+const INITIAL_STEP_INDEX: usize = 0x2C;
+
+const NEXT_STEP: [i32; 32] = [
+    -1, 0, -1, 4, -1, 2, -1, 6, -1, 1, -1, 5, -1, 3, -1, 7, -1, 1, -1, 5, -1, 3, -1, 7, -1, 2, -1,
+    4, -1, 6, -1, 8,
+];
+
+const STEP_SIZES: [i32; 89] = [
+    7, 8, 9, 10, 11, 12, 13, 14, 16, 17, 19, 21, 23, 25, 28, 31, 34, 37, 41, 45, 50, 55, 60, 66,
+    73, 80, 88, 97, 107, 118, 130, 143, 157, 173, 190, 209, 230, 253, 279, 307, 337, 371, 408, 449,
+    494, 544, 598, 658, 724, 796, 876, 963, 1060, 1166, 1282, 1411, 1552, 1707, 1878, 2066, 2272,
+    2499, 2749, 3024, 3327, 3660, 4026, 4428, 4871, 5358, 5894, 6484, 7132, 7845, 8630, 9493,
+    10442, 11487, 12635, 13899, 15289, 16818, 18500, 20350, 22385, 24623, 27086, 29794, 32767,
+];
+
+pub fn decompress_mono(data: &[u8], out_size: usize) -> Result<Vec<u8>> {
+    decompress(data, out_size, 1)
+}
+
+pub fn decompress_stereo(data: &[u8], out_size: usize) -> Result<Vec<u8>> {
+    decompress(data, out_size, 2)
+}
+
+fn decode_sample(predicted: i32, encoded: u8, step: i32, shift: u32) -> i32 {
+    let mut difference = step >> shift;
+    for bit in 0..6 {
+        if encoded & (1 << bit) != 0 {
+            difference += step >> bit;
+        }
+    }
+    if encoded & 0x40 != 0 {
+        (predicted - difference).max(-32768)
+    } else {
+        (predicted + difference).min(32767)
+    }
+}
+
+fn decompress(data: &[u8], out_size: usize, channels: usize) -> Result<Vec<u8>> {
+    if data.len() < 2 + channels * 2 {
+        return Err(StormError::FileCorrupt);
+    }
+    let shift = data[1] as u32;
+    let mut out = Vec::with_capacity(out_size);
+    let mut predicted = [0i32; 2];
+    let mut step_index = [INITIAL_STEP_INDEX; 2];
+    for ch in 0..channels {
+        let sample = i16::from_le_bytes([data[2 + ch * 2], data[3 + ch * 2]]);
+        predicted[ch] = sample as i32;
+        out.extend_from_slice(&sample.to_le_bytes());
+    }
+
+    let mut ch = channels - 1;
+    for &encoded in &data[2 + channels * 2..] {
+        if out.len() + 2 > out_size {
+            break;
+        }
+        ch = (ch + 1) % channels;
+        if encoded & 0x80 != 0 {
+            match encoded & 0x7F {
+                0 => {
+                    step_index[ch] = step_index[ch].saturating_sub(1);
+                    out.extend_from_slice(&(predicted[ch] as i16).to_le_bytes());
+                }
+                1 => {
+                    step_index[ch] = (step_index[ch] + 8).min(STEP_SIZES.len() - 1);
+                    ch = (ch + 1) % channels;
+                }
+                2 => ch = (ch + 1) % channels,
+                _ => {
+                    step_index[ch] = step_index[ch].saturating_sub(8);
+                    ch = (ch + 1) % channels;
+                }
+            }
+        } else {
+            let step = STEP_SIZES[step_index[ch]];
+            predicted[ch] = decode_sample(predicted[ch], encoded, step, shift);
+            out.extend_from_slice(&(predicted[ch] as i16).to_le_bytes());
+            let next = step_index[ch] as i32 + NEXT_STEP[(encoded & 0x1F) as usize];
+            step_index[ch] = next.clamp(0, STEP_SIZES.len() as i32 - 1) as usize;
+        }
+    }
+    Ok(out)
+}
+
+#[test]
+fn test_adpcm_mono() {
+    // initial sample 100, one step up by the full step size, one repeat
+    let data = [0, 0, 100, 0, 0x01, 0x80];
+    let out = decompress_mono(&data, 6).unwrap();
+    let samples: Vec<i16> = out
+        .chunks_exact(2)
+        .map(|b| i16::from_le_bytes([b[0], b[1]]))
+        .collect();
+    let step = STEP_SIZES[INITIAL_STEP_INDEX];
+    assert_eq!(
+        samples,
+        vec![100, (100 + step + step) as i16, (100 + step + step) as i16]
+    );
+}
@@ -0,0 +1,619 @@
+//! Decoder of `MPQ_COMPRESSION_HUFFMANN`, a port of StormLib's `huff.cpp`
+//!
+//! The stream starts with a byte selecting a table of byte weights the tree
+//! is built from. Bytes missing from the table are sent as an escape code
+//! followed by the byte itself and then added to the tree; with type 0 the
+//! weight of every decoded byte is incremented too, so the tree adapts to the
+//! data. The codes are read from the low bit of each byte up. Warcraft III
+//! only uses it on top of ADPCM, with types 6 to 8; the other types come from
+//! older Blizzard games and StormLib's own compressor.
+
+use crate::error::*;
+
+/// Most items a tree can have: 0x102 leaves and their parents
+const MAX_ITEMS: usize = 0x203;
+
+const END_OF_STREAM: u16 = 0x100;
+const ESCAPE: u16 = 0x101;
+
+/// Weights of every byte, by compression type, StormLib's `ByteToWeight_00`
+/// to `ByteToWeight_08`; `test_huffman_matches_stormlib` decodes what
+/// StormLib encodes with each of them
+const WEIGHTS: [&[u8; 256]; 9] = [
+    &WEIGHTS_0, &WEIGHTS_1, &WEIGHTS_2, &WEIGHTS_3, &WEIGHTS_4, &WEIGHTS_5, &WEIGHTS_6, &WEIGHTS_7,
+    &WEIGHTS_8,
+];
+
+/// Every byte weighs the same, the tree adapts to the data instead
+const WEIGHTS_0: [u8; 256] = [0x0A; 256];
+
+const WEIGHTS_1: [u8; 256] = [
+    0x54, 0x16, 0x16, 0x0D, 0x0C, 0x08, 0x06, 0x05, 0x06, 0x05, 0x06, 0x03, 0x04, 0x04, 0x03, 0x05,
+    0x0E, 0x0B, 0x14, 0x13, 0x13, 0x09, 0x0B, 0x06, 0x05, 0x04, 0x03, 0x02, 0x03, 0x02, 0x02, 0x02,
+    0x0D, 0x07, 0x09, 0x06, 0x06, 0x04, 0x03, 0x02, 0x04, 0x03, 0x03, 0x03, 0x03, 0x03, 0x02, 0x02,
+    0x09, 0x06, 0x04, 0x04, 0x04, 0x04, 0x03, 0x02, 0x03, 0x02, 0x02, 0x02, 0x02, 0x03, 0x02, 0x04,
+    0x08, 0x03, 0x04, 0x07, 0x09, 0x05, 0x03, 0x03, 0x03, 0x03, 0x02, 0x02, 0x02, 0x03, 0x02, 0x02,
+    0x03, 0x02, 0x02, 0x02, 0x02, 0x02, 0x02, 0x02, 0x02, 0x01, 0x01, 0x01, 0x02, 0x01, 0x02, 0x02,
+    0x06, 0x0A, 0x08, 0x08, 0x06, 0x07, 0x04, 0x03, 0x04, 0x04, 0x02, 0x02, 0x04, 0x02, 0x03, 0x03,
+    0x04, 0x03, 0x07, 0x07, 0x09, 0x06, 0x04, 0x03, 0x03, 0x02, 0x01, 0x02, 0x02, 0x02, 0x02, 0x02,
+    0x0A, 0x02, 0x02, 0x03, 0x02, 0x02, 0x01, 0x01, 0x02, 0x02, 0x02, 0x06, 0x03, 0x05, 0x02, 0x03,
+    0x02, 0x01, 0x01, 0x01, 0x01, 0x01, 0x01, 0x01, 0x01, 0x01, 0x01, 0x01, 0x01, 0x01, 0x01, 0x01,
+    0x02, 0x01, 0x01, 0x01, 0x01, 0x01, 0x01, 0x01, 0x01, 0x01, 0x01, 0x01, 0x01, 0x01, 0x01, 0x01,
+    0x02, 0x01, 0x01, 0x01, 0x01, 0x01, 0x01, 0x01, 0x01, 0x01, 0x01, 0x01, 0x01, 0x01, 0x01, 0x01,
+    0x02, 0x01, 0x01, 0x01, 0x01, 0x01, 0x01, 0x01, 0x01, 0x01, 0x01, 0x01, 0x01, 0x01, 0x01, 0x01,
+    0x02, 0x01, 0x01, 0x01, 0x01, 0x01, 0x01, 0x01, 0x01, 0x01, 0x01, 0x01, 0x01, 0x01, 0x01, 0x01,
+    0x02, 0x01, 0x01, 0x01, 0x01, 0x01, 0x01, 0x01, 0x01, 0x01, 0x01, 0x01, 0x01, 0x01, 0x01, 0x01,
+    0x04, 0x01, 0x01, 0x01, 0x01, 0x01, 0x01, 0x01, 0x01, 0x01, 0x01, 0x01, 0x01, 0x02, 0x02, 0x1B,
+];
+
+const WEIGHTS_2: [u8; 256] = [
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x17, 0x00, 0x00, 0x17, 0x00, 0x00,
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+    0xFF, 0x0B, 0x07, 0x05, 0x0B, 0x02, 0x02, 0x02, 0x06, 0x02, 0x02, 0x01, 0x04, 0x02, 0x01, 0x03,
+    0x09, 0x01, 0x01, 0x01, 0x03, 0x04, 0x01, 0x01, 0x02, 0x01, 0x01, 0x01, 0x02, 0x01, 0x01, 0x01,
+    0x05, 0x01, 0x01, 0x01, 0x0D, 0x01, 0x01, 0x01, 0x01, 0x01, 0x01, 0x01, 0x02, 0x01, 0x01, 0x01,
+    0x0A, 0x01, 0x01, 0x01, 0x01, 0x01, 0x01, 0x01, 0x01, 0x01, 0x01, 0x01, 0x01, 0x01, 0x01, 0x01,
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+];
+
+const WEIGHTS_3: [u8; 256] = [
+    0xFF, 0x0B, 0x07, 0x05, 0x0B, 0x02, 0x02, 0x02, 0x06, 0x02, 0x02, 0x01, 0x04, 0x02, 0x01, 0x03,
+    0x09, 0x01, 0x01, 0x01, 0x03, 0x04, 0x01, 0x01, 0x02, 0x01, 0x01, 0x01, 0x02, 0x01, 0x01, 0x01,
+    0x05, 0x01, 0x01, 0x01, 0x0D, 0x01, 0x01, 0x01, 0x01, 0x01, 0x01, 0x01, 0x02, 0x01, 0x01, 0x01,
+    0x0A, 0x01, 0x01, 0x01, 0x01, 0x01, 0x01, 0x01, 0x01, 0x01, 0x01, 0x01, 0x01, 0x01, 0x01, 0x01,
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+];
+
+const WEIGHTS_4: [u8; 256] = [
+    0xFF, 0xFB, 0x98, 0x9A, 0x84, 0x85, 0x63, 0x64, 0x3E, 0x3E, 0x22, 0x22, 0x13, 0x13, 0x18, 0x17,
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+];
+
+const WEIGHTS_5: [u8; 256] = [
+    0xFF, 0xF1, 0x9D, 0x9E, 0x9A, 0x9B, 0x9A, 0x97, 0x93, 0x93, 0x8C, 0x8E, 0x86, 0x88, 0x80, 0x82,
+    0x7C, 0x7C, 0x72, 0x73, 0x69, 0x6B, 0x5F, 0x60, 0x55, 0x56, 0x4A, 0x4B, 0x40, 0x41, 0x37, 0x37,
+    0x2F, 0x2F, 0x27, 0x27, 0x21, 0x21, 0x1B, 0x1C, 0x17, 0x17, 0x13, 0x13, 0x10, 0x10, 0x0D, 0x0D,
+    0x0B, 0x0B, 0x09, 0x09, 0x08, 0x08, 0x07, 0x07, 0x06, 0x05, 0x05, 0x04, 0x04, 0x04, 0x19, 0x18,
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+];
+
+/// ADPCM with compression level 1 or 2
+const WEIGHTS_6: [u8; 256] = [
+    0xFF, 0x0B, 0x07, 0x05, 0x0B, 0x02, 0x02, 0x02, 0x06, 0x02, 0x02, 0x01, 0x04, 0x02, 0x01, 0x03,
+    0x09, 0x01, 0x01, 0x01, 0x03, 0x04, 0x01, 0x01, 0x02, 0x01, 0x01, 0x01, 0x02, 0x01, 0x01, 0x01,
+    0x05, 0x01, 0x01, 0x01, 0x0D, 0x01, 0x01, 0x01, 0x01, 0x01, 0x01, 0x01, 0x02, 0x01, 0x01, 0x01,
+    0x0A, 0x01, 0x01, 0x01, 0x01, 0x01, 0x01, 0x01, 0x01, 0x01, 0x01, 0x01, 0x01, 0x01, 0x01, 0x01,
+    0xFF, 0x0B, 0x07, 0x05, 0x0B, 0x02, 0x02, 0x02, 0x06, 0x02, 0x02, 0x01, 0x04, 0x02, 0x01, 0x03,
+    0x09, 0x01, 0x01, 0x01, 0x03, 0x04, 0x01, 0x01, 0x02, 0x01, 0x01, 0x01, 0x02, 0x01, 0x01, 0x01,
+    0x05, 0x01, 0x01, 0x01, 0x0D, 0x01, 0x01, 0x01, 0x01, 0x01, 0x01, 0x01, 0x02, 0x01, 0x01, 0x01,
+    0x0A, 0x01, 0x01, 0x01, 0x01, 0x01, 0x01, 0x01, 0x01, 0x01, 0x01, 0x01, 0x01, 0x01, 0x01, 0x01,
+    0x40, 0x08, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+];
+
+/// ADPCM with the default compression level
+const WEIGHTS_7: [u8; 256] = [
+    0xFF, 0xFB, 0x98, 0x9A, 0x84, 0x85, 0x63, 0x64, 0x3E, 0x3E, 0x22, 0x22, 0x13, 0x13, 0x18, 0x17,
+    0x10, 0x10, 0x0E, 0x0E, 0x0C, 0x0C, 0x0A, 0x0A, 0x09, 0x09, 0x08, 0x08, 0x07, 0x07, 0x06, 0x06,
+    0x05, 0x05, 0x05, 0x04, 0x04, 0x04, 0x03, 0x03, 0x03, 0x03, 0x02, 0x02, 0x02, 0x02, 0x02, 0x02,
+    0x02, 0x02, 0x01, 0x01, 0x01, 0x01, 0x01, 0x01, 0x01, 0x01, 0x01, 0x01, 0x01, 0x01, 0x01, 0x01,
+    0xFF, 0xFB, 0x98, 0x9A, 0x84, 0x85, 0x63, 0x64, 0x3E, 0x3E, 0x22, 0x22, 0x13, 0x13, 0x18, 0x17,
+    0x10, 0x10, 0x0E, 0x0E, 0x0C, 0x0C, 0x0A, 0x0A, 0x09, 0x09, 0x08, 0x08, 0x07, 0x07, 0x06, 0x06,
+    0x05, 0x05, 0x05, 0x04, 0x04, 0x04, 0x03, 0x03, 0x03, 0x03, 0x02, 0x02, 0x02, 0x02, 0x02, 0x02,
+    0x02, 0x02, 0x01, 0x01, 0x01, 0x01, 0x01, 0x01, 0x01, 0x01, 0x01, 0x01, 0x01, 0x01, 0x01, 0x01,
+    0x40, 0x08, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+];
+
+/// ADPCM with compression level 3
+const WEIGHTS_8: [u8; 256] = [
+    0xFF, 0xF1, 0x9D, 0x9E, 0x9A, 0x9B, 0x9A, 0x97, 0x93, 0x93, 0x8C, 0x8E, 0x86, 0x88, 0x80, 0x82,
+    0x7C, 0x7C, 0x72, 0x73, 0x69, 0x6B, 0x5F, 0x60, 0x55, 0x56, 0x4A, 0x4B, 0x40, 0x41, 0x37, 0x37,
+    0x2F, 0x2F, 0x27, 0x27, 0x21, 0x21, 0x1B, 0x1C, 0x17, 0x17, 0x13, 0x13, 0x10, 0x10, 0x0D, 0x0D,
+    0x0B, 0x0B, 0x09, 0x09, 0x08, 0x08, 0x07, 0x07, 0x06, 0x05, 0x05, 0x04, 0x04, 0x04, 0x19, 0x18,
+    0xFF, 0xF1, 0x9D, 0x9E, 0x9A, 0x9B, 0x9A, 0x97, 0x93, 0x93, 0x8C, 0x8E, 0x86, 0x88, 0x80, 0x82,
+    0x7C, 0x7C, 0x72, 0x73, 0x69, 0x6B, 0x5F, 0x60, 0x55, 0x56, 0x4A, 0x4B, 0x40, 0x41, 0x37, 0x37,
+    0x2F, 0x2F, 0x27, 0x27, 0x21, 0x21, 0x1B, 0x1C, 0x17, 0x17, 0x13, 0x13, 0x10, 0x10, 0x0D, 0x0D,
+    0x0B, 0x0B, 0x09, 0x09, 0x08, 0x08, 0x07, 0x07, 0x06, 0x05, 0x05, 0x04, 0x04, 0x04, 0x19, 0x18,
+    0x40, 0x08, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+];
+
+/// Index of the list head, which isn't an item of the tree
+const HEAD: usize = 0;
+
+/// Marks an item that isn't linked into the list
+const UNLINKED: usize = usize::MAX;
+
+#[derive(Debug, Clone, Copy)]
+struct Item {
+    next:     usize,
+    prev:     usize,
+    parent:   Option<usize>,
+    /// Child with the lower weight, the other one is the item before it
+    child_lo: Option<usize>,
+    value:    u16,
+    weight:   u32,
+}
+
+/// Tree whose items are also kept in a list sorted by weight, heaviest first
+struct Tree {
+    items:    Vec<Item>,
+    by_value: [Option<usize>; 0x102],
+    adaptive: bool,
+}
+
+impl Tree {
+    fn new(compression_type: u8) -> Result<Tree> {
+        let weights = WEIGHTS
+            .get(compression_type as usize)
+            .copied()
+            .ok_or(StormError::NotSupported)?;
+        let head = Item {
+            next:     HEAD,
+            prev:     HEAD,
+            parent:   None,
+            child_lo: None,
+            value:    0,
+            weight:   0,
+        };
+        let mut tree = Tree {
+            items:    vec![head],
+            by_value: [None; 0x102],
+            adaptive: compression_type == 0,
+        };
+
+        let mut max_weight = 0;
+        for (byte, &weight) in weights.iter().enumerate() {
+            if weight != 0 {
+                let item = tree.create(byte as u16, weight as u32, false)?;
+                tree.by_value[byte] = Some(item);
+                max_weight = tree.place_by_weight(item, max_weight);
+            }
+        }
+        tree.by_value[END_OF_STREAM as usize] = Some(tree.create(END_OF_STREAM, 1, true)?);
+        tree.by_value[ESCAPE as usize] = Some(tree.create(ESCAPE, 1, true)?);
+
+        // pair the two lightest items until only the root is left
+        let mut child_lo = tree.last();
+        while child_lo != HEAD {
+            let child_hi = tree.items[child_lo].prev;
+            if child_hi == HEAD {
+                break;
+            }
+            let weight = tree.items[child_hi].weight + tree.items[child_lo].weight;
+            let parent = tree.create(0, weight, false)?;
+            tree.items[child_lo].parent = Some(parent);
+            tree.items[child_hi].parent = Some(parent);
+            tree.items[parent].child_lo = Some(child_lo);
+            max_weight = tree.place_by_weight(parent, max_weight);
+            child_lo = tree.items[child_hi].prev;
+        }
+        Ok(tree)
+    }
+
+    fn first(&self) -> usize {
+        self.items[HEAD].next
+    }
+
+    fn last(&self) -> usize {
+        self.items[HEAD].prev
+    }
+
+    fn unlink(&mut self, item: usize) {
+        let Item { next, prev, .. } = self.items[item];
+        if next != UNLINKED {
+            self.items[prev].next = next;
+            self.items[next].prev = prev;
+            self.items[item].next = UNLINKED;
+            self.items[item].prev = UNLINKED;
+        }
+    }
+
+    fn link_after(&mut self, at: usize, item: usize) {
+        let next = self.items[at].next;
+        self.items[item].next = next;
+        self.items[item].prev = at;
+        self.items[next].prev = item;
+        self.items[at].next = item;
+    }
+
+    /// Adds an item at the front of the list, or at its end
+    fn create(&mut self, value: u16, weight: u32, at_end: bool) -> Result<usize> {
+        if self.items.len() > MAX_ITEMS {
+            return Err(StormError::FileCorrupt);
+        }
+        let item = self.items.len();
+        self.items.push(Item {
+            next: UNLINKED,
+            prev: UNLINKED,
+            parent: None,
+            child_lo: None,
+            value,
+            weight,
+        });
+        let at = if at_end { self.last() } else { HEAD };
+        self.link_after(at, item);
+        Ok(item)
+    }
+
+    /// Item at or before `from` weighing at least `weight`, or the head
+    fn find_heavier(&self, from: usize, weight: u32) -> usize {
+        let mut item = from;
+        while item != HEAD {
+            if self.items[item].weight >= weight {
+                return item;
+            }
+            item = self.items[item].prev;
+        }
+        HEAD
+    }
+
+    /// Moves an item created at the front behind the items weighing as much or
+    /// more, returning the weight of the heaviest item
+    fn place_by_weight(&mut self, item: usize, max_weight: u32) -> u32 {
+        let weight = self.items[item].weight;
+        if weight < max_weight {
+            let heavier = self.find_heavier(self.last(), weight);
+            self.unlink(item);
+            self.link_after(heavier, item);
+            max_weight
+        } else {
+            weight
+        }
+    }
+
+    /// Increments the weight of an item and its parents, swapping items that
+    /// became heavier than the ones before them
+    fn increment(&mut self, item: usize) -> Result<()> {
+        let mut item = Some(item);
+        while let Some(current) = item {
+            self.items[current].weight += 1;
+            let weight = self.items[current].weight;
+            let heavier = self.find_heavier(self.items[current].prev, weight);
+            let swapped = self.items[heavier].next;
+            if swapped != current {
+                self.unlink(swapped);
+                self.link_after(current, swapped);
+                self.unlink(current);
+                self.link_after(heavier, current);
+
+                let (parent, swapped_parent) =
+                    match (self.items[current].parent, self.items[swapped].parent) {
+                        (Some(parent), Some(swapped_parent)) => (parent, swapped_parent),
+                        _ => return Err(StormError::FileCorrupt),
+                    };
+                let swapped_sibling = self.items[swapped_parent].child_lo;
+                if self.items[parent].child_lo == Some(current) {
+                    self.items[parent].child_lo = Some(swapped);
+                }
+                if swapped_sibling == Some(swapped) {
+                    self.items[swapped_parent].child_lo = Some(current);
+                }
+                self.items[current].parent = Some(swapped_parent);
+                self.items[swapped].parent = Some(parent);
+            }
+            item = self.items[current].parent;
+        }
+        Ok(())
+    }
+
+    /// Adds a byte that had no item: the lightest leaf becomes the parent of
+    /// itself and of the new byte
+    fn insert(&mut self, byte: u8) -> Result<()> {
+        let last = self.last();
+        let Item { value, weight, .. } = self.items[last];
+        let child_hi = self.create(value, weight, true)?;
+        self.items[child_hi].parent = Some(last);
+        self.by_value[value as usize] = Some(child_hi);
+
+        let child_lo = self.create(byte as u16, 0, true)?;
+        self.items[child_lo].parent = Some(last);
+        self.items[last].child_lo = Some(child_lo);
+        self.by_value[byte as usize] = Some(child_lo);
+        self.increment(child_lo)
+    }
+
+    /// Item of a byte once it was decoded or encoded
+    fn byte_item(&self, byte: u8) -> Result<usize> {
+        self.by_value[byte as usize].ok_or(StormError::FileCorrupt)
+    }
+
+    fn decode(&self, input: &mut BitReader) -> Result<u16> {
+        let mut item = self.first();
+        if item == HEAD {
+            return Err(StormError::FileCorrupt);
+        }
+        while let Some(child_lo) = self.items[item].child_lo {
+            item = if input.read_bit()? {
+                self.items[child_lo].prev
+            } else {
+                child_lo
+            };
+        }
+        Ok(self.items[item].value)
+    }
+
+    #[cfg(test)]
+    fn encode(&self, output: &mut BitWriter, value: u16) -> Result<()> {
+        let mut item = self.by_value[value as usize].ok_or(StormError::FileCorrupt)?;
+        let mut bits = Vec::new();
+        while let Some(parent) = self.items[item].parent {
+            bits.push(self.items[parent].child_lo != Some(item));
+            item = parent;
+        }
+        for bit in bits.into_iter().rev() {
+            output.write_bit(bit);
+        }
+        Ok(())
+    }
+}
+
+/// Reads bits from the low bit of each byte up
+struct BitReader<'a> {
+    data:   &'a [u8],
+    pos:    usize,
+    buffer: u32,
+    count:  u32,
+}
+
+impl BitReader<'_> {
+    fn next_byte(&mut self) -> Result<u32> {
+        let byte = *self.data.get(self.pos).ok_or(StormError::FileCorrupt)?;
+        self.pos += 1;
+        Ok(byte as u32)
+    }
+
+    fn read_bit(&mut self) -> Result<bool> {
+        if self.count == 0 {
+            self.buffer = self.next_byte()?;
+            self.count = 8;
+        }
+        let bit = self.buffer & 1 != 0;
+        self.buffer >>= 1;
+        self.count -= 1;
+        Ok(bit)
+    }
+
+    fn read_byte(&mut self) -> Result<u8> {
+        if self.count < 8 {
+            self.buffer |= self.next_byte()? << self.count;
+            self.count += 8;
+        }
+        let byte = self.buffer as u8;
+        self.buffer >>= 8;
+        self.count -= 8;
+        Ok(byte)
+    }
+}
+
+#[cfg(test)]
+#[derive(Default)]
+struct BitWriter {
+    out:   Vec<u8>,
+    count: u32,
+}
+
+#[cfg(test)]
+impl BitWriter {
+    fn write_bit(&mut self, bit: bool) {
+        if self.count.is_multiple_of(8) {
+            self.out.push(0);
+        }
+        if bit {
+            *self.out.last_mut().unwrap() |= 1 << (self.count % 8);
+        }
+        self.count += 1;
+    }
+
+    fn write_byte(&mut self, byte: u8) {
+        for bit in 0..8 {
+            self.write_bit(byte & (1 << bit) != 0);
+        }
+    }
+}
+
+/// Decodes at most `out_size` bytes, up to the end mark; like StormLib, the
+/// bytes after it are ignored
+pub fn decompress(data: &[u8], out_size: usize) -> Result<Vec<u8>> {
+    let mut input = BitReader {
+        data,
+        pos: 0,
+        buffer: 0,
+        count: 0,
+    };
+    let mut tree = Tree::new(input.read_byte()?)?;
+    let mut out = Vec::with_capacity(out_size);
+    while out.len() < out_size {
+        let byte = match tree.decode(&mut input)? {
+            END_OF_STREAM => break,
+            ESCAPE => {
+                let byte = input.read_byte()?;
+                tree.insert(byte)?;
+                if !tree.adaptive {
+                    tree.increment(tree.byte_item(byte)?)?;
+                }
+                byte
+            }
+            value => value as u8,
+        };
+        out.push(byte);
+        if tree.adaptive && out.len() < out_size {
+            tree.increment(tree.byte_item(byte)?)?;
+        }
+    }
+    Ok(out)
+}
+
+/// Encoder matching `decompress`, StormLib's `THuffmannTree::Compress`
+#[cfg(test)]
+fn compress(data: &[u8], compression_type: u8) -> Result<Vec<u8>> {
+    let mut tree = Tree::new(compression_type)?;
+    let mut output = BitWriter::default();
+    output.write_byte(compression_type);
+    for &byte in data {
+        match tree.by_value[byte as usize] {
+            Some(_) => {
+                tree.encode(&mut output, byte as u16)?;
+                if tree.adaptive {
+                    tree.increment(tree.byte_item(byte)?)?;
+                }
+            }
+            None => {
+                tree.encode(&mut output, ESCAPE)?;
+                output.write_byte(byte);
+                tree.insert(byte)?;
+                tree.increment(tree.byte_item(byte)?)?;
+            }
+        }
+    }
+    tree.encode(&mut output, END_OF_STREAM)?;
+    Ok(output.out)
+}
+
+#[test]
+fn test_huffman() {
+    use crate::native::sys::*;
+
+    let text = b"call CreateUnit(Player(0), 'hfoo', 0.0, 0.0, 270.0)\r\n".repeat(40);
+    for compression_type in 0..=8 {
+        let packed = compress(&text, compression_type).unwrap();
+        assert_eq!(packed[0], compression_type);
+        assert_eq!(decompress(&packed, 0x10000).unwrap(), text);
+        // output full before the end mark
+        assert_eq!(decompress(&packed, 100).unwrap(), &text[..100]);
+    }
+    assert!(compress(&text, 0).unwrap().len() < text.len() * 4 / 5);
+    assert!(matches!(
+        decompress(&[9, 0], 10),
+        Err(StormError::NotSupported)
+    ));
+
+    // a sector of a mono sound: ADPCM packed by Huffman with the default level
+    let mut adpcm = vec![0u8, 5, 0x10, 0x02];
+    for i in 0..2000u32 {
+        let magnitude = [0, 1, 1, 2, 3, 1, 0, 4][(i % 8) as usize];
+        let sign = if (i / 16) % 2 == 0 { 0 } else { 0x40 };
+        adpcm.push(magnitude | sign);
+        if i % 500 == 499 {
+            adpcm.push(0x80);
+        }
+    }
+    // the first sample, then one per code
+    let out_size = 2 * (adpcm.len() - 3);
+    let mut sector = vec![MPQ_COMPRESSION_ADPCM_MONO | MPQ_COMPRESSION_HUFFMANN];
+    sector.extend(compress(&adpcm, 7).unwrap());
+    assert!(sector.len() < adpcm.len());
+    let samples = super::decompress(&sector, out_size).unwrap();
+    assert_eq!(
+        samples,
+        super::adpcm::decompress_mono(&adpcm, out_size).unwrap()
+    );
+    assert_eq!(samples.len(), out_size);
+
+    // StormLib ignores what follows the end mark
+    let mut packed = compress(&adpcm, 7).unwrap();
+    packed.extend_from_slice(&[0xAA, 0x55]);
+    assert_eq!(decompress(&packed, out_size).unwrap(), adpcm);
+}
+
+#[cfg(not(feature = "pure-rust"))]
+#[test]
+fn test_huffman_matches_stormlib() {
+    use crate::native::sys::*;
+    use std::os::raw::{c_int, c_void};
+
+    let text = b"call CreateUnit(Player(0), 'hfoo', 0.0, 0.0, 270.0)\r\n".repeat(40);
+    let adpcm: Vec<u8> = (0..4000u32)
+        .map(|i| {
+            let sign = if (i / 16) % 2 == 0 { 0 } else { 0x40 };
+            [0, 1, 1, 2, 3, 1, 0, 4][(i % 8) as usize] | sign
+        })
+        .collect();
+    for &data in &[&text, &adpcm] {
+        for compression_type in 0..=8 {
+            let mut input = data.clone();
+            let mut sector = vec![0u8; data.len() * 2];
+            let mut size = sector.len() as c_int;
+            let compressed = unsafe {
+                stormlib_sys::SCompCompress(
+                    sector.as_mut_ptr() as *mut c_void,
+                    &mut size,
+                    input.as_mut_ptr() as *mut c_void,
+                    input.len() as c_int,
+                    MPQ_COMPRESSION_HUFFMANN as u32,
+                    compression_type,
+                    0,
+                )
+            };
+            assert_ne!(compressed, 0);
+            sector.truncate(size as usize);
+            assert_eq!(sector[0], MPQ_COMPRESSION_HUFFMANN);
+            assert_eq!(sector[1] as c_int, compression_type);
+            assert_eq!(decompress(&sector[1..], data.len()).unwrap(), *data);
+        }
+    }
+}
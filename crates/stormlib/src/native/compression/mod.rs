@@ -0,0 +1,175 @@
+//! Sector compression, mirroring StormLib's `SCompCompress`/`SCompDecompress`
+//!
+//! A compressed sector starts with a byte holding the mask of the methods
+//! that were applied; they are undone in the reverse order of compression.
+
+use std::io::{Read, Write};
+
+use super::sys::*;
+use crate::error::*;
+
+mod adpcm;
+mod huffman;
+mod pkware;
+mod sparse;
+
+pub use pkware::explode;
+
+/// Compression applied to the sectors of a file
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Compression {
+    None,
+    Zlib,
+    Bzip2,
+    /// Lossy compression for WAVE files, only available through StormLib
+    AdpcmMono,
+    AdpcmStereo,
+}
+
+impl Compression {
+    /// StormLib compression mask, as passed to `SFileAddFileEx`
+    pub fn mask(self) -> u8 {
+        match self {
+            Compression::None => 0,
+            Compression::Zlib => MPQ_COMPRESSION_ZLIB,
+            Compression::Bzip2 => MPQ_COMPRESSION_BZIP2,
+            Compression::AdpcmMono => MPQ_COMPRESSION_ADPCM_MONO | MPQ_COMPRESSION_HUFFMANN,
+            Compression::AdpcmStereo => MPQ_COMPRESSION_ADPCM_STEREO | MPQ_COMPRESSION_HUFFMANN,
+        }
+    }
+}
+
+/// Compresses one unit of data, keeping it as is when compression doesn't help
+pub fn compress(data: &[u8], compression: Compression) -> Result<Vec<u8>> {
+    let compressed = match compression {
+        Compression::None => return Ok(data.to_vec()),
+        Compression::Zlib => {
+            let mut encoder = flate2::write::ZlibEncoder::new(
+                vec![MPQ_COMPRESSION_ZLIB],
+                flate2::Compression::default(),
+            );
+            encoder.write_all(data)?;
+            encoder.finish()?
+        }
+        Compression::Bzip2 => {
+            let mut encoder = bzip2::write::BzEncoder::new(
+                vec![MPQ_COMPRESSION_BZIP2],
+                bzip2::Compression::best(),
+            );
+            encoder.write_all(data)?;
+            encoder.finish()?
+        }
+        Compression::AdpcmMono | Compression::AdpcmStereo => return Err(StormError::NotSupported),
+    };
+    if compressed.len() < data.len() {
+        Ok(compressed)
+    } else {
+        Ok(data.to_vec())
+    }
+}
+
+type Decompressor = fn(&[u8], usize) -> Result<Vec<u8>>;
+
+/// Decompression steps, in the order they are undone
+const DECOMPRESSORS: &[(u8, Decompressor)] = &[
+    (MPQ_COMPRESSION_BZIP2, decompress_bzip2),
+    (MPQ_COMPRESSION_PKWARE, explode),
+    (MPQ_COMPRESSION_ZLIB, decompress_zlib),
+    (MPQ_COMPRESSION_HUFFMANN, huffman::decompress),
+    (MPQ_COMPRESSION_ADPCM_STEREO, adpcm::decompress_stereo),
+    (MPQ_COMPRESSION_ADPCM_MONO, adpcm::decompress_mono),
+    (MPQ_COMPRESSION_SPARSE, sparse::decompress),
+];
+
+/// Decompresses a sector compressed with `MPQ_FILE_COMPRESS` to `out_size` bytes
+pub fn decompress(data: &[u8], out_size: usize) -> Result<Vec<u8>> {
+    let (&mask, data) = data.split_first().ok_or(StormError::FileCorrupt)?;
+    if mask == MPQ_COMPRESSION_LZMA {
+        return decompress_lzma(data, out_size);
+    }
+    let known = DECOMPRESSORS
+        .iter()
+        .fold(0, |known, (method, _)| known | method);
+    if mask & !known != 0 {
+        return Err(StormError::NotSupported);
+    }
+
+    let mut buf = data.to_vec();
+    for (method, decompressor) in DECOMPRESSORS {
+        if mask & method != 0 {
+            buf = decompressor(&buf, out_size)?;
+        }
+    }
+    Ok(buf)
+}
+
+fn read_limited<R: Read>(reader: R, out_size: usize) -> Result<Vec<u8>> {
+    let mut out = Vec::with_capacity(out_size);
+    reader
+        .take(out_size as u64)
+        .read_to_end(&mut out)
+        .map_err(|_| StormError::FileCorrupt)?;
+    Ok(out)
+}
+
+fn decompress_zlib(data: &[u8], out_size: usize) -> Result<Vec<u8>> {
+    read_limited(flate2::read::ZlibDecoder::new(data), out_size)
+}
+
+fn decompress_bzip2(data: &[u8], out_size: usize) -> Result<Vec<u8>> {
+    read_limited(bzip2::read::BzDecoder::new(data), out_size)
+}
+
+/// LZMA sectors hold a filter byte and the 5 property bytes, but no size
+fn decompress_lzma(data: &[u8], out_size: usize) -> Result<Vec<u8>> {
+    use lzma_rs::decompress::{Options, UnpackedSize};
+    match data.split_first() {
+        Some((0, stream)) => {
+            let mut out = Vec::with_capacity(out_size);
+            let options = Options {
+                unpacked_size: UnpackedSize::UseProvided(Some(out_size as u64)),
+                ..Default::default()
+            };
+            lzma_rs::lzma_decompress_with_options(&mut &stream[..], &mut out, &options)
+                .map_err(|_| StormError::FileCorrupt)?;
+            Ok(out)
+        }
+        _ => Err(StormError::NotSupported),
+    }
+}
+
+#[test]
+fn test_decompress_chain() {
+    use std::io::Write;
+    let text = b"war3map.j war3map.j war3map.j war3map.j".to_vec();
+
+    let mut encoder = flate2::write::ZlibEncoder::new(Vec::new(), flate2::Compression::best());
+    encoder.write_all(&text).unwrap();
+    let mut sector = vec![MPQ_COMPRESSION_ZLIB];
+    sector.extend(encoder.finish().unwrap());
+    assert_eq!(decompress(&sector, text.len()).unwrap(), text);
+
+    let mut encoder = bzip2::write::BzEncoder::new(Vec::new(), bzip2::Compression::best());
+    encoder.write_all(&text).unwrap();
+    let mut sector = vec![MPQ_COMPRESSION_BZIP2];
+    sector.extend(encoder.finish().unwrap());
+    assert_eq!(decompress(&sector, text.len()).unwrap(), text);
+
+    let mut sector = vec![MPQ_COMPRESSION_LZMA, 0];
+    let mut lzma = Vec::new();
+    lzma_rs::lzma_compress_with_options(
+        &mut &text[..],
+        &mut lzma,
+        &lzma_rs::compress::Options {
+            unpacked_size: lzma_rs::compress::UnpackedSize::SkipWritingToHeader,
+        },
+    )
+    .unwrap();
+    sector.extend(lzma);
+    assert_eq!(decompress(&sector, text.len()).unwrap(), text);
+
+    assert!(matches!(
+        decompress(&[0x04, 0], 1),
+        Err(StormError::NotSupported)
+    ));
+}
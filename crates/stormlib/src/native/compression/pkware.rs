@@ -0,0 +1,166 @@
+//! PKWARE Data Compression Library "implode" decoder, a port of zlib's `blast.c`
+
+use crate::error::*;
+
+const MAX_BITS: usize = 13;
+
+/// Bit lengths of the literal codes, in `blast.c`'s compact repeat form
+const LITERAL_LENGTHS: &[u8] = &[
+    11, 124, 8, 7, 28, 7, 188, 13, 76, 4, 10, 8, 12, 10, 12, 10, 8, 23, 8, 9, 7, 6, 7, 8, 7, 6, 55,
+    8, 23, 24, 12, 11, 7, 9, 11, 12, 6, 7, 22, 5, 7, 24, 6, 11, 9, 6, 7, 22, 7, 11, 38, 7, 9, 8,
+    25, 11, 8, 11, 9, 12, 8, 12, 5, 38, 5, 38, 5, 11, 7, 5, 6, 21, 6, 10, 53, 8, 7, 24, 10, 27, 44,
+    253, 253, 253, 252, 252, 252, 13, 12, 45, 12, 45, 12, 61, 12, 45, 44, 173,
+];
+const LENGTH_LENGTHS: &[u8] = &[2, 35, 36, 53, 38, 23];
+const DISTANCE_LENGTHS: &[u8] = &[2, 20, 53, 230, 247, 151, 248];
+const LENGTH_BASE: [u16; 16] = [3, 2, 4, 5, 6, 7, 8, 9, 10, 12, 16, 24, 40, 72, 136, 264];
+const LENGTH_EXTRA: [u8; 16] = [0, 0, 0, 0, 0, 0, 0, 0, 1, 2, 3, 4, 5, 6, 7, 8];
+
+/// Canonical Huffman code: number of codes per length and symbols ordered by code
+struct Huffman {
+    count:  [u16; MAX_BITS + 1],
+    symbol: Vec<u16>,
+}
+
+impl Huffman {
+    /// Builds a code from the compact representation, also returning the number of
+    /// unused codes so tests can check the tables are complete
+    fn construct(rep: &[u8]) -> (Self, i32) {
+        let mut lengths = Vec::new();
+        for &byte in rep {
+            let repeat = (byte >> 4) as usize + 1;
+            lengths.extend(std::iter::repeat_n(byte & 15, repeat));
+        }
+
+        let mut count = [0u16; MAX_BITS + 1];
+        for &len in &lengths {
+            count[len as usize] += 1;
+        }
+        let mut left: i32 = 1;
+        for &n in &count[1..] {
+            left = (left << 1) - n as i32;
+        }
+
+        let mut offsets = [0u16; MAX_BITS + 1];
+        for len in 1..MAX_BITS {
+            offsets[len + 1] = offsets[len] + count[len];
+        }
+        let mut symbol = vec![0u16; lengths.len()];
+        for (sym, &len) in lengths.iter().enumerate() {
+            if len != 0 {
+                symbol[offsets[len as usize] as usize] = sym as u16;
+                offsets[len as usize] += 1;
+            }
+        }
+        (Huffman { count, symbol }, left)
+    }
+}
+
+struct BitReader<'a> {
+    data:    &'a [u8],
+    pos:     usize,
+    bit_buf: u32,
+    bit_cnt: u32,
+}
+
+impl<'a> BitReader<'a> {
+    fn bits(&mut self, need: u32) -> Result<u32> {
+        while self.bit_cnt < need {
+            let byte = *self.data.get(self.pos).ok_or(StormError::FileCorrupt)?;
+            self.pos += 1;
+            self.bit_buf |= (byte as u32) << self.bit_cnt;
+            self.bit_cnt += 8;
+        }
+        let value = self.bit_buf & ((1u32 << need) - 1);
+        self.bit_buf >>= need;
+        self.bit_cnt -= need;
+        Ok(value)
+    }
+
+    /// Decodes a symbol, the codes are stored bit-inverted
+    fn decode(&mut self, h: &Huffman) -> Result<u16> {
+        let (mut code, mut first, mut index) = (0i32, 0i32, 0i32);
+        for len in 1..=MAX_BITS {
+            code |= (self.bits(1)? ^ 1) as i32;
+            let count = h.count[len] as i32;
+            if code < first + count {
+                return Ok(h.symbol[(index + code - first) as usize]);
+            }
+            index += count;
+            first = (first + count) << 1;
+            code <<= 1;
+        }
+        Err(StormError::FileCorrupt)
+    }
+}
+
+/// Decompresses data compressed with `MPQ_COMPRESSION_PKWARE` or stored with `MPQ_FILE_IMPLODE`
+pub fn explode(data: &[u8], out_size: usize) -> Result<Vec<u8>> {
+    let (literals, _) = Huffman::construct(LITERAL_LENGTHS);
+    let (lengths, _) = Huffman::construct(LENGTH_LENGTHS);
+    let (distances, _) = Huffman::construct(DISTANCE_LENGTHS);
+
+    let mut s = BitReader {
+        data,
+        pos: 0,
+        bit_buf: 0,
+        bit_cnt: 0,
+    };
+    let coded_literals = match s.bits(8)? {
+        0 => false,
+        1 => true,
+        _ => return Err(StormError::FileCorrupt),
+    };
+    let dict = s.bits(8)?;
+    if !(4..=6).contains(&dict) {
+        return Err(StormError::FileCorrupt);
+    }
+
+    let mut out = Vec::with_capacity(out_size);
+    while out.len() < out_size {
+        if s.bits(1)? == 1 {
+            let symbol = s.decode(&lengths)? as usize;
+            let len = LENGTH_BASE[symbol] as usize + s.bits(LENGTH_EXTRA[symbol] as u32)? as usize;
+            if len == 519 {
+                break;
+            }
+            let shift = if len == 2 { 2 } else { dict };
+            let dist = ((s.decode(&distances)? as usize) << shift) + s.bits(shift)? as usize + 1;
+            if dist > out.len() {
+                return Err(StormError::FileCorrupt);
+            }
+            for _ in 0..len.min(out_size - out.len()) {
+                out.push(out[out.len() - dist]);
+            }
+        } else {
+            let literal = if coded_literals {
+                s.decode(&literals)? as u8
+            } else {
+                s.bits(8)? as u8
+            };
+            out.push(literal);
+        }
+    }
+    Ok(out)
+}
+
+#[test]
+fn test_tables_complete() {
+    for (rep, symbols) in &[
+        (LITERAL_LENGTHS, 256),
+        (LENGTH_LENGTHS, 16),
+        (DISTANCE_LENGTHS, 64),
+    ] {
+        let (h, left) = Huffman::construct(rep);
+        assert_eq!(left, 0);
+        assert_eq!(h.symbol.len(), *symbols);
+    }
+}
+
+#[test]
+fn test_explode() {
+    // test vector from blast.c
+    let data = [0x00, 0x04, 0x82, 0x24, 0x25, 0x8f, 0x80, 0x7f];
+    assert_eq!(explode(&data, 13).unwrap(), b"AIAIAIAIAIAIA");
+    assert_eq!(explode(&data, 5).unwrap(), b"AIAIA");
+}
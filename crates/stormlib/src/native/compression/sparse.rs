@@ -0,0 +1,39 @@
+//! Decoder of `MPQ_COMPRESSION_SPARSE`, a run-length encoding of zeros
+
+use crate::error::*;
+
+pub fn decompress(data: &[u8], out_size: usize) -> Result<Vec<u8>> {
+    if data.len() < 4 {
+        return Err(StormError::FileCorrupt);
+    }
+    let size = u32::from_be_bytes([data[0], data[1], data[2], data[3]]) as usize;
+    if size == 0 || size > out_size {
+        return Err(StormError::FileCorrupt);
+    }
+
+    let mut out = Vec::with_capacity(size);
+    let mut input = data[4..].iter();
+    while let Some(&byte) = input.next() {
+        let room = size - out.len();
+        if byte & 0x80 != 0 {
+            let len = ((byte & 0x7F) as usize + 1).min(room);
+            let chunk = input.as_slice().get(..len).ok_or(StormError::FileCorrupt)?;
+            out.extend_from_slice(chunk);
+            input.nth(len - 1);
+        } else {
+            let len = ((byte & 0x7F) as usize + 3).min(room);
+            out.resize(out.len() + len, 0);
+        }
+        if out.len() == size {
+            break;
+        }
+    }
+    Ok(out)
+}
+
+#[test]
+fn test_sparse() {
+    let data = [0, 0, 0, 8, 0x81, b'a', b'b', 0x00, 0x80, b'c', 0x7F];
+    assert_eq!(decompress(&data, 8).unwrap(), b"ab\0\0\0c\0\0");
+    assert!(decompress(&[0, 0, 0, 0, 0x80, b'a'], 8).is_err());
+}
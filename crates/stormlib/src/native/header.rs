@@ -0,0 +1,267 @@
+use std::convert::TryInto;
+use std::io::{Read, Seek, SeekFrom};
+
+use super::sys::*;
+use crate::error::*;
+use crate::{OpenArchiveFlags, UserData};
+
+/// Largest sector size shift that fits the 32-bit sector size
+pub const MAX_SECTOR_SIZE_SHIFT: u16 = 22;
+/// Sector size shift of the World Editor, 4 KiB sectors
+pub const DEFAULT_SECTOR_SIZE_SHIFT: u16 = 3;
+
+/// MPQ header, with the fields of every format version widened to 64 bits
+///
+/// Table positions are relative to the header, `offset` is where the header
+/// itself sits in the file.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct MpqHeader {
+    pub offset:                u64,
+    pub header_size:           u32,
+    pub archive_size:          u64,
+    pub format_version:        u16,
+    pub sector_size_shift:     u16,
+    pub hash_table_pos:        u64,
+    pub block_table_pos:       u64,
+    pub hash_table_size:       u32,
+    pub block_table_size:      u32,
+    pub hi_block_table_pos:    u64,
+    pub het_table_pos:         u64,
+    pub bet_table_pos:         u64,
+    /// Compressed table sizes, only stored by v4 headers
+    pub hash_table_size64:     u64,
+    pub block_table_size64:    u64,
+    pub hi_block_table_size64: u64,
+    pub het_table_size64:      u64,
+    pub bet_table_size64:      u64,
+    pub raw_chunk_size:        u32,
+    /// Whether the header had to be repaired, e.g. by map protectors
+    pub malformed:             bool,
+}
+
+impl MpqHeader {
+    /// Size of a sector, in bytes
+    ///
+    /// A shift too big for the sector size, which is only found in damaged
+    /// headers, gives the default 4 KiB sectors.
+    pub fn sector_size(&self) -> u32 {
+        0x200u32
+            .checked_shl(self.sector_size_shift.into())
+            .filter(|&size| size != 0)
+            .unwrap_or(0x200 << DEFAULT_SECTOR_SIZE_SHIFT)
+    }
+
+    /// Parses a header, `bytes` must start at the `MPQ\x1A` signature
+    ///
+    /// Like StormLib, unknown versions and inconsistent sizes are read as a v1
+    /// header, which is what Warcraft III itself does.
+    pub fn parse(bytes: &[u8], offset: u64, force_v1: bool) -> Option<Self> {
+        let dword = |pos: usize| {
+            bytes
+                .get(pos..pos + 4)
+                .map(|b| u32::from_le_bytes(b.try_into().unwrap()))
+        };
+        let word = |pos: usize| {
+            bytes
+                .get(pos..pos + 2)
+                .map(|b| u16::from_le_bytes([b[0], b[1]]))
+        };
+        let qword = |pos: usize| {
+            bytes
+                .get(pos..pos + 8)
+                .map(|b| u64::from_le_bytes(b.try_into().unwrap()))
+        };
+
+        if dword(0)? != ID_MPQ || dword(4)? < MPQ_HEADER_SIZE_V1 {
+            return None;
+        }
+        let mut header = MpqHeader {
+            offset,
+            header_size: dword(4)?,
+            archive_size: dword(8)? as u64,
+            format_version: word(12)?,
+            sector_size_shift: word(14)?,
+            hash_table_pos: dword(16)? as u64,
+            block_table_pos: dword(20)? as u64,
+            hash_table_size: dword(24)?,
+            block_table_size: dword(28)?,
+            ..Default::default()
+        };
+        header.malformed = header.sector_size_shift > MAX_SECTOR_SIZE_SHIFT;
+
+        let version = header.format_version;
+        let size = header.header_size;
+        let as_v1 = force_v1
+            || version == MPQ_FORMAT_VERSION_1
+            || version > MPQ_FORMAT_VERSION_4
+            || size < MPQ_HEADER_SIZE_V2
+            || (version == MPQ_FORMAT_VERSION_4 && size < MPQ_HEADER_SIZE_V4);
+        if as_v1 {
+            header.malformed |= version != MPQ_FORMAT_VERSION_1 || size != MPQ_HEADER_SIZE_V1;
+            header.format_version = MPQ_FORMAT_VERSION_1;
+            header.header_size = MPQ_HEADER_SIZE_V1;
+            return Some(header);
+        }
+
+        header.hi_block_table_pos = qword(0x20)?;
+        header.hash_table_pos |= (word(0x28)? as u64) << 32;
+        header.block_table_pos |= (word(0x2A)? as u64) << 32;
+        if version >= MPQ_FORMAT_VERSION_3 && size >= MPQ_HEADER_SIZE_V3 {
+            header.archive_size = qword(0x2C)?;
+            header.bet_table_pos = qword(0x34)?;
+            header.het_table_pos = qword(0x3C)?;
+        }
+        if version >= MPQ_FORMAT_VERSION_4 {
+            header.hash_table_size64 = qword(0x44)?;
+            header.block_table_size64 = qword(0x4C)?;
+            header.hi_block_table_size64 = qword(0x54)?;
+            header.het_table_size64 = qword(0x5C)?;
+            header.bet_table_size64 = qword(0x64)?;
+            header.raw_chunk_size = dword(0x6C)?;
+        }
+        Some(header)
+    }
+
+    /// Serializes the header in the v1 layout, the only one the writer produces
+    pub fn to_bytes_v1(&self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(MPQ_HEADER_SIZE_V1 as usize);
+        bytes.extend_from_slice(&ID_MPQ.to_le_bytes());
+        bytes.extend_from_slice(&MPQ_HEADER_SIZE_V1.to_le_bytes());
+        bytes.extend_from_slice(&(self.archive_size as u32).to_le_bytes());
+        bytes.extend_from_slice(&MPQ_FORMAT_VERSION_1.to_le_bytes());
+        bytes.extend_from_slice(&self.sector_size_shift.to_le_bytes());
+        bytes.extend_from_slice(&(self.hash_table_pos as u32).to_le_bytes());
+        bytes.extend_from_slice(&(self.block_table_pos as u32).to_le_bytes());
+        bytes.extend_from_slice(&self.hash_table_size.to_le_bytes());
+        bytes.extend_from_slice(&self.block_table_size.to_le_bytes());
+        bytes
+    }
+
+    /// Searches a file for the MPQ header at every 512-byte boundary, following
+    /// a user data block if one comes first
+    pub fn find<R: Read + Seek>(
+        reader: &mut R,
+        flags: OpenArchiveFlags,
+    ) -> Result<(MpqHeader, Option<UserData>)> {
+        let file_size = reader.seek(SeekFrom::End(0))?;
+        let mut user_data = None;
+        let mut force_v1 = flags.contains(OpenArchiveFlags::MPQ_OPEN_FORCE_MPQ_V1);
+        let mut offset = 0;
+        while offset < file_size {
+            reader.seek(SeekFrom::Start(offset))?;
+            let mut buf = Vec::with_capacity(MPQ_HEADER_SIZE_V4 as usize);
+            reader
+                .by_ref()
+                .take(MPQ_HEADER_SIZE_V4 as u64)
+                .read_to_end(&mut buf)?;
+            let id = buf
+                .get(..4)
+                .map(|b| u32::from_le_bytes(b.try_into().unwrap()));
+
+            // Warcraft III only ever reads v1 headers, protectors rely on it
+            if offset == 0 && id == Some(ID_W3M_HEADER) {
+                force_v1 = true;
+            }
+            if id == Some(ID_MPQ_USERDATA) && user_data.is_none() && !force_v1 {
+                if let Some(found) = read_user_data(reader, offset, file_size)? {
+                    offset += found.header_offset as u64;
+                    user_data = Some(found);
+                    continue;
+                }
+            }
+            if let Some(header) = MpqHeader::parse(&buf, offset, force_v1) {
+                return Ok((header, user_data));
+            }
+            if flags.contains(OpenArchiveFlags::MPQ_OPEN_NO_HEADER_SEARCH) {
+                break;
+            }
+            offset += 0x200;
+        }
+        Err(StormError::BadFormat)
+    }
+}
+
+fn read_user_data<R: Read + Seek>(
+    reader: &mut R,
+    offset: u64,
+    file_size: u64,
+) -> Result<Option<UserData>> {
+    let mut fixed = [0u8; crate::USER_DATA_HEADER_SIZE as usize];
+    reader.seek(SeekFrom::Start(offset))?;
+    reader.read_exact(&mut fixed)?;
+    let user_data_size = u32::from_le_bytes(fixed[4..8].try_into().unwrap());
+    let header_offset = u32::from_le_bytes(fixed[8..12].try_into().unwrap());
+    let content_size = u32::from_le_bytes(fixed[12..16].try_into().unwrap());
+    if content_size > user_data_size
+        || user_data_size > header_offset
+        || offset + header_offset as u64 >= file_size
+    {
+        return Ok(None);
+    }
+
+    let mut content = vec![0u8; content_size as usize];
+    reader.read_exact(&mut content)?;
+    Ok(UserData::parse(&[&fixed[..], &content].concat()))
+}
+
+#[test]
+fn test_find_header() {
+    use std::io::Cursor;
+
+    let mut v1 = vec![0u8; 0x200];
+    v1.extend_from_slice(&ID_MPQ.to_le_bytes());
+    v1.extend_from_slice(&0x20u32.to_le_bytes());
+    v1.extend_from_slice(&0x40u32.to_le_bytes());
+    v1.extend_from_slice(&[0, 0, 3, 0]);
+    v1.extend_from_slice(&[0x20, 0, 0, 0, 0x30, 0, 0, 0, 1, 0, 0, 0, 0, 0, 0, 0]);
+    v1.resize(0x300, 0);
+    v1[..4].copy_from_slice(b"HM3W");
+
+    let (header, user_data) =
+        MpqHeader::find(&mut Cursor::new(&v1), OpenArchiveFlags::MPQ_OPEN_NO_FLAG).unwrap();
+    assert_eq!(header.offset, 0x200);
+    assert_eq!(header.sector_size(), 0x1000);
+    assert_eq!(header.hash_table_pos, 0x20);
+    assert_eq!(header.hash_table_size, 1);
+    assert!(!header.malformed);
+    assert!(user_data.is_none());
+
+    // protected maps claim an unknown version, Warcraft III reads them as v1 anyway
+    v1[0x20C] = 7;
+    let (header, _) =
+        MpqHeader::find(&mut Cursor::new(&v1), OpenArchiveFlags::MPQ_OPEN_NO_FLAG).unwrap();
+    assert_eq!(header.format_version, MPQ_FORMAT_VERSION_1);
+    assert!(header.malformed);
+
+    // shifts overflowing the sector size fall back to 4 KiB sectors
+    for &shift in &[23u8, 40] {
+        let mut garbled = v1.clone();
+        garbled[0x20C] = 0;
+        garbled[0x20E] = shift;
+        let (header, _) = MpqHeader::find(
+            &mut Cursor::new(&garbled),
+            OpenArchiveFlags::MPQ_OPEN_NO_FLAG,
+        )
+        .unwrap();
+        assert_eq!(header.sector_size(), 0x1000);
+        assert!(header.malformed);
+    }
+
+    assert!(matches!(
+        MpqHeader::find(
+            &mut Cursor::new(&v1),
+            OpenArchiveFlags::MPQ_OPEN_NO_HEADER_SEARCH
+        ),
+        Err(StormError::BadFormat)
+    ));
+
+    let mut with_user_data = UserData::new(b"launcher").to_bytes();
+    with_user_data.extend_from_slice(&v1[0x200..]);
+    let (header, user_data) = MpqHeader::find(
+        &mut Cursor::new(&with_user_data),
+        OpenArchiveFlags::MPQ_OPEN_NO_FLAG,
+    )
+    .unwrap();
+    assert_eq!(header.offset, 0x200);
+    assert_eq!(user_data.unwrap().content, b"launcher");
+}
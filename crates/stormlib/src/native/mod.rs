@@ -0,0 +1,37 @@
+//! MPQ reader written in Rust, without StormLib
+//!
+//! Always available, and used as the crate's `Archive`/`File` when the
+//! `pure-rust` feature is enabled. It reads MPQ v1 to v4 archives, including
+//! HET/BET tables, encrypted and compressed files, but cannot modify them;
+//! `ArchiveBuilder` writes new v1 archives reproducibly instead.
+//!
+//! With `pure-rust`, the methods of `Archive` changing an archive in place
+//! (`create`, `add_file`, `add_file_with`, `write_file`, `create_file`,
+//! `remove_file`, `remove_file_locale`, `remove_matching`, `import_from`,
+//! `compact` and `rebuild`) fail with `StormError::NotSupported`. Tools built
+//! on them, like the `pack`, `rm`, `put`, `watch` and `generate --base`
+//! commands of MopaqPack, compile but fail at runtime; only the reading and
+//! `ArchiveBuilder` based ones work.
+
+pub mod sys;
+
+mod header;
+pub use header::{MpqHeader, DEFAULT_SECTOR_SIZE_SHIFT, MAX_SECTOR_SIZE_SHIFT};
+
+mod tables;
+pub use tables::BlockEntry;
+
+pub mod compression;
+
+mod attributes;
+pub use attributes::Attributes;
+
+mod archive;
+pub use archive::{Archive, File, FileWriter};
+
+mod writer;
+pub use writer::{ArchiveBuilder, FileOptions, RawFile};
+pub use compression::Compression;
+
+#[cfg(any(test, feature = "testing"))]
+pub mod testing;
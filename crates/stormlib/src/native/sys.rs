@@ -0,0 +1,99 @@
+//! Subset of the `StormLib.h` constants, mirrored so the native backend
+//! builds without stormlib-sys
+
+pub const ID_MPQ: u32 = 0x1A51_504D;
+pub const ID_MPQ_USERDATA: u32 = 0x1B51_504D;
+pub const ID_W3M_HEADER: u32 = 0x5733_4D48;
+pub const HET_TABLE_SIGNATURE: u32 = 0x1A54_4548;
+pub const BET_TABLE_SIGNATURE: u32 = 0x1A54_4542;
+
+pub const MPQ_HEADER_SIZE_V1: u32 = 0x20;
+pub const MPQ_HEADER_SIZE_V2: u32 = 0x2C;
+pub const MPQ_HEADER_SIZE_V3: u32 = 0x44;
+pub const MPQ_HEADER_SIZE_V4: u32 = 0xD0;
+
+pub const MPQ_FORMAT_VERSION_1: u16 = 0;
+pub const MPQ_FORMAT_VERSION_2: u16 = 1;
+pub const MPQ_FORMAT_VERSION_3: u16 = 2;
+pub const MPQ_FORMAT_VERSION_4: u16 = 3;
+
+pub const BLOCK_INDEX_MASK: u32 = 0x0FFF_FFFF;
+
+pub const MPQ_FILE_IMPLODE: u32 = 0x0000_0100;
+pub const MPQ_FILE_COMPRESS: u32 = 0x0000_0200;
+pub const MPQ_FILE_ENCRYPTED: u32 = 0x0001_0000;
+pub const MPQ_FILE_FIX_KEY: u32 = 0x0002_0000;
+pub const MPQ_FILE_PATCH_FILE: u32 = 0x0010_0000;
+pub const MPQ_FILE_SINGLE_UNIT: u32 = 0x0100_0000;
+pub const MPQ_FILE_DELETE_MARKER: u32 = 0x0200_0000;
+pub const MPQ_FILE_SECTOR_CRC: u32 = 0x0400_0000;
+pub const MPQ_FILE_SIGNATURE: u32 = 0x1000_0000;
+pub const MPQ_FILE_EXISTS: u32 = 0x8000_0000;
+
+pub const MPQ_COMPRESSION_HUFFMANN: u8 = 0x01;
+pub const MPQ_COMPRESSION_ZLIB: u8 = 0x02;
+pub const MPQ_COMPRESSION_PKWARE: u8 = 0x08;
+pub const MPQ_COMPRESSION_BZIP2: u8 = 0x10;
+pub const MPQ_COMPRESSION_SPARSE: u8 = 0x20;
+pub const MPQ_COMPRESSION_ADPCM_MONO: u8 = 0x40;
+pub const MPQ_COMPRESSION_ADPCM_STEREO: u8 = 0x80;
+pub const MPQ_COMPRESSION_LZMA: u8 = 0x12;
+
+pub const MPQ_ATTRIBUTES_V1: u32 = 100;
+pub const MPQ_ATTRIBUTE_CRC32: u32 = 0x01;
+pub const MPQ_ATTRIBUTE_FILETIME: u32 = 0x02;
+pub const MPQ_ATTRIBUTE_MD5: u32 = 0x04;
+pub const MPQ_ATTRIBUTE_PATCH_BIT: u32 = 0x08;
+
+pub const LISTFILE_NAME: &str = "(listfile)";
+pub const ATTRIBUTES_NAME: &str = "(attributes)";
+pub const SIGNATURE_NAME: &str = "(signature)";
+
+pub const BASE_PROVIDER_FILE: u32 = 0x0000_0000;
+pub const BASE_PROVIDER_MAP: u32 = 0x0000_0001;
+pub const BASE_PROVIDER_HTTP: u32 = 0x0000_0002;
+pub const BASE_PROVIDER_MASK: u32 = 0x0000_000F;
+pub const STREAM_PROVIDER_PARTIAL: u32 = 0x0000_0010;
+pub const STREAM_PROVIDER_MPQE: u32 = 0x0000_0020;
+pub const STREAM_PROVIDER_BLOCK4: u32 = 0x0000_0030;
+pub const STREAM_PROVIDER_MASK: u32 = 0x0000_00F0;
+pub const STREAM_FLAG_READ_ONLY: u32 = 0x0000_0100;
+pub const STREAM_FLAG_WRITE_SHARE: u32 = 0x0000_0200;
+
+pub const MPQ_OPEN_NO_LISTFILE: u32 = 0x0001_0000;
+pub const MPQ_OPEN_NO_ATTRIBUTES: u32 = 0x0002_0000;
+pub const MPQ_OPEN_NO_HEADER_SEARCH: u32 = 0x0004_0000;
+pub const MPQ_OPEN_FORCE_MPQ_V1: u32 = 0x0008_0000;
+pub const MPQ_OPEN_CHECK_SECTOR_CRC: u32 = 0x0010_0000;
+pub const MPQ_OPEN_READ_ONLY: u32 = STREAM_FLAG_READ_ONLY;
+
+#[cfg(not(feature = "pure-rust"))]
+#[test]
+fn test_mirrors_stormlib() {
+    use std::ffi::CStr;
+    assert_eq!(ID_MPQ, stormlib_sys::ID_MPQ);
+    assert_eq!(ID_MPQ_USERDATA, stormlib_sys::ID_MPQ_USERDATA);
+    assert_eq!(HET_TABLE_SIGNATURE, stormlib_sys::HET_TABLE_SIGNATURE);
+    assert_eq!(BET_TABLE_SIGNATURE, stormlib_sys::BET_TABLE_SIGNATURE);
+    assert_eq!(MPQ_HEADER_SIZE_V4, stormlib_sys::MPQ_HEADER_SIZE_V4);
+    assert_eq!(BLOCK_INDEX_MASK, stormlib_sys::BLOCK_INDEX_MASK);
+    assert_eq!(MPQ_FILE_FIX_KEY, stormlib_sys::MPQ_FILE_FIX_KEY);
+    assert_eq!(MPQ_FILE_SECTOR_CRC, stormlib_sys::MPQ_FILE_SECTOR_CRC);
+    assert_eq!(MPQ_FILE_EXISTS, stormlib_sys::MPQ_FILE_EXISTS);
+    assert_eq!(
+        MPQ_COMPRESSION_LZMA as u32,
+        stormlib_sys::MPQ_COMPRESSION_LZMA
+    );
+    assert_eq!(
+        MPQ_COMPRESSION_ADPCM_STEREO as u32,
+        stormlib_sys::MPQ_COMPRESSION_ADPCM_STEREO
+    );
+    assert_eq!(MPQ_OPEN_FORCE_MPQ_V1, stormlib_sys::MPQ_OPEN_FORCE_MPQ_V1);
+    assert_eq!(
+        MPQ_OPEN_CHECK_SECTOR_CRC,
+        stormlib_sys::MPQ_OPEN_CHECK_SECTOR_CRC
+    );
+    assert_eq!(MPQ_OPEN_READ_ONLY, stormlib_sys::MPQ_OPEN_READ_ONLY);
+    let listfile = CStr::from_bytes_with_nul(stormlib_sys::LISTFILE_NAME).unwrap();
+    assert_eq!(LISTFILE_NAME, listfile.to_str().unwrap());
+}
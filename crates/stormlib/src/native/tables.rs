@@ -0,0 +1,380 @@
+use std::convert::TryInto;
+use std::io::{Read, Seek, SeekFrom};
+
+use super::compression;
+use super::sys::*;
+use crate::crypto::{
+    decrypt_bytes, encrypt_bytes, hash_string_jenkins, HashEntry, BLOCK_TABLE_KEY, HASH_TABLE_KEY,
+};
+use crate::error::*;
+use crate::FileFlags;
+
+use super::header::MpqHeader;
+
+/// Entry of the block table, or of the BET table for v3+ archives
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BlockEntry {
+    /// Position of the file data relative to the MPQ header
+    pub file_pos:        u64,
+    pub compressed_size: u32,
+    pub file_size:       u32,
+    pub flags:           FileFlags,
+}
+
+impl BlockEntry {
+    /// Size of an entry on disk
+    pub const SIZE: usize = 16;
+
+    /// Parses an already decrypted block table
+    pub fn parse_table(data: &[u8]) -> Vec<BlockEntry> {
+        data.chunks_exact(Self::SIZE)
+            .map(|b| {
+                let dword = |i: usize| u32::from_le_bytes(b[i..i + 4].try_into().unwrap());
+                BlockEntry {
+                    file_pos:        dword(0) as u64,
+                    compressed_size: dword(4),
+                    file_size:       dword(8),
+                    flags:           FileFlags::from_bits_truncate(dword(12)),
+                }
+            })
+            .collect()
+    }
+
+    /// Serializes and encrypts a block table, file positions must fit in 32 bits
+    pub fn write_table(entries: &[BlockEntry]) -> Vec<u8> {
+        let mut data = Vec::with_capacity(entries.len() * Self::SIZE);
+        for entry in entries {
+            data.extend_from_slice(&(entry.file_pos as u32).to_le_bytes());
+            data.extend_from_slice(&entry.compressed_size.to_le_bytes());
+            data.extend_from_slice(&entry.file_size.to_le_bytes());
+            data.extend_from_slice(&entry.flags.bits().to_le_bytes());
+        }
+        encrypt_bytes(&mut data, BLOCK_TABLE_KEY);
+        data
+    }
+
+    pub fn exists(&self) -> bool {
+        self.flags.contains(FileFlags::EXISTS) && !self.flags.contains(FileFlags::DELETE_MARKER)
+    }
+}
+
+fn dword(data: &[u8], pos: usize) -> Result<u32> {
+    data.get(pos..pos + 4)
+        .map(|b| u32::from_le_bytes(b.try_into().unwrap()))
+        .ok_or(StormError::FileCorrupt)
+}
+
+/// Reads `count` bits starting at bit `pos` of a little-endian bit array
+fn read_bits(data: &[u8], pos: u64, count: u32) -> Result<u64> {
+    let mut value = 0u64;
+    for i in 0..count as u64 {
+        let bit = pos + i;
+        let byte = *data
+            .get((bit / 8) as usize)
+            .ok_or(StormError::FileCorrupt)?;
+        value |= (((byte >> (bit % 8)) & 1) as u64) << i;
+    }
+    Ok(value)
+}
+
+/// Reads up to `size` bytes at `pos`, tables cut by map protectors or
+/// interrupted saves come back shorter
+fn read_at<R: Read + Seek>(reader: &mut R, pos: u64, size: u64) -> Result<Vec<u8>> {
+    let mut data = Vec::new();
+    reader.seek(SeekFrom::Start(pos))?;
+    reader.take(size).read_to_end(&mut data)?;
+    Ok(data)
+}
+
+/// Reads a table, decrypting it and then decompressing it if it is stored
+/// smaller than `full_size`
+fn load_table<R: Read + Seek>(
+    reader: &mut R,
+    header: &MpqHeader,
+    pos: u64,
+    compressed_size: u64,
+    full_size: u64,
+    key: u32,
+) -> Result<Vec<u8>> {
+    let stored_size = if compressed_size != 0 && compressed_size < full_size {
+        compressed_size
+    } else {
+        full_size
+    };
+    let mut data = read_at(reader, header.offset + pos, stored_size)?;
+    if key != 0 {
+        decrypt_bytes(&mut data, key);
+    }
+    if stored_size < full_size {
+        data = compression::decompress(&data, full_size as usize)?;
+    }
+    Ok(data)
+}
+
+pub(crate) fn load_hash_table<R: Read + Seek>(
+    reader: &mut R,
+    header: &MpqHeader,
+) -> Result<Vec<HashEntry>> {
+    if header.hash_table_pos == 0 || header.hash_table_size == 0 {
+        return Ok(Vec::new());
+    }
+    let size = header.hash_table_size as u64 * HashEntry::SIZE as u64;
+    let data = load_table(
+        reader,
+        header,
+        header.hash_table_pos,
+        header.hash_table_size64,
+        size,
+        HASH_TABLE_KEY,
+    )?;
+    let mut table = HashEntry::parse_table(&data);
+    table.resize(header.hash_table_size as usize, HashEntry::free());
+    Ok(table)
+}
+
+pub(crate) fn load_block_table<R: Read + Seek>(
+    reader: &mut R,
+    header: &MpqHeader,
+) -> Result<Vec<BlockEntry>> {
+    if header.block_table_size == 0 {
+        return Ok(Vec::new());
+    }
+    let count = header.block_table_size as u64;
+    let data = load_table(
+        reader,
+        header,
+        header.block_table_pos,
+        header.block_table_size64,
+        count * BlockEntry::SIZE as u64,
+        BLOCK_TABLE_KEY,
+    )?;
+    let mut blocks = BlockEntry::parse_table(&data);
+
+    if header.hi_block_table_pos != 0 {
+        let hi = load_table(
+            reader,
+            header,
+            header.hi_block_table_pos,
+            header.hi_block_table_size64,
+            count * 2,
+            0,
+        )?;
+        for (block, hi) in blocks.iter_mut().zip(hi.chunks_exact(2)) {
+            block.file_pos |= (u16::from_le_bytes([hi[0], hi[1]]) as u64) << 32;
+        }
+    }
+    Ok(blocks)
+}
+
+/// Reads a HET or BET table, returning the data following the 12-byte
+/// extended table header
+fn load_ext_table<R: Read + Seek>(
+    reader: &mut R,
+    header: &MpqHeader,
+    pos: u64,
+    size64: u64,
+    signature: u32,
+    key: u32,
+) -> Result<Vec<u8>> {
+    let ext_header = read_at(reader, header.offset + pos, 12)?;
+    if dword(&ext_header, 0)? != signature {
+        return Err(StormError::FileCorrupt);
+    }
+    let data_size = dword(&ext_header, 8)? as u64;
+    let stored_size = if size64 != 0 { size64 } else { data_size + 12 };
+
+    let mut data = read_at(
+        reader,
+        header.offset + pos + 12,
+        stored_size.saturating_sub(12),
+    )?;
+    decrypt_bytes(&mut data, key);
+    if data_size + 12 > stored_size {
+        data = compression::decompress(&data, data_size as usize)?;
+    }
+    Ok(data)
+}
+
+/// HET table, the hash table of MPQ v3 and later archives
+#[derive(Debug, Clone)]
+pub(crate) struct HetTable {
+    and_mask:         u64,
+    or_mask:          u64,
+    name_hash_bits:   u32,
+    name_hashes:      Vec<u8>,
+    index_size_total: u32,
+    index_size:       u32,
+    indexes:          Vec<u8>,
+}
+
+impl HetTable {
+    pub fn load<R: Read + Seek>(reader: &mut R, header: &MpqHeader) -> Result<Self> {
+        let data = load_ext_table(
+            reader,
+            header,
+            header.het_table_pos,
+            header.het_table_size64,
+            HET_TABLE_SIGNATURE,
+            HASH_TABLE_KEY,
+        )?;
+        let total_count = dword(&data, 8)? as usize;
+        let name_hash_bits = dword(&data, 12)?;
+        if !(8..=64).contains(&name_hash_bits) {
+            return Err(StormError::FileCorrupt);
+        }
+        let index_table_size = dword(&data, 28)? as usize;
+        let name_hashes = data
+            .get(32..32 + total_count)
+            .ok_or(StormError::FileCorrupt)?
+            .to_vec();
+        let indexes = data
+            .get(32 + total_count..32 + total_count + index_table_size)
+            .ok_or(StormError::FileCorrupt)?
+            .to_vec();
+        Ok(HetTable {
+            and_mask: if name_hash_bits == 64 {
+                !0
+            } else {
+                (1 << name_hash_bits) - 1
+            },
+            or_mask: 1 << (name_hash_bits - 1),
+            name_hash_bits,
+            name_hashes,
+            index_size_total: dword(&data, 16)?,
+            index_size: dword(&data, 24)?,
+            indexes,
+        })
+    }
+
+    /// Masked 64-bit hash of a name, as compared against `BetTable::name_hash`
+    pub fn name_hash(&self, name: &str) -> u64 {
+        (hash_string_jenkins(name) & self.and_mask) | self.or_mask
+    }
+
+    /// BET index stored in a slot
+    fn index(&self, slot: usize) -> Result<u32> {
+        let pos = slot as u64 * self.index_size_total as u64;
+        Ok(read_bits(&self.indexes, pos, self.index_size)? as u32)
+    }
+
+    /// Occupied slots, with the upper 8 bits of the name hash and the BET index
+    pub fn entries(&self) -> Vec<(usize, u8, u32)> {
+        self.name_hashes
+            .iter()
+            .enumerate()
+            .filter(|(_, &hash)| hash != 0)
+            .filter_map(|(slot, &hash)| Some((slot, hash, self.index(slot).ok()?)))
+            .collect()
+    }
+
+    /// Finds the slot and BET index of a name
+    pub fn lookup(&self, name: &str, bet: &BetTable) -> Option<(usize, u32)> {
+        let total = self.name_hashes.len();
+        if total == 0 {
+            return None;
+        }
+        let hash = self.name_hash(name);
+        let hash1 = (hash >> (self.name_hash_bits - 8)) as u8;
+        let start = (hash % total as u64) as usize;
+        for i in 0..total {
+            let slot = (start + i) % total;
+            match self.name_hashes[slot] {
+                0 => break,
+                h if h == hash1 => {
+                    let index = self.index(slot).ok()?;
+                    if bet.name_hash(index, h) == Some(hash & self.and_mask) {
+                        return Some((slot, index));
+                    }
+                }
+                _ => {}
+            }
+        }
+        None
+    }
+}
+
+/// BET table, the block table of MPQ v3 and later archives
+#[derive(Debug, Clone)]
+pub(crate) struct BetTable {
+    pub entries:     Vec<BlockEntry>,
+    name_hash_bits:  u32,
+    name_hash_total: u32,
+    name_hashes:     Vec<u8>,
+}
+
+impl BetTable {
+    pub fn load<R: Read + Seek>(reader: &mut R, header: &MpqHeader) -> Result<Self> {
+        let data = load_ext_table(
+            reader,
+            header,
+            header.bet_table_pos,
+            header.bet_table_size64,
+            BET_TABLE_SIGNATURE,
+            BLOCK_TABLE_KEY,
+        )?;
+        let field = |i: usize| dword(&data, 16 + i * 4);
+        let entry_count = dword(&data, 4)? as u64;
+        let entry_size = dword(&data, 12)? as u64;
+        let (pos_index, size_index, csize_index, flag_index) =
+            (field(0)?, field(1)?, field(2)?, field(3)?);
+        let (pos_bits, size_bits, csize_bits, flag_bits) =
+            (field(5)?, field(6)?, field(7)?, field(8)?);
+        let name_hash_total = field(10)?;
+        let name_hash_bits = field(12)?;
+        let name_hash_array_size = field(13)? as usize;
+        let flag_count = field(14)? as usize;
+
+        let mut flags = Vec::with_capacity(flag_count);
+        for i in 0..flag_count {
+            flags.push(dword(&data, 76 + i * 4)?);
+        }
+        let table_start = 76 + flag_count * 4;
+        let table_len = (entry_count * entry_size).div_ceil(8) as usize;
+        let table = data
+            .get(table_start..table_start + table_len)
+            .ok_or(StormError::FileCorrupt)?;
+        let name_hashes = data
+            .get(table_start + table_len..table_start + table_len + name_hash_array_size)
+            .ok_or(StormError::FileCorrupt)?
+            .to_vec();
+
+        let mut entries = Vec::with_capacity(entry_count as usize);
+        for i in 0..entry_count {
+            let base = i * entry_size;
+            let bits = |index: u32, count: u32| read_bits(table, base + index as u64, count);
+            let flag = bits(flag_index, flag_bits)? as usize;
+            entries.push(BlockEntry {
+                file_pos:        bits(pos_index, pos_bits)?,
+                file_size:       bits(size_index, size_bits)? as u32,
+                compressed_size: bits(csize_index, csize_bits)? as u32,
+                flags:           FileFlags::from_bits_truncate(
+                    flags.get(flag).copied().unwrap_or(0),
+                ),
+            });
+        }
+        Ok(BetTable {
+            entries,
+            name_hash_bits,
+            name_hash_total,
+            name_hashes,
+        })
+    }
+
+    /// Full name hash of an entry, given the upper 8 bits kept in the HET table
+    fn name_hash(&self, index: u32, hash1: u8) -> Option<u64> {
+        if index as usize >= self.entries.len() {
+            return None;
+        }
+        let pos = index as u64 * self.name_hash_total as u64;
+        let hash2 = read_bits(&self.name_hashes, pos, self.name_hash_bits).ok()?;
+        Some((hash1 as u64) << self.name_hash_bits | hash2)
+    }
+}
+
+#[test]
+fn test_read_bits() {
+    let data = [0b1011_0100, 0b0000_0011];
+    assert_eq!(read_bits(&data, 2, 3).unwrap(), 0b101);
+    assert_eq!(read_bits(&data, 6, 4).unwrap(), 0b1110);
+    assert!(read_bits(&data, 14, 4).is_err());
+}
@@ -0,0 +1,55 @@
+//! Helpers for tests of this crate and of the crates building on it, enabled
+//! by the `testing` feature
+
+use std::convert::TryInto;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use super::Archive;
+use crate::OpenArchiveFlags;
+
+/// Directory below the system temporary directory, removed with its content
+/// when dropped, even by a failing test
+pub struct TempDir(PathBuf);
+
+impl TempDir {
+    /// Creates the directory, named after `name` and the process
+    pub fn new(name: &str) -> Self {
+        let path = std::env::temp_dir().join(format!("{}-{}", name, std::process::id()));
+        fs::create_dir_all(&path).unwrap();
+        TempDir(path)
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.0
+    }
+
+    pub fn join<P: AsRef<Path>>(&self, path: P) -> PathBuf {
+        self.0.join(path)
+    }
+}
+
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        fs::remove_dir_all(&self.0).ok();
+    }
+}
+
+/// Garbles sector `index` of the compressed file `name` in the archive at
+/// `path`, keeping its compression type and the start of its stream so it
+/// fails to decompress
+pub fn garble_sector(path: &Path, name: &str, index: usize) {
+    let raw = Archive::open(path, OpenArchiveFlags::MPQ_OPEN_NO_FLAG)
+        .unwrap()
+        .read_raw(name)
+        .unwrap();
+    let mut bytes = fs::read(path).unwrap();
+    let start = bytes
+        .windows(raw.data.len())
+        .position(|w| w == raw.data.as_slice())
+        .unwrap();
+    let offset =
+        |i: usize| u32::from_le_bytes(raw.data[i * 4..i * 4 + 4].try_into().unwrap()) as usize;
+    bytes[start + offset(index) + 3..start + offset(index + 1)].fill(0x55);
+    fs::write(path, &bytes).unwrap();
+}
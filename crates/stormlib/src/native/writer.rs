@@ -0,0 +1,841 @@
+//! Deterministic MPQ v1 writer
+//!
+//! Files are laid out in the order of their normalized names, whatever order
+//! they were added in, with no gaps between them and fixed file times, so the
+//! same inputs always produce the same bytes.
+
+use std::collections::BTreeMap;
+use std::fs;
+use std::io::Write;
+use std::path::Path;
+
+use md5::{Digest, Md5};
+
+use super::archive::Archive;
+use super::attributes::Attributes;
+use super::compression::{compress, Compression};
+use super::header::MpqHeader;
+use super::sys::*;
+use super::tables::BlockEntry;
+use crate::crypto::{self, HashEntry, NameHash};
+use crate::error::*;
+use crate::{ConflictPolicy, FileEntry, FileFlags, Import};
+
+/// How a file is stored in the archive
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FileOptions {
+    pub compression: Compression,
+    pub encrypt:     bool,
+    /// Adjusts the encryption key by the file position, see `FileFlags::FIX_KEY`
+    pub fix_key:     bool,
+    /// Stores the file as one unit instead of sectors
+    pub single_unit: bool,
+    pub locale:      u16,
+}
+
+impl Default for FileOptions {
+    fn default() -> Self {
+        FileOptions {
+            compression: Compression::Zlib,
+            encrypt:     false,
+            fix_key:     false,
+            single_unit: false,
+            locale:      0,
+        }
+    }
+}
+
+impl FileOptions {
+    /// Options storing a file like an existing entry with these flags; the
+    /// compression method isn't part of the flags, compressed files use zlib
+    pub fn from_flags(flags: FileFlags, locale: u16) -> Self {
+        FileOptions {
+            compression: if flags.intersects(FileFlags::COMPRESS | FileFlags::IMPLODE) {
+                Compression::Zlib
+            } else {
+                Compression::None
+            },
+            encrypt: flags.contains(FileFlags::ENCRYPTED),
+            fix_key: flags.contains(FileFlags::FIX_KEY),
+            single_unit: flags.contains(FileFlags::SINGLE_UNIT),
+            locale,
+        }
+    }
+
+    fn flags(&self, size: usize) -> FileFlags {
+        let mut flags = FileFlags::EXISTS;
+        if self.compression != Compression::None && size > 0 {
+            flags |= FileFlags::COMPRESS;
+        }
+        if self.encrypt {
+            flags |= FileFlags::ENCRYPTED;
+            if self.fix_key {
+                flags |= FileFlags::FIX_KEY;
+            }
+        }
+        if self.single_unit {
+            flags |= FileFlags::SINGLE_UNIT;
+        }
+        flags
+    }
+}
+
+/// Options of the internal `(listfile)` and `(attributes)`, matching StormLib's defaults
+const INTERNAL_FILE: FileOptions = FileOptions {
+    compression: Compression::Zlib,
+    encrypt:     true,
+    fix_key:     true,
+    single_unit: false,
+    locale:      0,
+};
+
+/// File copied as stored in another archive, see `Archive::read_raw`
+#[derive(Debug, Clone, PartialEq)]
+pub struct RawFile {
+    /// Stored bytes, with the sector offset table, compressed and encrypted
+    pub data:        Vec<u8>,
+    pub file_size:   u32,
+    pub flags:       FileFlags,
+    /// Sector size of the archive the file comes from
+    pub sector_size: u32,
+    /// Checksums of the uncompressed content, for `(attributes)`
+    pub crc32:       u32,
+    pub md5:         [u8; 16],
+}
+
+impl RawFile {
+    /// Compresses and encrypts `data` as stored in an archive with sectors of
+    /// `sector_size` bytes, so it can be done ahead of writing, e.g. on other
+    /// threads. Files whose key depends on their position can't be encoded
+    /// before it is known.
+    pub fn encode(
+        name: &str,
+        data: &[u8],
+        options: FileOptions,
+        sector_size: u32,
+    ) -> Result<RawFile> {
+        if options.encrypt && options.fix_key {
+            return Err(StormError::InvalidParameter);
+        }
+        let (bytes, block) = encode_file(name, data, options, 0, sector_size as usize)?;
+        Ok(RawFile {
+            data: bytes,
+            file_size: block.file_size,
+            flags: block.flags,
+            sector_size,
+            crc32: crc32(data),
+            md5: Md5::digest(data).into(),
+        })
+    }
+
+    /// Whether the file can be stored in an archive with sectors of `sector_size` bytes
+    pub fn fits(&self, sector_size: u32) -> bool {
+        self.sector_size == sector_size
+            || self.flags.contains(FileFlags::SINGLE_UNIT)
+            || self.file_size <= sector_size.min(self.sector_size)
+    }
+}
+
+#[derive(Debug, Clone)]
+enum Content {
+    Data(Vec<u8>, FileOptions),
+    Raw(RawFile),
+}
+
+#[derive(Debug, Clone)]
+struct PendingFile {
+    name:      String,
+    locale:    u16,
+    content:   Content,
+    /// Overrides the file time of the archive, see `ArchiveBuilder::set_file_time`
+    file_time: Option<u64>,
+}
+
+impl PendingFile {
+    fn crc32(&self) -> u32 {
+        match &self.content {
+            Content::Data(data, _) => crc32(data),
+            Content::Raw(raw) => raw.crc32,
+        }
+    }
+
+    fn md5(&self) -> [u8; 16] {
+        match &self.content {
+            Content::Data(data, _) => Md5::digest(data).into(),
+            Content::Raw(raw) => raw.md5,
+        }
+    }
+}
+
+/// Builds a MPQ v1 archive from scratch
+#[derive(Debug, Clone)]
+pub struct ArchiveBuilder {
+    prefix:            Vec<u8>,
+    sector_size_shift: u16,
+    hash_table_size:   Option<u32>,
+    file_time:         u64,
+    listfile:          bool,
+    attributes:        bool,
+    files:             BTreeMap<(String, u16), PendingFile>,
+}
+
+impl Default for ArchiveBuilder {
+    fn default() -> Self {
+        ArchiveBuilder {
+            prefix:            Vec::new(),
+            sector_size_shift: 3,
+            hash_table_size:   None,
+            file_time:         0,
+            listfile:          true,
+            attributes:        true,
+            files:             BTreeMap::new(),
+        }
+    }
+}
+
+/// Sort key of a name, so ordering ignores case and separator style
+fn normalize(name: &str) -> String {
+    name.replace('/', "\\").to_uppercase()
+}
+
+impl ArchiveBuilder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Data written ahead of the archive, e.g. the 512-byte HM3W header of a
+    /// Warcraft III map; it is padded to the next 512-byte boundary
+    pub fn prefix(mut self, prefix: &[u8]) -> Self {
+        self.prefix = prefix.to_vec();
+        self
+    }
+
+    /// Sector size as a power of two times 512, 3 (4 KiB) by default like Warcraft III
+    pub fn sector_size_shift(mut self, shift: u16) -> Self {
+        self.sector_size_shift = shift;
+        self
+    }
+
+    /// Size of a sector, in bytes
+    pub fn sector_size(&self) -> u32 {
+        0x200 << self.sector_size_shift
+    }
+
+    /// Number of hash table entries, rounded up to a power of two; by default
+    /// the smallest one leaving room for every file
+    pub fn hash_table_size(mut self, size: u32) -> Self {
+        self.hash_table_size = Some(size.next_power_of_two());
+        self
+    }
+
+    /// File time recorded in `(attributes)` for every file, 0 by default
+    pub fn file_time(mut self, file_time: u64) -> Self {
+        self.file_time = file_time;
+        self
+    }
+
+    /// Whether to generate `(listfile)`, enabled by default
+    pub fn listfile(mut self, enabled: bool) -> Self {
+        self.listfile = enabled;
+        self
+    }
+
+    /// Whether to generate `(attributes)` with CRC32, MD5 and file times, enabled by default
+    pub fn attributes(mut self, enabled: bool) -> Self {
+        self.attributes = enabled;
+        self
+    }
+
+    /// Adds a file, replacing any file with the same name and locale
+    pub fn add(&mut self, name: &str, data: Vec<u8>, options: FileOptions) {
+        self.insert(name, options.locale, Content::Data(data, options));
+    }
+
+    /// Adds a file copied as stored in another archive, without recompressing it
+    ///
+    /// Files encrypted with `FIX_KEY` can't be moved and are rejected, as are
+    /// files whose sectors don't match the sector size of this archive when
+    /// it is written.
+    pub fn add_raw(&mut self, name: &str, raw: RawFile, locale: u16) -> Result<()> {
+        if raw.flags.contains(FileFlags::FIX_KEY) {
+            return Err(StormError::InvalidParameter);
+        }
+        self.insert(name, locale, Content::Raw(raw));
+        Ok(())
+    }
+
+    fn insert(&mut self, name: &str, locale: u16, content: Content) {
+        let name = name.replace('/', "\\");
+        self.files.insert(
+            (normalize(&name), locale),
+            PendingFile {
+                name,
+                locale,
+                content,
+                file_time: None,
+            },
+        );
+    }
+
+    /// Sets the time recorded in `(attributes)` for a file added before,
+    /// instead of the one of the whole archive
+    pub fn set_file_time(&mut self, name: &str, locale: u16, file_time: u64) -> bool {
+        match self.files.get_mut(&(normalize(name), locale)) {
+            Some(file) => {
+                file.file_time = Some(file_time);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Whether a file was added with this name and locale
+    pub fn contains(&self, name: &str, locale: u16) -> bool {
+        self.files.contains_key(&(normalize(name), locale))
+    }
+
+    /// Imports the files of `other` accepted by `filter`, in their own locale
+    ///
+    /// Files are copied as stored when their sectors fit this archive and
+    /// decompressed and stored again otherwise, or left out when they can't
+    /// be decompressed. Internal files like `(listfile)` are left out, as are
+    /// files without a known name.
+    pub fn import_from<F>(
+        &mut self,
+        other: &mut Archive,
+        mut filter: F,
+        policy: ConflictPolicy,
+    ) -> Result<Import>
+    where
+        F: FnMut(&FileEntry) -> bool,
+    {
+        let entries: Vec<FileEntry> = other
+            .list()?
+            .into_iter()
+            .filter(|entry| !entry.is_internal() && filter(entry))
+            .collect();
+        let exists =
+            |builder: &Self, entry: &FileEntry| builder.contains(&entry.name, entry.locale as u16);
+        if policy == ConflictPolicy::Fail && entries.iter().any(|e| exists(self, e)) {
+            return Err(StormError::AlreadyExists);
+        }
+
+        let sector_size = 0x200u32 << self.sector_size_shift;
+        let mut import = Import::default();
+        for entry in entries {
+            let locale = entry.locale as u16;
+            if entry.is_unnamed() {
+                import.unnamed.push(entry.name);
+                continue;
+            }
+            if policy == ConflictPolicy::Keep && exists(self, &entry) {
+                import.kept.push(entry.name);
+                continue;
+            }
+            let raw = other.read_raw_entry(&entry)?;
+            if raw.fits(sector_size) && !raw.flags.contains(FileFlags::FIX_KEY) {
+                self.add_raw(&entry.name, raw, locale)?;
+                import.copied.push(entry.name);
+            } else {
+                match other.open_block(entry.block_index).read_all() {
+                    Ok(data) => {
+                        self.add(
+                            &entry.name,
+                            data,
+                            FileOptions::from_flags(entry.flags, locale),
+                        );
+                        import.recompressed.push(entry.name);
+                    }
+                    Err(_) => import.unreadable.push(entry.name),
+                }
+            }
+        }
+        Ok(import)
+    }
+
+    /// Adds a file read from the local file system
+    pub fn add_file<P: AsRef<Path>>(
+        &mut self,
+        name: &str,
+        path: P,
+        options: FileOptions,
+    ) -> Result<()> {
+        self.add(name, fs::read(path)?, options);
+        Ok(())
+    }
+
+    /// Removes a file added before, in every locale
+    pub fn remove(&mut self, name: &str) -> bool {
+        let key = normalize(name);
+        let before = self.files.len();
+        self.files.retain(|(name, _), _| *name != key);
+        self.files.len() != before
+    }
+
+    pub fn len(&self) -> usize {
+        self.files.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.files.is_empty()
+    }
+
+    /// Writes the archive to `path`
+    pub fn write<P: AsRef<Path>>(&self, path: P) -> Result<()> {
+        let mut file = fs::File::create(path)?;
+        self.write_to(&mut file)?;
+        file.flush()?;
+        Ok(())
+    }
+
+    /// Writes the archive
+    pub fn write_to<W: Write>(&self, writer: &mut W) -> Result<()> {
+        let mut files: Vec<&PendingFile> = self.files.values().collect();
+        let has = |name: &str| self.files.keys().any(|(key, _)| *key == normalize(name));
+
+        let listfile;
+        if self.listfile && !has(LISTFILE_NAME) {
+            let mut names: Vec<&str> = files.iter().map(|f| f.name.as_str()).collect();
+            names.dedup_by(|a, b| a.eq_ignore_ascii_case(b));
+            listfile = PendingFile {
+                name:      LISTFILE_NAME.to_string(),
+                locale:    0,
+                content:   Content::Data(names.join("\r\n").into_bytes(), INTERNAL_FILE),
+                file_time: None,
+            };
+            files.push(&listfile);
+        }
+
+        let attributes;
+        if self.attributes && !has(ATTRIBUTES_NAME) {
+            // the entry of (attributes) itself is left empty, like StormLib does
+            let mut file_times: Vec<u64> = files
+                .iter()
+                .map(|f| f.file_time.unwrap_or(self.file_time))
+                .collect();
+            file_times.push(self.file_time);
+            let mut crc32: Vec<u32> = files.iter().map(|f| f.crc32()).collect();
+            let mut md5: Vec<[u8; 16]> = files.iter().map(|f| f.md5()).collect();
+            crc32.push(0);
+            md5.push([0; 16]);
+            let data = Attributes {
+                crc32,
+                file_times,
+                md5,
+            }
+            .to_bytes();
+            attributes = PendingFile {
+                name:      ATTRIBUTES_NAME.to_string(),
+                locale:    0,
+                content:   Content::Data(data, INTERNAL_FILE),
+                file_time: None,
+            };
+            files.push(&attributes);
+        }
+
+        let hash_table_size = match self.hash_table_size {
+            Some(size) if size as usize > files.len() => size,
+            Some(_) => return Err(StormError::InvalidParameter),
+            None => ((files.len() + 1).next_power_of_two() as u32).max(4),
+        };
+        let sector_size = 0x200usize << self.sector_size_shift;
+
+        let mut data = Vec::new();
+        let mut blocks = Vec::with_capacity(files.len());
+        let mut hash_table = vec![HashEntry::free(); hash_table_size as usize];
+        let mut pos = MPQ_HEADER_SIZE_V1 as u64;
+        for (index, file) in files.iter().enumerate() {
+            let (bytes, block) = match &file.content {
+                Content::Data(data, options) => {
+                    encode_file(&file.name, data, *options, pos, sector_size)?
+                }
+                Content::Raw(raw) => {
+                    if !raw.fits(sector_size as u32) {
+                        return Err(StormError::InvalidParameter);
+                    }
+                    let block = BlockEntry {
+                        file_pos:        pos,
+                        compressed_size: raw.data.len() as u32,
+                        file_size:       raw.file_size,
+                        flags:           raw.flags,
+                    };
+                    (raw.data.clone(), block)
+                }
+            };
+            pos += bytes.len() as u64;
+            data.extend(bytes);
+            blocks.push(block);
+            insert_hash(&mut hash_table, &file.name, file.locale, index as u32);
+        }
+
+        let hash_table_pos = pos;
+        let block_table_pos = hash_table_pos + hash_table_size as u64 * HashEntry::SIZE as u64;
+        let archive_size = block_table_pos + (blocks.len() * BlockEntry::SIZE) as u64;
+        if archive_size > u32::MAX as u64 {
+            return Err(StormError::DiskFull);
+        }
+        let header = MpqHeader {
+            header_size: MPQ_HEADER_SIZE_V1,
+            archive_size,
+            format_version: MPQ_FORMAT_VERSION_1,
+            sector_size_shift: self.sector_size_shift,
+            hash_table_pos,
+            block_table_pos,
+            hash_table_size,
+            block_table_size: blocks.len() as u32,
+            ..Default::default()
+        };
+
+        let mut prefix = self.prefix.clone();
+        prefix.resize((prefix.len() + 0x1FF) & !0x1FF, 0);
+        writer.write_all(&prefix)?;
+        writer.write_all(&header.to_bytes_v1())?;
+        writer.write_all(&data)?;
+        writer.write_all(&HashEntry::write_table(&hash_table))?;
+        writer.write_all(&BlockEntry::write_table(&blocks))?;
+        Ok(())
+    }
+}
+
+pub(super) fn crc32(data: &[u8]) -> u32 {
+    let mut crc = flate2::Crc::new();
+    crc.update(data);
+    crc.sum()
+}
+
+fn insert_hash(table: &mut [HashEntry], name: &str, locale: u16, block_index: u32) {
+    let hash = NameHash::new(name);
+    let mask = table.len() - 1;
+    let mut index = hash.table_offset as usize & mask;
+    while !table[index].is_free() {
+        index = (index + 1) & mask;
+    }
+    table[index] = HashEntry {
+        name_a: hash.name_a,
+        name_b: hash.name_b,
+        locale,
+        platform: 0,
+        block_index,
+    };
+}
+
+/// Lays out a file as stored at `pos`, relative to the MPQ header
+fn encode_file(
+    name: &str,
+    data: &[u8],
+    options: FileOptions,
+    pos: u64,
+    sector_size: usize,
+) -> Result<(Vec<u8>, BlockEntry)> {
+    let flags = options.flags(data.len());
+    let key = crypto::file_key(name, pos, data.len() as u32, flags);
+    let encrypted = flags.contains(FileFlags::ENCRYPTED);
+
+    let mut bytes;
+    if flags.contains(FileFlags::SINGLE_UNIT) {
+        bytes = compress(data, options.compression)?;
+        if encrypted {
+            crypto::encrypt_bytes(&mut bytes, key);
+        }
+    } else {
+        let compressed = flags.contains(FileFlags::COMPRESS);
+        let mut sectors = Vec::new();
+        for (i, chunk) in data.chunks(sector_size).enumerate() {
+            let mut sector = if compressed {
+                compress(chunk, options.compression)?
+            } else {
+                chunk.to_vec()
+            };
+            if encrypted {
+                crypto::encrypt_bytes(&mut sector, key.wrapping_add(i as u32));
+            }
+            sectors.push(sector);
+        }
+
+        bytes = Vec::new();
+        if compressed {
+            let mut offset = (sectors.len() as u32 + 1) * 4;
+            let mut table = offset.to_le_bytes().to_vec();
+            for sector in &sectors {
+                offset += sector.len() as u32;
+                table.extend_from_slice(&offset.to_le_bytes());
+            }
+            if encrypted {
+                crypto::encrypt_bytes(&mut table, key.wrapping_sub(1));
+            }
+            bytes.extend(table);
+        }
+        sectors.into_iter().for_each(|sector| bytes.extend(sector));
+    }
+
+    let block = BlockEntry {
+        file_pos: pos,
+        compressed_size: bytes.len() as u32,
+        file_size: data.len() as u32,
+        flags,
+    };
+    Ok((bytes, block))
+}
+
+#[test]
+fn test_write_roundtrip() {
+    use super::testing::TempDir;
+    use super::Archive;
+    use crate::OpenArchiveFlags;
+
+    let script: Vec<u8> = (0..20000u32)
+        .flat_map(|i| format!("call F({})\n", i % 97).into_bytes())
+        .collect();
+    let noise: Vec<u8> = (0..9000u32)
+        .map(|i| (i.wrapping_mul(2_654_435_761) >> 13) as u8)
+        .collect();
+    let files: Vec<(&str, Vec<u8>, FileOptions)> = vec![
+        ("war3map.j", script.clone(), FileOptions::default()),
+        (
+            "war3mapImported/a.mdx",
+            noise.clone(),
+            FileOptions {
+                compression: Compression::Bzip2,
+                encrypt: true,
+                fix_key: true,
+                ..Default::default()
+            },
+        ),
+        (
+            "war3map.w3i",
+            script[..1000].to_vec(),
+            FileOptions {
+                compression: Compression::Bzip2,
+                single_unit: true,
+                encrypt: true,
+                ..Default::default()
+            },
+        ),
+        (
+            "war3map.wts",
+            script[..5000].to_vec(),
+            FileOptions {
+                compression: Compression::None,
+                encrypt: true,
+                ..Default::default()
+            },
+        ),
+        ("empty.txt", Vec::new(), FileOptions::default()),
+    ];
+
+    let build = |order: &mut dyn Iterator<Item = &(&str, Vec<u8>, FileOptions)>| {
+        let mut builder = ArchiveBuilder::new().prefix(b"HM3W");
+        for (name, data, options) in order {
+            builder.add(name, data.clone(), *options);
+        }
+        let mut bytes = Vec::new();
+        builder.write_to(&mut bytes).unwrap();
+        bytes
+    };
+    let bytes = build(&mut files.iter());
+    assert_eq!(bytes, build(&mut files.iter().rev()));
+    assert_eq!(&bytes[..4], b"HM3W");
+
+    let dir = TempDir::new("stormlib-writer");
+    let path = dir.join("map.w3x");
+    fs::write(&path, &bytes).unwrap();
+    let mut archive = Archive::open(&path, OpenArchiveFlags::MPQ_OPEN_NO_FLAG).unwrap();
+    let mut names: Vec<String> = archive
+        .list()
+        .unwrap()
+        .into_iter()
+        .map(|e| e.name)
+        .collect();
+    names.sort();
+    assert_eq!(
+        names,
+        vec![
+            "(attributes)",
+            "(listfile)",
+            "empty.txt",
+            "war3map.j",
+            "war3map.w3i",
+            "war3map.wts",
+            "war3mapImported\\a.mdx",
+        ]
+    );
+    for (name, data, _) in &files {
+        assert_eq!(
+            archive.open_file(name).unwrap().read_all().unwrap(),
+            *data,
+            "{}",
+            name
+        );
+    }
+    drop(archive);
+
+    // encrypted files keep working without their names
+    let mut archive = Archive::open(&path, OpenArchiveFlags::MPQ_OPEN_NO_LISTFILE).unwrap();
+    let unnamed: Vec<String> = archive
+        .list()
+        .unwrap()
+        .into_iter()
+        .filter(|e| e.is_unnamed() && e.file_size as usize == noise.len())
+        .map(|e| e.name)
+        .collect();
+    assert_eq!(
+        archive.open_file(&unnamed[0]).unwrap().read_all().unwrap(),
+        noise
+    );
+}
+
+#[test]
+fn test_add_raw() {
+    use super::testing::TempDir;
+    use super::Archive;
+    use crate::OpenArchiveFlags;
+
+    let script = b"call Init()\n".repeat(1000);
+    let model = (0..9000u32).map(|i| (i % 251) as u8).collect::<Vec<_>>();
+    let encrypted = FileOptions {
+        compression: Compression::Bzip2,
+        encrypt: true,
+        ..Default::default()
+    };
+    let mut builder = ArchiveBuilder::new();
+    builder.add("war3map.j", script.clone(), FileOptions::default());
+    builder.add("war3mapImported\\a.mdx", model.clone(), encrypted);
+    let dir = TempDir::new("stormlib-raw");
+    let path = dir.join("map.w3x");
+    builder.write(&path).unwrap();
+    let mut expected = Vec::new();
+    builder.write_to(&mut expected).unwrap();
+
+    // a copied file is stored exactly as encoding it again would
+    let mut archive = Archive::open(&path, OpenArchiveFlags::MPQ_OPEN_NO_FLAG).unwrap();
+    let raw = archive.read_raw("war3mapImported\\a.mdx").unwrap();
+    assert_eq!(raw.file_size as usize, model.len());
+    let mut rebuilt = ArchiveBuilder::new();
+    rebuilt.add("war3map.j", script.clone(), FileOptions::default());
+    rebuilt.add_raw("war3mapImported\\a.mdx", raw, 0).unwrap();
+    let mut bytes = Vec::new();
+    rebuilt.write_to(&mut bytes).unwrap();
+    assert_eq!(bytes, expected);
+
+    // and so is a file encoded ahead of writing
+    let mut encoded = ArchiveBuilder::new();
+    let sector_size = encoded.sector_size();
+    for (name, data, options) in [
+        ("war3map.j", &script, FileOptions::default()),
+        ("war3mapImported\\a.mdx", &model, encrypted),
+    ] {
+        let raw = RawFile::encode(name, data, options, sector_size).unwrap();
+        encoded.add_raw(name, raw, 0).unwrap();
+    }
+    let mut bytes = Vec::new();
+    encoded.write_to(&mut bytes).unwrap();
+    assert_eq!(bytes, expected);
+    let fix_key = FileOptions {
+        encrypt: true,
+        fix_key: true,
+        ..Default::default()
+    };
+    assert!(RawFile::encode("a.mdx", &model, fix_key, sector_size).is_err());
+
+    let mut fixed = archive.read_raw("(listfile)").unwrap();
+    assert!(rebuilt.add_raw("(listfile)", fixed.clone(), 0).is_err());
+    fixed.flags.remove(FileFlags::FIX_KEY);
+    rebuilt.add_raw("other.txt", fixed, 0).unwrap();
+    assert!(rebuilt
+        .sector_size_shift(4)
+        .write_to(&mut Vec::new())
+        .is_err());
+}
+
+#[test]
+fn test_import_from() {
+    use super::testing::TempDir;
+    use crate::OpenArchiveFlags;
+
+    let dir = TempDir::new("stormlib-import");
+    let path = |name: &str| dir.join(format!("{}.mpq", name));
+    let model = (0..9000u32).map(|i| (i % 251) as u8).collect::<Vec<_>>();
+    let german = FileOptions {
+        locale: 0x407,
+        ..Default::default()
+    };
+    let fixed = FileOptions {
+        encrypt: true,
+        fix_key: true,
+        ..Default::default()
+    };
+    let mut source = ArchiveBuilder::new();
+    source.add("war3map.j", b"base".to_vec(), FileOptions::default());
+    source.add("units.txt", b"[hfoo]".to_vec(), FileOptions::default());
+    source.add("units.txt", b"[hfoo] de".to_vec(), german);
+    source.add("a.mdx", model.clone(), fixed);
+    source.add("skip.psd", b"psd".to_vec(), FileOptions::default());
+    source.write(path("import-source")).unwrap();
+    let mut source =
+        Archive::open(path("import-source"), OpenArchiveFlags::MPQ_OPEN_NO_FLAG).unwrap();
+
+    let mut target = ArchiveBuilder::new();
+    target.add("war3map.j", b"target".to_vec(), FileOptions::default());
+    let filter = |entry: &FileEntry| !entry.name.ends_with(".psd");
+    assert!(matches!(
+        target.import_from(&mut source, filter, ConflictPolicy::Fail),
+        Err(StormError::AlreadyExists)
+    ));
+    assert_eq!(target.len(), 1);
+
+    let import = target
+        .import_from(&mut source, filter, ConflictPolicy::Keep)
+        .unwrap();
+    assert_eq!(import.kept, vec!["war3map.j"]);
+    assert_eq!(import.copied.len(), 2);
+    // files encrypted by position are stored again
+    assert_eq!(import.recompressed, vec!["a.mdx"]);
+    target.write(path("import-target")).unwrap();
+
+    let mut merged =
+        Archive::open(path("import-target"), OpenArchiveFlags::MPQ_OPEN_NO_FLAG).unwrap();
+    assert_eq!(
+        merged.open_file("war3map.j").unwrap().read_all().unwrap(),
+        b"target"
+    );
+    assert_eq!(
+        merged.open_file("a.mdx").unwrap().read_all().unwrap(),
+        model
+    );
+    assert!(!merged.has_file("skip.psd").unwrap());
+    let locales: Vec<u32> = merged
+        .find("units.txt")
+        .unwrap()
+        .iter()
+        .map(|e| e.locale)
+        .collect();
+    assert_eq!(locales.len(), 2);
+    assert!(locales.contains(&0x407));
+
+    // sectors compressed with an unknown Huffman table can only be copied as stored
+    let mut sectors = Vec::new();
+    for offset in &[12u32, 16, 20] {
+        sectors.extend_from_slice(&offset.to_le_bytes());
+    }
+    sectors.extend_from_slice(&[0x01, 0x03, 0xAA, 0xBB, 0x01, 0x03, 0xCC, 0xDD]);
+    let huffman = RawFile {
+        data:        sectors,
+        file_size:   6000,
+        flags:       FileFlags::EXISTS | FileFlags::COMPRESS,
+        sector_size: 0x1000,
+        crc32:       0x1234,
+        md5:         [7; 16],
+    };
+    let mut source = ArchiveBuilder::new();
+    source.add_raw("sound.wav", huffman, 0).unwrap();
+    source.write(path("import-source")).unwrap();
+    let mut source =
+        Archive::open(path("import-source"), OpenArchiveFlags::MPQ_OPEN_NO_FLAG).unwrap();
+    let raw = source.read_raw("sound.wav").unwrap();
+    assert_eq!((raw.crc32, raw.md5), (0x1234, [7; 16]));
+    let import = ArchiveBuilder::new()
+        .sector_size_shift(4)
+        .import_from(&mut source, |_| true, ConflictPolicy::Fail)
+        .unwrap();
+    assert_eq!(import.unreadable, vec!["sound.wav"]);
+}
@@ -0,0 +1,81 @@
+use std::convert::TryInto;
+
+/// `ID_MPQ_USERDATA` signature, `MPQ\x1B`
+pub const USER_DATA_SIGNATURE: u32 = crate::native::sys::ID_MPQ_USERDATA;
+
+/// Size of the fixed part of the user data header
+pub const USER_DATA_HEADER_SIZE: u32 = 16;
+
+/// User data block placed ahead of the MPQ header, as used by StarCraft II
+/// replays and custom launchers
+#[derive(Debug, Clone, PartialEq)]
+pub struct UserData {
+    /// Maximum size of the user data
+    pub user_data_size: u32,
+    /// Offset of the MPQ header, relative to the start of the user data block
+    pub header_offset:  u32,
+    /// User data content, following the fixed header
+    pub content:        Vec<u8>,
+}
+
+impl UserData {
+    /// Creates a user data block for `content`, placing the MPQ header at the
+    /// next 512-byte boundary as StormLib expects
+    pub fn new(content: &[u8]) -> Self {
+        let header_offset = (USER_DATA_HEADER_SIZE + content.len() as u32 + 0x1FF) & !0x1FF;
+        UserData {
+            user_data_size: header_offset - USER_DATA_HEADER_SIZE,
+            header_offset,
+            content: content.to_vec(),
+        }
+    }
+
+    /// Parses a user data block, `bytes` must start at the `MPQ\x1B` signature
+    pub fn parse(bytes: &[u8]) -> Option<Self> {
+        let dword = |i: usize| {
+            bytes
+                .get(i * 4..i * 4 + 4)
+                .map(|b| u32::from_le_bytes(b.try_into().unwrap()))
+        };
+        if dword(0)? != USER_DATA_SIGNATURE {
+            return None;
+        }
+        let user_data_size = dword(1)?;
+        let header_offset = dword(2)?;
+        let content_size = dword(3)? as usize;
+        let start = USER_DATA_HEADER_SIZE as usize;
+        let content = bytes.get(start..start + content_size)?.to_vec();
+        Some(UserData {
+            user_data_size,
+            header_offset,
+            content,
+        })
+    }
+
+    /// Serializes the block, padded with zeros up to the MPQ header
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(self.header_offset as usize);
+        bytes.extend_from_slice(&USER_DATA_SIGNATURE.to_le_bytes());
+        bytes.extend_from_slice(&self.user_data_size.to_le_bytes());
+        bytes.extend_from_slice(&self.header_offset.to_le_bytes());
+        bytes.extend_from_slice(&(self.content.len() as u32).to_le_bytes());
+        bytes.extend_from_slice(&self.content);
+        if bytes.len() < self.header_offset as usize {
+            bytes.resize(self.header_offset as usize, 0);
+        }
+        bytes
+    }
+}
+
+#[test]
+fn test_user_data_roundtrip() {
+    let user_data = UserData::new(b"launcher v1");
+    assert_eq!(user_data.header_offset, 0x200);
+    assert_eq!(user_data.user_data_size, 0x200 - USER_DATA_HEADER_SIZE);
+
+    let bytes = user_data.to_bytes();
+    assert_eq!(bytes.len(), 0x200);
+    assert_eq!(&bytes[..4], b"MPQ\x1B");
+    assert_eq!(UserData::parse(&bytes), Some(user_data));
+    assert_eq!(UserData::parse(b"MPQ\x1A"), None);
+}
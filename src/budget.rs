@@ -0,0 +1,387 @@
+//! Map size budget for `budget` and `--max-size`.
+//!
+//! The size that counts is the one of the compacted map: the real file size of
+//! a compacted map, or an estimate from the stored sizes and the tables when
+//! replaced files left gaps. Sizes are counted like Warcraft III does, so the
+//! 8 MB limit of old patches is 8 MiB.
+//!
+//! Imports are files below `war3mapImported\`, the default path of the import
+//! manager. One is reported as possibly unreferenced when its path appears in
+//! no script, object data, sound definition, model, map info, trigger string
+//! or UI file: those refer to imports by their full path, but a path built at
+//! runtime isn't found this way.
+
+use failure::{format_err, Error};
+use stormlib::{FileEntry, OpenArchiveFlags};
+
+use std::cmp::Reverse;
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::Path;
+
+const IMPORT_PREFIX: &str = "war3mapimported\\";
+
+/// Extensions of the files searched for references to imports: scripts,
+/// object data, sound definitions, models, `war3map.w3i` with its loading
+/// screen, `war3map.wts` and the data and frame definitions of custom UIs
+const REFERRERS: &[&str] = &[
+    "j", "lua", "w3u", "w3t", "w3b", "w3d", "w3a", "w3h", "w3q", "w3s", "mdx", "mdl", "w3i", "wts",
+    "txt", "slk", "fdf", "toc",
+];
+
+/// Size of a hash or block table entry
+const TABLE_ENTRY_SIZE: u64 = 16;
+
+/// Parses sizes like `8MB`, `128M`, `7.5 MiB` or `8388608`
+pub fn parse_size(value: &str) -> Result<u64, Error> {
+    let value = value.trim();
+    let split = value
+        .find(|c: char| !c.is_ascii_digit() && c != '.')
+        .unwrap_or(value.len());
+    let (number, unit) = value.split_at(split);
+    let multiplier: u64 = match unit.trim().to_lowercase().as_str() {
+        "" | "b" => 1,
+        "k" | "kb" | "kib" => 1 << 10,
+        "m" | "mb" | "mib" => 1 << 20,
+        "g" | "gb" | "gib" => 1 << 30,
+        _ => return Err(format_err!("invalid size: {}", value)),
+    };
+    let number: f64 = number
+        .parse()
+        .map_err(|_| format_err!("invalid size: {}", value))?;
+    Ok((number * multiplier as f64) as u64)
+}
+
+pub fn format_size(size: u64) -> String {
+    if size < 1 << 20 {
+        format!("{} bytes", size)
+    } else {
+        format!("{} bytes ({:.2} MB)", size, size as f64 / (1 << 20) as f64)
+    }
+}
+
+/// Files sharing an extension
+#[derive(Debug, Clone, PartialEq)]
+pub struct Extension {
+    pub extension:  String,
+    pub count:      usize,
+    pub size:       u64,
+    pub compressed: u64,
+}
+
+#[derive(Debug)]
+pub struct Report {
+    pub file_size:      u64,
+    /// Size once compacted, at most the file size
+    pub compacted_size: u64,
+    /// Largest files by stored size
+    pub largest:        Vec<FileEntry>,
+    /// Largest stored size first
+    pub extensions:     Vec<Extension>,
+    pub unreferenced:   Vec<FileEntry>,
+}
+
+impl Report {
+    /// Fails when the compacted map is larger than `budget`
+    pub fn check(&self, budget: u64) -> Result<(), Error> {
+        if self.compacted_size > budget {
+            return Err(format_err!(
+                "map is {} bytes, {} bytes over the budget of {} bytes",
+                self.compacted_size,
+                self.compacted_size - budget,
+                budget
+            ));
+        }
+        Ok(())
+    }
+}
+
+/// Analyzes the map at `mpq`, listing the `top` largest files
+pub fn analyze(mpq: &Path, top: usize) -> Result<Report, Error> {
+    let file_size = fs::metadata(mpq)?.len();
+    let mut file = fs::File::open(mpq)?;
+    let (header, _) =
+        stormlib::native::MpqHeader::find(&mut file, OpenArchiveFlags::MPQ_OPEN_NO_FLAG)?;
+
+    let mut ar = stormlib::Archive::open(mpq, OpenArchiveFlags::MPQ_OPEN_READ_ONLY)?;
+    let mut entries = ar.list()?;
+    let stored: u64 = entries.iter().map(|e| e.compressed_size as u64).sum();
+    let tables = (header.hash_table_size as u64 + entries.len() as u64) * TABLE_ENTRY_SIZE;
+    let compacted_size = file_size.min(header.offset + header.header_size as u64 + stored + tables);
+
+    let mut unreferenced = Vec::new();
+    if entries.iter().any(is_import) {
+        let mut files = Vec::new();
+        for entry in entries
+            .iter()
+            .filter(|e| !e.is_internal() && !e.is_unnamed())
+        {
+            if !is_referrer(&entry.name) {
+                files.push((entry.name.clone(), Vec::new()));
+                continue;
+            }
+            // files that can't be read can't be searched either
+            if let Ok(data) = ar
+                .open_file_locale(&entry.name, entry.locale)
+                .and_then(|mut f| f.read_all())
+            {
+                files.push((entry.name.clone(), data));
+            }
+        }
+        let names = find_unreferenced(&files);
+        unreferenced = entries
+            .iter()
+            .filter(|e| names.contains(&e.name))
+            .cloned()
+            .collect();
+        unreferenced.sort_by_key(|e| (Reverse(e.compressed_size), e.name.to_lowercase()));
+    }
+
+    let extensions = extensions(&entries);
+    entries.sort_by_key(|e| (Reverse(e.compressed_size), e.name.to_lowercase()));
+    entries.truncate(top);
+    Ok(Report {
+        file_size,
+        compacted_size,
+        largest: entries,
+        extensions,
+        unreferenced,
+    })
+}
+
+fn is_import(entry: &FileEntry) -> bool {
+    entry.name.to_lowercase().starts_with(IMPORT_PREFIX)
+}
+
+/// Whether a file may refer to imports, see `REFERRERS`
+fn is_referrer(name: &str) -> bool {
+    match name.rsplit_once('.') {
+        Some((_, extension)) => REFERRERS.iter().any(|r| r.eq_ignore_ascii_case(extension)),
+        None => false,
+    }
+}
+
+pub fn extensions(entries: &[FileEntry]) -> Vec<Extension> {
+    let mut by_extension: BTreeMap<String, Extension> = BTreeMap::new();
+    for entry in entries {
+        let file_name = entry.name.rsplit('\\').next().unwrap_or_default();
+        let extension = match file_name.rfind('.') {
+            Some(dot) if dot > 0 => file_name[dot + 1..].to_lowercase(),
+            _ => String::new(),
+        };
+        let stats = by_extension
+            .entry(extension.clone())
+            .or_insert_with(|| Extension {
+                extension,
+                count: 0,
+                size: 0,
+                compressed: 0,
+            });
+        stats.count += 1;
+        stats.size += entry.file_size as u64;
+        stats.compressed += entry.compressed_size as u64;
+    }
+    let mut extensions: Vec<Extension> = by_extension.into_values().collect();
+    extensions.sort_by_key(|e| Reverse(e.compressed));
+    extensions
+}
+
+/// Names of the imports whose path is found in none of the other `files`,
+/// only the files that may refer to imports being searched
+pub fn find_unreferenced(files: &[(String, Vec<u8>)]) -> Vec<String> {
+    let contents: Vec<(usize, String)> = files
+        .iter()
+        .enumerate()
+        .filter(|(_, (name, _))| is_referrer(name))
+        .map(|(index, (_, data))| (index, String::from_utf8_lossy(data).to_lowercase()))
+        .collect();
+    files
+        .iter()
+        .enumerate()
+        .filter(|(_, (name, _))| name.to_lowercase().starts_with(IMPORT_PREFIX))
+        .filter(|&(index, (name, _))| {
+            let needles = references(name);
+            !contents
+                .iter()
+                .filter(|&&(other, _)| other != index)
+                .any(|(_, content)| needles.iter().any(|n| content.contains(n.as_str())))
+        })
+        .map(|(_, (name, _))| name.clone())
+        .collect()
+}
+
+/// Ways other files spell the path of an import
+fn references(name: &str) -> Vec<String> {
+    let name = name.to_lowercase();
+    let mut paths = vec![name.clone()];
+    // object data names models .mdl even though the game loads the .mdx
+    if let Some(stem) = name.strip_suffix(".mdx") {
+        paths.push(format!("{}.mdl", stem));
+    }
+    let mut needles = Vec::new();
+    for path in paths {
+        // script strings escape the separator
+        needles.push(path.replace('\\', "\\\\"));
+        needles.push(path.replace('\\', "/"));
+        needles.push(path);
+    }
+    needles
+}
+
+pub fn render(report: &Report, budget: Option<u64>) -> String {
+    let mut out = String::new();
+    out.push_str(&format!(
+        "map size:        {}\n",
+        format_size(report.file_size)
+    ));
+    if report.compacted_size < report.file_size {
+        out.push_str(&format!(
+            "compacted size:  {}\n",
+            format_size(report.compacted_size)
+        ));
+    }
+    if let Some(budget) = budget {
+        out.push_str(&format!("budget:          {}\n", format_size(budget)));
+        if report.compacted_size > budget {
+            out.push_str(&format!(
+                "over budget by:  {}\n",
+                format_size(report.compacted_size - budget)
+            ));
+        } else {
+            out.push_str(&format!(
+                "left:            {}\n",
+                format_size(budget - report.compacted_size)
+            ));
+        }
+    }
+
+    out.push_str("\nLargest files\n");
+    for e in &report.largest {
+        out.push_str(&format!(
+            "  {:>10}  {:>10}  {:>6}  {}\n",
+            e.compressed_size,
+            e.file_size,
+            percent(e.compressed_size as u64, e.file_size as u64),
+            e.name
+        ));
+    }
+
+    out.push_str("\nBy extension\n");
+    for e in &report.extensions {
+        let extension = if e.extension.is_empty() {
+            "(none)"
+        } else {
+            &e.extension
+        };
+        out.push_str(&format!(
+            "  {:<8}  {:>5} files  {:>10}  {:>10}  {:>6}\n",
+            extension,
+            e.count,
+            e.compressed,
+            e.size,
+            percent(e.compressed, e.size)
+        ));
+    }
+
+    if !report.unreferenced.is_empty() {
+        let size: u64 = report
+            .unreferenced
+            .iter()
+            .map(|e| e.compressed_size as u64)
+            .sum();
+        out.push_str(&format!(
+            "\nImports not referenced by other files, {} stored\n",
+            format_size(size)
+        ));
+        for e in &report.unreferenced {
+            out.push_str(&format!("  {:>10}  {}\n", e.compressed_size, e.name));
+        }
+    }
+    out
+}
+
+fn percent(compressed: u64, size: u64) -> String {
+    if size == 0 {
+        "-".to_string()
+    } else {
+        format!("{:.1}%", compressed as f64 * 100.0 / size as f64)
+    }
+}
+
+#[test]
+fn test_budget() {
+    assert_eq!(parse_size("8MB").unwrap(), 8 * 1024 * 1024);
+    assert_eq!(parse_size("128 M").unwrap(), 128 * 1024 * 1024);
+    assert_eq!(parse_size("1.5KiB").unwrap(), 1536);
+    assert_eq!(parse_size("4096").unwrap(), 4096);
+    assert!(parse_size("8 parsecs").is_err());
+    assert!(parse_size("MB").is_err());
+
+    let entry = |name: &str, file_size, compressed_size| FileEntry {
+        name: name.to_string(),
+        hash_index: 0,
+        block_index: 0,
+        file_size,
+        compressed_size,
+        flags: stormlib::FileFlags::empty(),
+        file_time: 0,
+        locale: 0,
+    };
+    let extensions = extensions(&[
+        entry("war3map.j", 100, 40),
+        entry("war3mapImported\\a.MDX", 1000, 900),
+        entry("war3mapImported\\b.mdx", 500, 400),
+        entry("README", 10, 10),
+    ]);
+    assert_eq!(extensions[0].extension, "mdx");
+    assert_eq!(extensions[0].count, 2);
+    assert_eq!(extensions[0].compressed, 1300);
+    assert_eq!(extensions[1].extension, "j");
+    assert_eq!(extensions[2].extension, "");
+
+    let files = vec![
+        (
+            "war3map.j".to_string(),
+            b"call AddSpecialEffect(\"war3mapImported\\\\Fire.mdx\", x, y)".to_vec(),
+        ),
+        (
+            "war3map.w3u".to_string(),
+            b"umdlwar3mapImported\\Hero.mdl\0".to_vec(),
+        ),
+        (
+            "war3mapImported\\Hero.mdx".to_string(),
+            b"TEXSwar3mapImported\\hero.blp\0".to_vec(),
+        ),
+        (
+            "war3map.w3i".to_string(),
+            b"\x1c\0\0\0war3mapImported\\Loading.blp\0".to_vec(),
+        ),
+        (
+            "war3mapImported\\ui.toc".to_string(),
+            b"war3mapImported\\menu.fdf\r\n".to_vec(),
+        ),
+        ("war3mapImported\\menu.fdf".to_string(), Vec::new()),
+        ("war3mapImported\\loading.blp".to_string(), Vec::new()),
+        ("war3mapImported\\fire.mdx".to_string(), Vec::new()),
+        ("war3mapImported\\hero.blp".to_string(), Vec::new()),
+        ("war3mapImported\\old.blp".to_string(), Vec::new()),
+        // only files that may refer to imports are searched
+        (
+            "war3mapImported\\cover.blp".to_string(),
+            b"war3mapImported\\old.blp".to_vec(),
+        ),
+        (
+            "war3mapImported\\self.mdx".to_string(),
+            b"war3mapImported\\self.mdx".to_vec(),
+        ),
+    ];
+    assert_eq!(
+        find_unreferenced(&files),
+        vec![
+            "war3mapImported\\ui.toc",
+            "war3mapImported\\old.blp",
+            "war3mapImported\\cover.blp",
+            "war3mapImported\\self.mdx"
+        ]
+    );
+}
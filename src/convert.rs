@@ -0,0 +1,348 @@
+//! Conversion between MPQ archives and zip or tar archives, for `export` and
+//! `import`.
+//!
+//! MPQ names use `\` as separator and become `/` separated paths. Files of a
+//! locale other than the neutral one are put below `@<LCID>/`, e.g.
+//! `@1031/units\human.txt` is the German `units\human.txt`. File times come
+//! from `(attributes)`, the other internal files are left out since `import`
+//! generates them again. Data ahead of the MPQ archive, like the header of a
+//! Warcraft III map, is kept as `(header)`.
+
+use failure::{format_err, Error};
+use stormlib::native::ArchiveBuilder;
+use stormlib::{FileOptions, OpenArchiveFlags};
+
+use std::convert::TryFrom;
+use std::fs;
+use std::io::{Read, Write};
+use std::path::Path;
+
+pub const HEADER_NAME: &str = "(header)";
+
+/// FILETIME of the Unix epoch, in 100 ns intervals since 1601
+const UNIX_EPOCH_FILETIME: u64 = 116_444_736_000_000_000;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Format {
+    Zip,
+    Tar,
+}
+
+impl Format {
+    pub fn from_path(path: &Path) -> Result<Self, Error> {
+        match path.extension().and_then(|ext| ext.to_str()) {
+            Some(ext) if ext.eq_ignore_ascii_case("zip") => Ok(Format::Zip),
+            Some(ext) if ext.eq_ignore_ascii_case("tar") => Ok(Format::Tar),
+            _ => Err(format_err!(
+                "{}: expected a .zip or .tar file",
+                path.display()
+            )),
+        }
+    }
+}
+
+/// File of a zip or tar archive
+#[derive(Debug, Clone, PartialEq)]
+pub struct Entry {
+    pub path:  String,
+    pub data:  Vec<u8>,
+    /// Seconds since the Unix epoch
+    pub mtime: Option<u64>,
+}
+
+/// Path of an MPQ file in a zip or tar archive
+pub fn entry_path(name: &str, locale: u32) -> String {
+    let path = name.replace('\\', "/");
+    if locale == 0 {
+        path
+    } else {
+        format!("@{}/{}", locale, path)
+    }
+}
+
+/// MPQ name and locale of a path written by `entry_path`
+pub fn parse_path(path: &str) -> (String, u16) {
+    if let Some(rest) = path.strip_prefix('@') {
+        if let Some((locale, name)) = rest.split_once('/') {
+            if let Ok(locale) = locale.parse() {
+                return (name.replace('/', "\\"), locale);
+            }
+        }
+    }
+    (path.replace('/', "\\"), 0)
+}
+
+pub fn unix_time(file_time: u64) -> Option<u64> {
+    file_time
+        .checked_sub(UNIX_EPOCH_FILETIME)
+        .filter(|_| file_time != 0)
+        .map(|t| t / 10_000_000)
+}
+
+pub fn file_time(unix_time: u64) -> u64 {
+    unix_time * 10_000_000 + UNIX_EPOCH_FILETIME
+}
+
+/// Writes the files of `mpq` to a zip or tar archive
+pub fn export(mpq: &Path, to: &Path) -> Result<usize, Error> {
+    let format = Format::from_path(to)?;
+    let mut entries = Vec::new();
+
+    let mut file = fs::File::open(mpq)?;
+    let (header, _) =
+        stormlib::native::MpqHeader::find(&mut file, OpenArchiveFlags::MPQ_OPEN_NO_FLAG)?;
+    if header.offset > 0 {
+        let mut prefix = Vec::new();
+        fs::File::open(mpq)?
+            .take(header.offset)
+            .read_to_end(&mut prefix)?;
+        entries.push(Entry {
+            path:  HEADER_NAME.to_string(),
+            data:  prefix,
+            mtime: None,
+        });
+    }
+
+    let mut ar = stormlib::Archive::open(mpq, OpenArchiveFlags::MPQ_OPEN_READ_ONLY)?;
+    let mut files: Vec<_> = ar
+        .list()?
+        .into_iter()
+        .filter(|entry| !entry.is_internal())
+        .collect();
+    files.sort_by_key(|entry| (entry.name.to_lowercase(), entry.locale));
+    for entry in files {
+        let data = ar.open_file_locale(&entry.name, entry.locale)?.read_all()?;
+        entries.push(Entry {
+            path: entry_path(&entry.name, entry.locale),
+            data,
+            mtime: unix_time(entry.file_time),
+        });
+    }
+
+    let count = entries.len();
+    write_entries(format, to, &entries)?;
+    Ok(count)
+}
+
+/// Builds an MPQ archive from the files of a zip or tar archive
+pub fn import(from: &Path, to: &Path) -> Result<usize, Error> {
+    let entries = read_entries(Format::from_path(from)?, from)?;
+    let mut builder = ArchiveBuilder::new();
+    let mut count = 0;
+    for entry in entries {
+        if entry.path == HEADER_NAME {
+            builder = builder.prefix(&entry.data);
+            continue;
+        }
+        let (name, locale) = parse_path(&entry.path);
+        let options = FileOptions {
+            locale,
+            ..FileOptions::default()
+        };
+        builder.add(&name, entry.data, options);
+        if let Some(mtime) = entry.mtime {
+            builder.set_file_time(&name, locale, file_time(mtime));
+        }
+        count += 1;
+    }
+    builder.write(to)?;
+    Ok(count)
+}
+
+pub fn write_entries(format: Format, to: &Path, entries: &[Entry]) -> Result<(), Error> {
+    let file = fs::File::create(to)?;
+    match format {
+        Format::Zip => {
+            let mut zip = zip::ZipWriter::new(file);
+            for entry in entries {
+                let mut options = zip::write::SimpleFileOptions::default()
+                    .compression_method(zip::CompressionMethod::Deflated);
+                if let Some(time) = entry.mtime.and_then(zip_time) {
+                    options = options.last_modified_time(time);
+                }
+                zip.start_file(entry.path.as_str(), options)?;
+                zip.write_all(&entry.data)?;
+            }
+            zip.finish()?;
+        }
+        Format::Tar => {
+            let mut tar = tar::Builder::new(file);
+            for entry in entries {
+                let mut header = tar::Header::new_gnu();
+                header.set_size(entry.data.len() as u64);
+                header.set_mode(0o644);
+                header.set_mtime(entry.mtime.unwrap_or(0));
+                tar.append_data(&mut header, &entry.path, entry.data.as_slice())?;
+            }
+            tar.into_inner()?.flush()?;
+        }
+    }
+    Ok(())
+}
+
+pub fn read_entries(format: Format, from: &Path) -> Result<Vec<Entry>, Error> {
+    let file = fs::File::open(from)?;
+    let mut entries = Vec::new();
+    match format {
+        Format::Zip => {
+            let mut zip = zip::ZipArchive::new(file)?;
+            for i in 0..zip.len() {
+                let mut file = zip.by_index(i)?;
+                if file.is_dir() {
+                    continue;
+                }
+                let mut data = Vec::with_capacity(file.size() as usize);
+                file.read_to_end(&mut data)?;
+                entries.push(Entry {
+                    path: file.name().to_string(),
+                    data,
+                    mtime: file.last_modified().and_then(unix_from_zip),
+                });
+            }
+        }
+        Format::Tar => {
+            let mut tar = tar::Archive::new(file);
+            for file in tar.entries()? {
+                let mut file = file?;
+                if !file.header().entry_type().is_file() {
+                    continue;
+                }
+                let path = file
+                    .path()?
+                    .to_str()
+                    .ok_or_else(|| format_err!("non UTF-8 path in {}", from.display()))?
+                    .to_string();
+                let mtime = file.header().mtime()?;
+                let mut data = Vec::new();
+                file.read_to_end(&mut data)?;
+                entries.push(Entry {
+                    path,
+                    data,
+                    mtime: Some(mtime).filter(|&t| t != 0),
+                });
+            }
+        }
+    }
+    Ok(entries)
+}
+
+/// Zip time of a Unix time, zip times start in 1980
+fn zip_time(unix_time: u64) -> Option<zip::DateTime> {
+    let days = (unix_time / 86400) as i64;
+    let secs = unix_time % 86400;
+    let (year, month, day) = civil_from_days(days);
+    zip::DateTime::from_date_and_time(
+        u16::try_from(year).ok()?,
+        month as u8,
+        day as u8,
+        (secs / 3600) as u8,
+        (secs / 60 % 60) as u8,
+        (secs % 60) as u8,
+    )
+    .ok()
+}
+
+/// Unix time of a zip time; files written without a time have the earliest
+/// one, 1980-01-01 00:00
+fn unix_from_zip(time: zip::DateTime) -> Option<u64> {
+    if time == zip::DateTime::default() {
+        return None;
+    }
+    let days = days_from_civil(time.year() as i64, time.month() as u32, time.day() as u32);
+    Some(
+        days as u64 * 86400
+            + time.hour() as u64 * 3600
+            + time.minute() as u64 * 60
+            + time.second() as u64,
+    )
+}
+
+/// Date of a number of days since 1970-01-01, in the proleptic Gregorian calendar
+fn civil_from_days(days: i64) -> (i64, u32, u32) {
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = (doy - (153 * mp + 2) / 5 + 1) as u32;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 } as u32;
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+    (year, month, day)
+}
+
+fn days_from_civil(year: i64, month: u32, day: u32) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let yoe = year.rem_euclid(400);
+    let month = month as i64;
+    let doy = (153 * (if month > 2 { month - 3 } else { month + 9 }) + 2) / 5 + day as i64 - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    era * 146_097 + doe - 719_468
+}
+
+#[test]
+fn test_convert() {
+    use stormlib::native::testing::TempDir;
+
+    assert_eq!(
+        entry_path("war3mapImported\\a.mdx", 0),
+        "war3mapImported/a.mdx"
+    );
+    assert_eq!(
+        entry_path("units\\human.txt", 1031),
+        "@1031/units/human.txt"
+    );
+    assert_eq!(
+        parse_path("@1031/units/human.txt"),
+        ("units\\human.txt".to_string(), 1031)
+    );
+    assert_eq!(parse_path("@home/a.txt"), ("@home\\a.txt".to_string(), 0));
+    assert_eq!(civil_from_days(0), (1970, 1, 1));
+    assert_eq!(civil_from_days(19_723), (2024, 1, 1));
+    assert_eq!(days_from_civil(2024, 2, 29), 19_782);
+    assert_eq!(unix_time(file_time(1_700_000_000)), Some(1_700_000_000));
+    assert_eq!(unix_time(0), None);
+
+    let dir = TempDir::new("mopaq-convert");
+    let entries = vec![
+        Entry {
+            path:  HEADER_NAME.to_string(),
+            data:  b"HM3W".to_vec(),
+            mtime: None,
+        },
+        Entry {
+            path:  "war3map.j".to_string(),
+            data:  b"function main takes nothing returns nothing".to_vec(),
+            mtime: Some(1_700_000_000),
+        },
+        Entry {
+            path:  "@1031/units/human.txt".to_string(),
+            data:  b"[hfoo]".to_vec(),
+            mtime: Some(1_600_000_002),
+        },
+    ];
+    for format in [Format::Zip, Format::Tar] {
+        let path = dir.join(if format == Format::Zip {
+            "map.zip"
+        } else {
+            "map.tar"
+        });
+        write_entries(format, &path, &entries).unwrap();
+        assert_eq!(read_entries(format, &path).unwrap(), entries);
+
+        let map = dir.join("map.w3x");
+        assert_eq!(import(&path, &map).unwrap(), 2);
+        assert!(fs::read(&map).unwrap().starts_with(b"HM3W"));
+        let mut ar =
+            stormlib::native::Archive::open(&map, OpenArchiveFlags::MPQ_OPEN_NO_FLAG).unwrap();
+        let human = ar
+            .list()
+            .unwrap()
+            .into_iter()
+            .find(|e| e.name == "units\\human.txt")
+            .unwrap();
+        assert_eq!(human.locale, 1031);
+        assert_eq!(unix_time(human.file_time), Some(1_600_000_002));
+    }
+}
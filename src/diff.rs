@@ -0,0 +1,250 @@
+//! Comparison of two archives entry by entry.
+//!
+//! Entries are matched by name, case-insensitively like MPQ lookups, and by
+//! locale, and compared by CRC32 and MD5 of their content. Size, stored size and flags
+//! are reported as well so recompressed files show up even when their content
+//! is the same. The special files describing the archive are left out since
+//! they change with every other file.
+
+use failure::Error;
+use md5::{Digest, Md5};
+use similar::TextDiff;
+use stormlib::{Archive, FileEntry};
+
+use crate::list;
+
+use std::collections::BTreeMap;
+
+/// Extensions of files shown with an inline diff
+const TEXT_EXTENSIONS: &[&str] = &["j", "lua", "txt", "slk", "fdf"];
+
+/// Entry of an archive with the checksums of its content
+#[derive(Debug, Clone)]
+pub struct Entry {
+    pub name:            String,
+    pub locale:          u32,
+    pub size:            u32,
+    pub compressed_size: u32,
+    pub flags:           String,
+    pub crc32:           u32,
+    pub md5:             [u8; 16],
+    /// Content of text files, kept for the inline diff
+    pub text:            Option<String>,
+}
+
+impl Entry {
+    fn read(ar: &mut Archive, entry: &FileEntry) -> Result<Self, Error> {
+        let data = ar.open_file_locale(&entry.name, entry.locale)?.read_all()?;
+        let text = if is_text(&entry.name) {
+            Some(String::from_utf8_lossy(&data).into_owned())
+        } else {
+            None
+        };
+        Ok(Entry {
+            name: entry.name.clone(),
+            locale: entry.locale,
+            size: entry.file_size,
+            compressed_size: entry.compressed_size,
+            flags: list::flags(entry.flags),
+            crc32: crc32fast::hash(&data),
+            md5: Md5::digest(&data).into(),
+            text,
+        })
+    }
+
+    /// Name shown in the output, with the locale unless it is the neutral one
+    fn label(&self) -> String {
+        if self.locale == 0 {
+            self.name.clone()
+        } else {
+            format!("{} (locale {})", self.name, self.locale)
+        }
+    }
+
+    fn same_content(&self, other: &Entry) -> bool {
+        self.crc32 == other.crc32 && self.md5 == other.md5 && self.size == other.size
+    }
+
+    fn same_storage(&self, other: &Entry) -> bool {
+        self.compressed_size == other.compressed_size && self.flags == other.flags
+    }
+}
+
+#[derive(Debug)]
+pub enum Change {
+    Added(Entry),
+    Removed(Entry),
+    /// Same name, different content or storage
+    Changed {
+        old: Entry,
+        new: Entry,
+    },
+}
+
+/// Entries of an archive by lowercase name and locale
+pub type Snapshot = BTreeMap<(String, u32), Entry>;
+
+/// Reads every entry of an archive
+pub fn snapshot(ar: &mut Archive) -> Result<Snapshot, Error> {
+    let mut entries = BTreeMap::new();
+    for entry in ar.list()? {
+        if entry.name.starts_with('(') && entry.name.ends_with(')') {
+            continue;
+        }
+        entries.insert(
+            (entry.name.to_lowercase(), entry.locale),
+            Entry::read(ar, &entry)?,
+        );
+    }
+    Ok(entries)
+}
+
+pub fn compare(old: Snapshot, mut new: Snapshot) -> Vec<Change> {
+    let mut changes = Vec::new();
+    for (key, old) in old {
+        match new.remove(&key) {
+            None => changes.push(Change::Removed(old)),
+            Some(new) if old.same_content(&new) && old.same_storage(&new) => {}
+            Some(new) => changes.push(Change::Changed { old, new }),
+        }
+    }
+    changes.extend(new.into_values().map(Change::Added));
+    changes.sort_by_key(|change| match change {
+        Change::Added(entry) | Change::Removed(entry) | Change::Changed { new: entry, .. } => {
+            (entry.name.to_lowercase(), entry.locale)
+        }
+    });
+    changes
+}
+
+/// Renders the changes, one line per file followed by inline diffs of text files
+pub fn render(changes: &[Change], old_label: &str, new_label: &str) -> String {
+    let mut out = String::new();
+    let (mut added, mut removed, mut changed) = (0, 0, 0);
+    for change in changes {
+        match change {
+            Change::Added(entry) => {
+                added += 1;
+                out.push_str(&format!("+ {} ({} bytes)\n", entry.label(), entry.size));
+            }
+            Change::Removed(entry) => {
+                removed += 1;
+                out.push_str(&format!("- {} ({} bytes)\n", entry.label(), entry.size));
+            }
+            Change::Changed { old, new } => {
+                changed += 1;
+                let mut details = Vec::new();
+                if old.size != new.size {
+                    details.push(format!("size {} -> {}", old.size, new.size));
+                }
+                if old.compressed_size != new.compressed_size {
+                    details.push(format!(
+                        "compressed {} -> {}",
+                        old.compressed_size, new.compressed_size
+                    ));
+                }
+                if old.flags != new.flags {
+                    details.push(format!("flags {} -> {}", old.flags, new.flags));
+                }
+                if !old.same_content(new) && old.size == new.size {
+                    details.push("content".to_string());
+                }
+                // `M` for content changes, `C` when only the storage differs
+                let marker = if old.same_content(new) { 'C' } else { 'M' };
+                out.push_str(&format!(
+                    "{} {}: {}\n",
+                    marker,
+                    new.label(),
+                    details.join(", ")
+                ));
+
+                if let (Some(old_text), Some(new_text)) = (&old.text, &new.text) {
+                    if old_text != new_text {
+                        out.push_str(
+                            &TextDiff::from_lines(old_text, new_text)
+                                .unified_diff()
+                                .context_radius(3)
+                                .header(
+                                    &format!("{}/{}", old_label, old.label()),
+                                    &format!("{}/{}", new_label, new.label()),
+                                )
+                                .to_string(),
+                        );
+                    }
+                }
+            }
+        }
+    }
+    out.push_str(&format!(
+        "{} added, {} removed, {} changed\n",
+        added, removed, changed
+    ));
+    out
+}
+
+fn is_text(name: &str) -> bool {
+    name.rsplit('.')
+        .next()
+        .map(|ext| TEXT_EXTENSIONS.iter().any(|t| t.eq_ignore_ascii_case(ext)))
+        .unwrap_or(false)
+}
+
+#[test]
+fn test_compare() {
+    let localized = |name: &str, locale, data: &str, compressed_size| Entry {
+        name: name.to_string(),
+        locale,
+        size: data.len() as u32,
+        compressed_size,
+        flags: "C".to_string(),
+        crc32: crc32fast::hash(data.as_bytes()),
+        md5: Md5::digest(data.as_bytes()).into(),
+        text: if is_text(name) {
+            Some(data.to_string())
+        } else {
+            None
+        },
+    };
+    let entry = |name: &str, data: &str, compressed_size| localized(name, 0, data, compressed_size);
+    let snapshot = |entries: Vec<Entry>| -> Snapshot {
+        entries
+            .into_iter()
+            .map(|e| ((e.name.to_lowercase(), e.locale), e))
+            .collect()
+    };
+    let old = snapshot(vec![
+        entry("war3map.j", "function main\nendfunction\n", 20),
+        entry("old.blp", "blp", 3),
+        entry("war3map.w3e", "terrain", 5),
+        entry("same.mdx", "model", 5),
+        entry("war3map.wts", "STRING 1\n{\nHello\n}\n", 10),
+        localized("war3map.wts", 1031, "STRING 1\n{\nHallo\n}\n", 10),
+    ]);
+    let new = snapshot(vec![
+        entry(
+            "War3map.j",
+            "function main\n  call Init()\nendfunction\n",
+            30,
+        ),
+        entry("war3map.w3e", "terrain", 4),
+        entry("same.mdx", "model", 5),
+        entry("new.mdx", "model", 5),
+        entry("war3map.wts", "STRING 1\n{\nHello\n}\n", 10),
+        localized("war3map.wts", 1031, "STRING 1\n{\nGuten Tag\n}\n", 14),
+    ]);
+
+    let changes = compare(old, new);
+    assert_eq!(changes.len(), 5);
+    let out = render(&changes, "a", "b");
+    let lines: Vec<&str> = out.lines().collect();
+    assert_eq!(lines[0], "+ new.mdx (5 bytes)");
+    assert_eq!(lines[1], "- old.blp (3 bytes)");
+    assert_eq!(lines[2], "M War3map.j: size 26 -> 40, compressed 20 -> 30");
+    assert!(out.contains("+  call Init()\n"));
+    assert!(out.contains("C war3map.w3e: compressed 5 -> 4\n"));
+    assert!(out.contains("M war3map.wts (locale 1031): size 19 -> 23, compressed 10 -> 14\n"));
+    assert!(!out.contains("M war3map.wts:"));
+    assert!(out.ends_with("1 added, 1 removed, 3 changed\n"));
+    assert!(is_text("Units\\UnitData.SLK"));
+    assert!(!is_text("war3map.w3e"));
+}
@@ -0,0 +1,124 @@
+//! Parallel compression for `generate`.
+//!
+//! Files are read, compressed and encrypted on every core with rayon, then
+//! handed to the built-in writer as stored, which only has to lay them out.
+//! Files encrypted with a key depending on their position can only be encoded
+//! once it is known, so those are left to the writer.
+
+use failure::{format_err, Error};
+use rayon::prelude::*;
+use stormlib::native::{ArchiveBuilder, RawFile};
+use stormlib::{Compression, FileOptions};
+
+use crate::filelist::File;
+use crate::manifest::Manifest;
+
+use std::path::Path;
+
+/// Options of every file, as `generate` stores them
+pub fn options(files: &[File], defaults: FileOptions, manifest: &Manifest) -> Vec<FileOptions> {
+    files
+        .iter()
+        .map(|f| f.overrides.apply(manifest.options(&f.name, defaults)))
+        .collect()
+}
+
+/// Whether the built-in writer can store files with these options; it has no
+/// ADPCM encoder
+pub fn is_supported(options: &[FileOptions]) -> bool {
+    !options.iter().any(|o| {
+        matches!(
+            o.compression,
+            Compression::AdpcmMono | Compression::AdpcmStereo
+        )
+    })
+}
+
+/// Builds `output` from `files`, returning how many were encoded in parallel
+pub fn build(
+    files: &[File],
+    options: &[FileOptions],
+    output: &Path,
+    mut builder: ArchiveBuilder,
+) -> Result<usize, Error> {
+    let sector_size = builder.sector_size();
+    let encoded: Vec<Option<RawFile>> = files
+        .par_iter()
+        .zip(options)
+        .map(|(f, options)| {
+            if options.encrypt && options.fix_key {
+                return Ok(None);
+            }
+            let data = f.read()?;
+            RawFile::encode(&f.name, &data, *options, sector_size)
+                .map(Some)
+                .map_err(|e| format_err!("{}: {}", f.name, e))
+        })
+        .collect::<Result<_, Error>>()?;
+
+    let mut parallel = 0;
+    for ((f, options), raw) in files.iter().zip(options).zip(encoded) {
+        match raw {
+            Some(raw) => {
+                builder.add_raw(&f.name, raw, options.locale)?;
+                parallel += 1;
+            }
+            None => builder.add(&f.name, f.read()?.into_owned(), *options),
+        }
+    }
+    builder.write(output)?;
+    Ok(parallel)
+}
+
+#[test]
+fn test_build() {
+    use stormlib::native::testing::TempDir;
+    use stormlib::OpenArchiveFlags;
+
+    let dir = TempDir::new("mopaq-encode");
+    let script = b"call Init()\n".repeat(2000);
+    let model: Vec<u8> = (0..50_000u32).map(|i| (i % 251) as u8).collect();
+    std::fs::write(dir.join("war3map.j"), &script).unwrap();
+    let files = vec![
+        File::from_path(
+            "war3map.j".to_string(),
+            dir.join("war3map.j").to_str().unwrap().to_string(),
+        ),
+        File {
+            name:      "war3mapImported\\a.mdx".to_string(),
+            source:    crate::filelist::Source::Data(model.clone()),
+            overrides: Default::default(),
+        },
+    ];
+    let manifest = Manifest::parse(
+        r#"
+        [[rules]]
+        glob = "*.mdx"
+        encrypt = true
+        fix_key = true
+        "#,
+    )
+    .unwrap();
+    let options = options(&files, FileOptions::default(), &manifest);
+    assert!(is_supported(&options));
+
+    let output = dir.join("map.w3x");
+    assert_eq!(
+        build(&files, &options, &output, ArchiveBuilder::new()).unwrap(),
+        1
+    );
+    let mut ar =
+        stormlib::native::Archive::open(&output, OpenArchiveFlags::MPQ_OPEN_NO_FLAG).unwrap();
+    assert_eq!(
+        ar.open_file("war3map.j").unwrap().read_all().unwrap(),
+        script
+    );
+    assert_eq!(
+        ar.open_file("war3mapImported\\a.mdx")
+            .unwrap()
+            .read_all()
+            .unwrap(),
+        model
+    );
+    drop(ar);
+}
@@ -0,0 +1,221 @@
+//! Extraction of archive entries into a directory tree.
+//!
+//! Requested names are either exact file names or globs (see `pattern`). With
+//! nothing requested the whole archive is extracted, including entries only
+//! known by their `File00000012.xxx` pseudo name, but without the special
+//! files like `(listfile)` which only describe the archive itself. Every
+//! locale of a file is extracted, the ones other than the neutral locale
+//! below `@<LCID>/` like `export` names them.
+
+use failure::{format_err, Error};
+use stormlib::Archive;
+
+use crate::convert;
+use crate::pattern::{self, Patterns};
+
+use std::collections::BTreeSet;
+use std::fs;
+use std::path::{Component, Path, PathBuf};
+
+/// What to do when an output file already exists
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Existing {
+    Fail,
+    Overwrite,
+    Skip,
+}
+
+/// Archive entry picked for extraction
+#[derive(Debug, Clone, PartialEq)]
+pub struct Selected {
+    pub name:   String,
+    pub locale: u32,
+}
+
+impl Selected {
+    /// Name for the log, with the locale unless it is the neutral one
+    pub fn label(&self) -> String {
+        if self.locale == 0 {
+            self.name.clone()
+        } else {
+            format!("{} (locale {})", self.name, self.locale)
+        }
+    }
+}
+
+/// Outcome of an extraction run
+#[derive(Debug, Default)]
+pub struct Extraction {
+    pub extracted: Vec<String>,
+    /// Files left alone because the output already existed
+    pub skipped:   Vec<String>,
+}
+
+/// Resolves the requested names and globs to archive entries, every locale
+/// of a name in turn with the neutral one first
+///
+/// Returns the matched entries and the requests that matched nothing.
+pub fn select(ar: &mut Archive, requested: &[&str]) -> Result<(Vec<Selected>, Vec<String>), Error> {
+    let mut entries = ar.list()?;
+    entries.sort_by_key(|e| e.locale);
+    let mut seen = BTreeSet::new();
+    let mut selected = Vec::new();
+    let mut missing = Vec::new();
+
+    if requested.is_empty() {
+        for entry in entries {
+            if !is_special(&entry.name) && seen.insert((entry.name.to_lowercase(), entry.locale)) {
+                selected.push(Selected {
+                    name:   entry.name,
+                    locale: entry.locale,
+                });
+            }
+        }
+        return Ok((selected, missing));
+    }
+
+    for request in requested {
+        let mut found: Vec<Selected> = if pattern::is_glob(request) {
+            let patterns = Patterns::new(&[request])?;
+            entries
+                .iter()
+                .filter(|e| !is_special(&e.name) && patterns.is_match(&e.name))
+                .map(|e| Selected {
+                    name:   e.name.clone(),
+                    locale: e.locale,
+                })
+                .collect()
+        } else {
+            entries
+                .iter()
+                .filter(|e| e.name.eq_ignore_ascii_case(request))
+                .map(|e| Selected {
+                    name:   request.to_string(),
+                    locale: e.locale,
+                })
+                .collect()
+        };
+        // names missing from the listfile are still found by their hash
+        if found.is_empty() && !pattern::is_glob(request) && ar.has_file(request)? {
+            found.push(Selected {
+                name:   request.to_string(),
+                locale: 0,
+            });
+        }
+
+        if found.is_empty() {
+            missing.push(request.to_string());
+        }
+        for entry in found {
+            if seen.insert((entry.name.to_lowercase(), entry.locale)) {
+                selected.push(entry);
+            }
+        }
+    }
+    Ok((selected, missing))
+}
+
+/// Output paths of `selected` below `root`, turning `\` separators into
+/// directories
+pub fn targets(selected: &[Selected], root: &Path) -> Result<Vec<(Selected, PathBuf)>, Error> {
+    selected
+        .iter()
+        .map(|entry| {
+            let path = convert::entry_path(&entry.name, entry.locale);
+            match output_path(root, &path) {
+                Some(path) => Ok((entry.clone(), path)),
+                None => Err(format_err!(
+                    "refusing to extract {} outside the output directory",
+                    entry.name
+                )),
+            }
+        })
+        .collect()
+}
+
+/// Extracts every entry of `targets` to its path
+pub fn extract(
+    ar: &mut Archive,
+    targets: &[(Selected, PathBuf)],
+    existing: Existing,
+) -> Result<Extraction, Error> {
+    // fail before writing anything rather than leaving a half extracted tree
+    if existing == Existing::Fail {
+        if let Some((_, path)) = targets.iter().find(|(_, path)| path.exists()) {
+            return Err(format_err!(
+                "{} already exists, pass --overwrite or --skip-existing",
+                path.display()
+            ));
+        }
+    }
+
+    let mut extraction = Extraction::default();
+    for (entry, path) in targets {
+        if existing == Existing::Skip && path.exists() {
+            extraction.skipped.push(entry.label());
+            continue;
+        }
+        let data = ar.open_file_locale(&entry.name, entry.locale)?.read_all()?;
+        write_file(path, &data)?;
+        extraction.extracted.push(entry.label());
+    }
+    Ok(extraction)
+}
+
+pub fn write_file(path: &Path, data: &[u8]) -> Result<(), Error> {
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    fs::write(path, data)?;
+    Ok(())
+}
+
+/// Where `name` ends up below `root`, `None` if it would escape `root`
+pub fn output_path(root: &Path, name: &str) -> Option<PathBuf> {
+    let relative = PathBuf::from(name.replace('\\', "/"));
+    let mut path = root.to_path_buf();
+    for component in relative.components() {
+        match component {
+            Component::Normal(part) => path.push(part),
+            Component::CurDir => {}
+            _ => return None,
+        }
+    }
+    if path == root {
+        None
+    } else {
+        Some(path)
+    }
+}
+
+/// Files describing the archive itself rather than the map
+fn is_special(name: &str) -> bool {
+    name.starts_with('(') && name.ends_with(')')
+}
+
+#[test]
+fn test_output_path() {
+    let root = Path::new("out");
+    assert_eq!(
+        output_path(root, "war3mapImported\\units\\a.mdx"),
+        Some(PathBuf::from("out/war3mapImported/units/a.mdx"))
+    );
+    assert_eq!(
+        output_path(root, "File00000012.xxx"),
+        Some(PathBuf::from("out/File00000012.xxx"))
+    );
+    assert_eq!(output_path(root, "..\\evil.j"), None);
+    assert_eq!(output_path(root, "/etc/passwd"), None);
+    assert_eq!(output_path(root, ""), None);
+    let german = Selected {
+        name:   "units\\human.txt".to_string(),
+        locale: 1031,
+    };
+    assert_eq!(
+        targets(std::slice::from_ref(&german), root).unwrap(),
+        vec![(german.clone(), PathBuf::from("out/@1031/units/human.txt"))]
+    );
+    assert_eq!(german.label(), "units\\human.txt (locale 1031)");
+    assert!(is_special("(listfile)"));
+    assert!(!is_special("war3map.j"));
+}
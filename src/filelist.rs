@@ -0,0 +1,249 @@
+//! JSON file lists accepted by `--input`.
+//!
+//! The versioned format is an object listing one entry per file:
+//!
+//! ```json
+//! {
+//!   "version": 1,
+//!   "files": [
+//!     { "name": "war3map.j", "path": "build/war3map.j", "encrypt": false },
+//!     { "name": "war3mapExtra.txt", "content": "[Extra]\n" },
+//!     { "name": "war3mapMap.blp", "base64": "QkxQMQ==", "compression": "none" },
+//!     { "name": "war3mapPreview.tga", "path": "preview.tga", "optional": true }
+//!   ]
+//! }
+//! ```
+//!
+//! Each entry takes its content from exactly one of `path`, `content` or
+//! `base64`. Optional entries whose path doesn't exist are skipped. The
+//! legacy format, an array of `[name, path]` rows, is still accepted.
+
+use base64::Engine;
+use failure::{format_err, Error};
+use serde::Deserialize;
+use serde_json::Value;
+use stormlib::{Compression, FileOptions};
+
+use crate::manifest;
+
+use std::borrow::Cow;
+use std::fs;
+use std::path::Path;
+
+pub const VERSION: u64 = 1;
+
+/// File to pack
+#[derive(Debug, Clone, PartialEq)]
+pub struct File {
+    pub name:      String,
+    pub source:    Source,
+    pub overrides: Overrides,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Source {
+    Path(String),
+    Data(Vec<u8>),
+}
+
+/// Options set by a file list entry, taking precedence over the manifest
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Overrides {
+    pub compression: Option<Compression>,
+    pub encrypt:     Option<bool>,
+    pub locale:      Option<u16>,
+}
+
+impl Overrides {
+    pub fn apply(&self, mut options: FileOptions) -> FileOptions {
+        if let Some(compression) = self.compression {
+            options.compression = compression;
+        }
+        if let Some(encrypt) = self.encrypt {
+            options.encrypt = encrypt;
+        }
+        if let Some(locale) = self.locale {
+            options.locale = locale;
+        }
+        options
+    }
+}
+
+impl File {
+    pub fn from_path(name: String, path: String) -> Self {
+        File {
+            name,
+            source: Source::Path(path),
+            overrides: Overrides::default(),
+        }
+    }
+
+    pub fn read(&self) -> Result<Cow<'_, [u8]>, Error> {
+        Ok(match &self.source {
+            Source::Path(path) => Cow::Owned(fs::read(path)?),
+            Source::Data(data) => Cow::Borrowed(data),
+        })
+    }
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Entry {
+    name:        String,
+    path:        Option<String>,
+    content:     Option<String>,
+    base64:      Option<String>,
+    compression: Option<String>,
+    encrypt:     Option<bool>,
+    locale:      Option<u16>,
+    #[serde(default)]
+    optional:    bool,
+}
+
+/// Parses a file list, reporting problems with the index and name of the entry
+pub fn parse(json: &str) -> Result<Vec<File>, Error> {
+    let value: Value = serde_json::from_str(json)?;
+    match value {
+        Value::Array(rows) => parse_legacy(rows),
+        Value::Object(mut object) => {
+            match object.remove("version") {
+                Some(Value::Number(n)) if n.as_u64() == Some(VERSION) => {}
+                Some(other) => return Err(format_err!("unsupported file list version {}", other)),
+                None => return Err(format_err!("file list has no `version`")),
+            }
+            let entries = match object.remove("files") {
+                Some(Value::Array(entries)) => entries,
+                _ => return Err(format_err!("file list has no `files` array")),
+            };
+            if let Some(key) = object.keys().next() {
+                return Err(format_err!("unknown file list field `{}`", key));
+            }
+
+            let mut files = Vec::with_capacity(entries.len());
+            for (index, entry) in entries.into_iter().enumerate() {
+                let entry: Entry = serde_json::from_value(entry)
+                    .map_err(|e| format_err!("files[{}]: {}", index, e))?;
+                let name = entry.name.clone();
+                if let Some(file) = parse_entry(entry)
+                    .map_err(|e| format_err!("files[{}] ({}): {}", index, name, e))?
+                {
+                    files.push(file);
+                }
+            }
+            Ok(files)
+        }
+        _ => Err(format_err!("file list must be an object or an array")),
+    }
+}
+
+fn parse_legacy(rows: Vec<Value>) -> Result<Vec<File>, Error> {
+    rows.into_iter()
+        .enumerate()
+        .map(
+            |(index, row)| match serde_json::from_value::<Vec<String>>(row) {
+                // elements past the path were ignored by earlier versions
+                Ok(row) if row.len() >= 2 => {
+                    let mut row = row.into_iter();
+                    Ok(File::from_path(row.next().unwrap(), row.next().unwrap()))
+                }
+                Ok(row) => Err(format_err!(
+                    "[{}]: expected [name, path], found {} elements",
+                    index,
+                    row.len()
+                )),
+                Err(e) => Err(format_err!("[{}]: {}", index, e)),
+            },
+        )
+        .collect()
+}
+
+fn parse_entry(entry: Entry) -> Result<Option<File>, Error> {
+    if entry.name.is_empty() {
+        return Err(format_err!("`name` is empty"));
+    }
+    let source = match (entry.path, entry.content, entry.base64) {
+        (Some(path), None, None) => {
+            if !Path::new(&path).is_file() {
+                if entry.optional {
+                    return Ok(None);
+                }
+                return Err(format_err!("{} does not exist", path));
+            }
+            Source::Path(path)
+        }
+        (None, Some(content), None) => Source::Data(content.into_bytes()),
+        (None, None, Some(encoded)) => Source::Data(
+            base64::engine::general_purpose::STANDARD
+                .decode(encoded.as_bytes())
+                .map_err(|e| format_err!("invalid `base64`: {}", e))?,
+        ),
+        _ => {
+            return Err(format_err!(
+                "exactly one of `path`, `content` or `base64` is required"
+            ))
+        }
+    };
+    Ok(Some(File {
+        name: entry.name,
+        source,
+        overrides: Overrides {
+            compression: entry
+                .compression
+                .as_deref()
+                .map(manifest::parse_compression)
+                .transpose()?,
+            encrypt:     entry.encrypt,
+            locale:      entry.locale,
+        },
+    }))
+}
+
+#[test]
+fn test_parse() {
+    let legacy = parse(r#"[["war3map.j", "src/war3map.j"]]"#).unwrap();
+    assert_eq!(
+        legacy,
+        vec![File::from_path(
+            "war3map.j".to_string(),
+            "src/war3map.j".to_string()
+        )]
+    );
+    let extra = parse(r#"[["war3map.j", "src/war3map.j", "unused"]]"#).unwrap();
+    assert_eq!(extra, legacy);
+    let err = parse(r#"[["war3map.j", "a"], ["war3map.w3i"]]"#).unwrap_err();
+    assert_eq!(
+        err.to_string(),
+        "[1]: expected [name, path], found 1 elements"
+    );
+
+    let files = parse(
+        r#"{
+            "version": 1,
+            "files": [
+                { "name": "war3mapExtra.txt", "content": "[Extra]", "encrypt": false },
+                { "name": "a.blp", "base64": "QkxQMQ==", "compression": "bzip2", "locale": 1033 },
+                { "name": "preview.tga", "path": "does/not/exist.tga", "optional": true }
+            ]
+        }"#,
+    )
+    .unwrap();
+    assert_eq!(files.len(), 2);
+    assert_eq!(files[0].read().unwrap().as_ref(), b"[Extra]");
+    assert_eq!(files[1].source, Source::Data(b"BLP1".to_vec()));
+    let options = files[1].overrides.apply(FileOptions::default());
+    assert_eq!(options.compression, Compression::Bzip2);
+    assert_eq!(options.locale, 1033);
+
+    let error = |json: &str| parse(json).unwrap_err().to_string();
+    assert_eq!(
+        error(r#"{"version": 1, "files": [{"name": "a", "path": "missing.j"}]}"#),
+        "files[0] (a): missing.j does not exist"
+    );
+    assert_eq!(
+        error(r#"{"version": 1, "files": [{"name": "a", "content": "", "base64": ""}]}"#),
+        "files[0] (a): exactly one of `path`, `content` or `base64` is required"
+    );
+    assert!(error(r#"{"version": 1, "files": [{"path": "a"}]}"#)
+        .starts_with("files[0]: missing field `name`"));
+    assert!(error(r#"{"version": 2, "files": []}"#).starts_with("unsupported file list version"));
+}
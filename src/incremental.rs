@@ -0,0 +1,297 @@
+//! Incremental rebuilds for `generate --incremental`.
+//!
+//! A cache next to the output, `<output>.cache.json`, records for every
+//! archive name the source path, its modification time and size, the MD5 of
+//! its content, the options it was stored with and the MD5 of its stored
+//! bytes. On the next build a file whose options and content are unchanged
+//! is copied as stored from the previous output instead of being compressed
+//! again, once its stored bytes are found unchanged too; they are never
+//! decoded. Sources with the same time and size are not even read. The archive is written with the built-in
+//! deterministic writer, so an incremental build produces the same bytes as a
+//! full one.
+
+use failure::{format_err, Error};
+use md5::{Digest, Md5};
+use serde::{Deserialize, Serialize};
+use stormlib::native::{self, ArchiveBuilder, RawFile};
+use stormlib::{Compression, FileOptions, OpenArchiveFlags};
+
+use crate::filelist::{File, Source};
+use crate::manifest::Manifest;
+use crate::temp::TempFile;
+
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::UNIX_EPOCH;
+
+const CACHE_VERSION: u32 = 2;
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct Cache {
+    version: u32,
+    files:   BTreeMap<String, CacheEntry>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+struct CacheEntry {
+    path:    Option<String>,
+    /// Seconds and nanoseconds since the Unix epoch
+    mtime:   Option<(u64, u32)>,
+    size:    u64,
+    md5:     String,
+    options: String,
+    /// See `stored_hash`
+    stored:  String,
+}
+
+impl Cache {
+    /// Loads the cache, an unreadable or outdated cache is simply ignored
+    fn load(path: &Path) -> Self {
+        fs::read_to_string(path)
+            .ok()
+            .and_then(|json| serde_json::from_str::<Cache>(&json).ok())
+            .filter(|cache| cache.version == CACHE_VERSION)
+            .unwrap_or_default()
+    }
+}
+
+/// Outcome of an incremental build
+#[derive(Debug, Default, PartialEq)]
+pub struct Stats {
+    /// Files copied as stored in the previous output
+    pub reused:  usize,
+    /// Files compressed again
+    pub encoded: usize,
+}
+
+pub fn cache_path(output: &Path) -> PathBuf {
+    let mut path = output.as_os_str().to_owned();
+    path.push(".cache.json");
+    PathBuf::from(path)
+}
+
+/// Builds `output` from `files`, reusing what the previous build left there
+///
+/// The new archive only replaces `output` once `accept` approves it, a
+/// rejected build leaves the output and its cache as they were.
+pub fn build<F>(
+    files: &[File],
+    output: &Path,
+    mut builder: ArchiveBuilder,
+    defaults: FileOptions,
+    manifest: &Manifest,
+    accept: F,
+) -> Result<Stats, Error>
+where
+    F: FnOnce(&Path) -> Result<(), Error>,
+{
+    let cache_path = cache_path(output);
+    let old_cache = Cache::load(&cache_path);
+    let mut previous = if output.is_file() && !old_cache.files.is_empty() {
+        native::Archive::open(output, OpenArchiveFlags::MPQ_OPEN_NO_FLAG).ok()
+    } else {
+        None
+    };
+
+    let mut cache = Cache {
+        version: CACHE_VERSION,
+        files:   BTreeMap::new(),
+    };
+    let mut stats = Stats::default();
+    for f in files {
+        let options = f.overrides.apply(manifest.options(&f.name, defaults));
+        if let Compression::AdpcmMono | Compression::AdpcmStereo = options.compression {
+            return Err(format_err!(
+                "{}: ADPCM compression is not available in incremental builds",
+                f.name
+            ));
+        }
+
+        let (path, mtime, size) = match &f.source {
+            Source::Path(path) => {
+                let metadata = fs::metadata(path)?;
+                let mtime = metadata.modified()?.duration_since(UNIX_EPOCH)?;
+                let mtime = (mtime.as_secs(), mtime.subsec_nanos());
+                (Some(path.clone()), Some(mtime), metadata.len())
+            }
+            Source::Data(data) => (None, None, data.len() as u64),
+        };
+        let signature = format!("{:?}", options);
+        let old = old_cache
+            .files
+            .get(&f.name)
+            .filter(|e| e.options == signature && e.path == path);
+
+        let mut data = None;
+        let md5 = match old {
+            Some(old) if mtime.is_some() && old.mtime == mtime && old.size == size => {
+                old.md5.clone()
+            }
+            _ => {
+                let content = f.read()?.into_owned();
+                let md5 = hex(&Md5::digest(&content));
+                data = Some(content);
+                md5
+            }
+        };
+
+        // files encrypted with a key depending on their position can't be moved
+        let unchanged = old.filter(|old| old.md5 == md5 && !options.fix_key);
+        let reused = match (previous.as_mut(), unchanged) {
+            // the previous output may have been changed since it was cached
+            (Some(previous), Some(old)) => match previous.read_stored(&f.name) {
+                Ok(raw) if stored_hash(&raw) == old.stored => {
+                    builder.add_raw(&f.name, raw, options.locale).is_ok()
+                }
+                _ => false,
+            },
+            _ => false,
+        };
+        if reused {
+            stats.reused += 1;
+        } else {
+            let data = match data {
+                Some(data) => data,
+                None => f.read()?.into_owned(),
+            };
+            builder.add(&f.name, data, options);
+            stats.encoded += 1;
+        }
+
+        cache.files.insert(
+            f.name.clone(),
+            CacheEntry {
+                path,
+                mtime,
+                size,
+                md5,
+                options: signature,
+                stored: String::new(),
+            },
+        );
+    }
+    drop(previous);
+
+    // the previous output is still being read from until the new one is complete
+    let temp = TempFile::next_to(output)?;
+    builder.write(temp.path())?;
+    accept(temp.path())?;
+    temp.persist(output)?;
+
+    let mut written = native::Archive::open(output, OpenArchiveFlags::MPQ_OPEN_NO_FLAG)?;
+    for (name, entry) in cache.files.iter_mut() {
+        entry.stored = stored_hash(&written.read_stored(name)?);
+    }
+    fs::write(&cache_path, serde_json::to_string_pretty(&cache)?)?;
+    Ok(stats)
+}
+
+/// MD5 of a file as stored and of its flags; its position doesn't matter as
+/// files whose key depends on it are never copied
+fn stored_hash(raw: &RawFile) -> String {
+    let mut md5 = Md5::new();
+    md5.update(&raw.data);
+    md5.update(raw.flags.bits().to_le_bytes());
+    md5.update(raw.file_size.to_le_bytes());
+    hex(&md5.finalize())
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+#[test]
+fn test_incremental_build() {
+    use stormlib::native::testing::TempDir;
+
+    let dir = TempDir::new("mopaq-incremental");
+    let source = |name: &str| dir.join(name).to_str().unwrap().to_string();
+    fs::write(source("war3map.j"), b"call Init()\n".repeat(2000)).unwrap();
+    fs::write(source("a.mdx"), vec![7u8; 50_000]).unwrap();
+    let files = vec![
+        File::from_path("war3map.j".to_string(), source("war3map.j")),
+        File::from_path("war3mapImported\\a.mdx".to_string(), source("a.mdx")),
+    ];
+    let output = dir.join("map.w3x");
+    let manifest = Manifest::default();
+    let build = |output: &Path| {
+        build(
+            &files,
+            output,
+            ArchiveBuilder::new(),
+            FileOptions::default(),
+            &manifest,
+            |_| Ok(()),
+        )
+        .unwrap()
+    };
+
+    assert_eq!(
+        build(&output),
+        Stats {
+            reused:  0,
+            encoded: 2,
+        }
+    );
+    assert_eq!(
+        build(&output),
+        Stats {
+            reused:  2,
+            encoded: 0,
+        }
+    );
+    let names: Vec<_> = fs::read_dir(dir.path())
+        .unwrap()
+        .map(|e| e.unwrap().file_name().into_string().unwrap())
+        .filter(|name| name.starts_with("map.") || name.starts_with(".map."))
+        .collect();
+    assert_eq!(names.len(), 2, "{:?}", names);
+
+    fs::write(source("war3map.j"), b"call Main()\n".repeat(2000)).unwrap();
+    assert_eq!(
+        build(&output),
+        Stats {
+            reused:  1,
+            encoded: 1,
+        }
+    );
+    let fresh = dir.join("fresh.w3x");
+    build(&fresh);
+    assert_eq!(fs::read(&output).unwrap(), fs::read(&fresh).unwrap());
+
+    // an output edited by another tool isn't trusted for files it changed
+    let mut edited = ArchiveBuilder::new();
+    edited.add(
+        "war3map.j",
+        b"call Other()\n".to_vec(),
+        FileOptions::default(),
+    );
+    edited.add(
+        "war3mapImported\\a.mdx",
+        vec![7u8; 50_000],
+        FileOptions::default(),
+    );
+    edited.write(&output).unwrap();
+    assert_eq!(
+        build(&output),
+        Stats {
+            reused:  1,
+            encoded: 1,
+        }
+    );
+    assert_eq!(fs::read(&output).unwrap(), fs::read(&fresh).unwrap());
+
+    // a rejected build leaves the output as it was
+    fs::write(source("war3map.j"), b"call Rejected()\n".repeat(2000)).unwrap();
+    let rejected = self::build(
+        &files,
+        &output,
+        ArchiveBuilder::new(),
+        FileOptions::default(),
+        &manifest,
+        |_| Err(format_err!("over budget")),
+    );
+    assert!(rejected.is_err());
+    assert_eq!(fs::read(&output).unwrap(), fs::read(&fresh).unwrap());
+}
@@ -0,0 +1,232 @@
+//! Parsers for the map metadata shown by the `info` subcommand: the 512-byte
+//! `HM3W` header in front of the MPQ and the start of `war3map.w3i`.
+
+use std::convert::TryInto;
+
+/// Header Warcraft III writes in front of the MPQ of every map
+#[derive(Debug, Clone, PartialEq)]
+pub struct MapHeader {
+    pub name:        String,
+    pub flags:       u32,
+    pub max_players: u32,
+}
+
+impl MapHeader {
+    pub const SIZE: usize = 0x200;
+
+    pub fn parse(data: &[u8]) -> Option<Self> {
+        if data.get(..4)? != b"HM3W" {
+            return None;
+        }
+        let mut reader = Reader::new(data.get(8..)?);
+        Some(MapHeader {
+            name:        reader.string()?,
+            flags:       reader.u32()?,
+            max_players: reader.u32()?,
+        })
+    }
+}
+
+/// Summary of `war3map.w3i`, only the fields up to the player count
+#[derive(Debug, Clone, PartialEq)]
+pub struct MapInfo {
+    pub format_version: u32,
+    pub saves:          u32,
+    pub editor_version: u32,
+    /// Major, minor, patch and build of the game that saved the map, v27+
+    pub game_version:   Option<[u32; 4]>,
+    pub name:           String,
+    pub author:         String,
+    pub flags:          u32,
+    pub tileset:        char,
+    pub players:        u32,
+}
+
+impl MapInfo {
+    pub fn parse(data: &[u8]) -> Option<Self> {
+        let mut r = Reader::new(data);
+        let format_version = r.u32()?;
+        if format_version < 18 {
+            return None;
+        }
+        let saves = r.u32()?;
+        let editor_version = r.u32()?;
+        let game_version = if format_version >= 27 {
+            Some([r.u32()?, r.u32()?, r.u32()?, r.u32()?])
+        } else {
+            None
+        };
+        let name = r.string()?;
+        let author = r.string()?;
+        r.string()?; // description
+        r.string()?; // recommended players
+        r.skip(8 * 4 + 4 * 4 + 2 * 4)?; // camera bounds and playable area
+        let flags = r.u32()?;
+        let tileset = r.u8()? as char;
+
+        if format_version >= 25 {
+            r.skip(4)?; // loading screen number
+            for _ in 0..4 {
+                r.string()?; // loading screen model, text, title, subtitle
+            }
+            r.skip(4)?; // game data set
+            for _ in 0..4 {
+                r.string()?; // prologue path, text, title, subtitle
+            }
+            r.skip(4 + 4 + 4 + 4 + 4)?; // terrain fog
+            r.skip(4)?; // weather
+            r.string()?; // sound environment
+            r.skip(1 + 4)?; // light environment and water color
+            if format_version >= 28 {
+                r.skip(4)?; // script language
+            }
+            if format_version >= 31 {
+                r.skip(4 + 4)?; // supported modes and game data version
+            }
+        } else {
+            r.skip(4)?;
+            for _ in 0..3 {
+                r.string()?;
+            }
+            r.skip(4)?;
+            for _ in 0..3 {
+                r.string()?;
+            }
+        }
+
+        Some(MapInfo {
+            format_version,
+            saves,
+            editor_version,
+            game_version,
+            name,
+            author,
+            flags,
+            tileset,
+            players: r.u32()?,
+        })
+    }
+}
+
+pub fn tileset_name(tileset: char) -> Option<&'static str> {
+    Some(match tileset {
+        'A' => "Ashenvale",
+        'B' => "Barrens",
+        'C' => "Felwood",
+        'D' => "Dungeon",
+        'F' => "Lordaeron Fall",
+        'G' => "Underground",
+        'I' => "Icecrown Glacier",
+        'J' => "Dalaran Ruins",
+        'K' => "Black Citadel",
+        'L' => "Lordaeron Summer",
+        'N' => "Northrend",
+        'O' => "Outland",
+        'Q' => "Village Fall",
+        'V' => "Village",
+        'W' => "Lordaeron Winter",
+        'X' => "Dalaran",
+        'Y' => "Cityscape",
+        'Z' => "Sunken Ruins",
+        _ => return None,
+    })
+}
+
+struct Reader<'a> {
+    data: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+    fn new(data: &'a [u8]) -> Self {
+        Reader { data }
+    }
+
+    fn take(&mut self, len: usize) -> Option<&'a [u8]> {
+        if self.data.len() < len {
+            return None;
+        }
+        let (head, tail) = self.data.split_at(len);
+        self.data = tail;
+        Some(head)
+    }
+
+    fn skip(&mut self, len: usize) -> Option<()> {
+        self.take(len).map(|_| ())
+    }
+
+    fn u8(&mut self) -> Option<u8> {
+        self.take(1).map(|b| b[0])
+    }
+
+    fn u32(&mut self) -> Option<u32> {
+        self.take(4)
+            .map(|b| u32::from_le_bytes(b.try_into().unwrap()))
+    }
+
+    fn string(&mut self) -> Option<String> {
+        let len = self.data.iter().position(|&b| b == 0)?;
+        let s = String::from_utf8_lossy(self.take(len)?).into_owned();
+        self.skip(1)?;
+        Some(s)
+    }
+}
+
+#[test]
+fn test_parse_info() {
+    let mut header = b"HM3W\0\0\0\0Test Map\0".to_vec();
+    header.extend_from_slice(&0x8004u32.to_le_bytes());
+    header.extend_from_slice(&12u32.to_le_bytes());
+    header.resize(MapHeader::SIZE, 0);
+    assert_eq!(
+        MapHeader::parse(&header),
+        Some(MapHeader {
+            name:        "Test Map".to_string(),
+            flags:       0x8004,
+            max_players: 12,
+        })
+    );
+    assert_eq!(MapHeader::parse(b"MPQ\x1a"), None);
+
+    let dword = |v: u32| v.to_le_bytes().to_vec();
+    let w3i = |format_version: u32| {
+        let mut w3i = [dword(format_version), dword(3), dword(6072)].concat();
+        if format_version >= 27 {
+            w3i.extend([1, 29, 0, 6072].iter().flat_map(|&v| dword(v)));
+        }
+        w3i.extend_from_slice(b"TRIGSTR_001\0Author\0Description\0Any\0");
+        w3i.extend(vec![0; 8 * 4 + 4 * 4 + 2 * 4]);
+        w3i.extend(dword(0x10));
+        w3i.push(b'L');
+        w3i.extend(dword(0));
+        w3i.extend_from_slice(b"\0\0\0\0");
+        w3i.extend(dword(0));
+        w3i.extend_from_slice(b"\0\0\0\0");
+        w3i.extend(vec![0; 24]);
+        w3i.extend_from_slice(b"\0");
+        w3i.extend(vec![0; 5]);
+        if format_version >= 28 {
+            w3i.extend(dword(1));
+        }
+        if format_version >= 31 {
+            w3i.extend(dword(3));
+            w3i.extend(dword(1));
+        }
+        w3i.extend(dword(4));
+        w3i
+    };
+
+    let info = MapInfo::parse(&w3i(25)).unwrap();
+    assert_eq!(info.editor_version, 6072);
+    assert_eq!(info.game_version, None);
+    assert_eq!(info.name, "TRIGSTR_001");
+    assert_eq!(info.tileset, 'L');
+    assert_eq!(info.players, 4);
+    assert_eq!(tileset_name(info.tileset), Some("Lordaeron Summer"));
+    assert_eq!(MapInfo::parse(&w3i(25)[..40]), None);
+    // the supported modes only come with format 31
+    for &format_version in &[28, 29, 31] {
+        let info = MapInfo::parse(&w3i(format_version)).unwrap();
+        assert_eq!(info.game_version, Some([1, 29, 0, 6072]));
+        assert_eq!(info.players, 4);
+    }
+}
@@ -0,0 +1,238 @@
+//! Listing of archive entries as an aligned table, JSON or CSV.
+
+use failure::{format_err, Error};
+use serde_json::json;
+use stormlib::{FileEntry, FileFlags};
+
+use std::cmp::Reverse;
+use std::str::FromStr;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Format {
+    Table,
+    Json,
+    Csv,
+}
+
+impl FromStr for Format {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Error> {
+        match s {
+            "table" => Ok(Format::Table),
+            "json" => Ok(Format::Json),
+            "csv" => Ok(Format::Csv),
+            other => Err(format_err!("unknown format: {}", other)),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SortKey {
+    Name,
+    /// Largest files first
+    Size,
+    /// Largest stored size first
+    Compressed,
+}
+
+impl FromStr for SortKey {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Error> {
+        match s {
+            "name" => Ok(SortKey::Name),
+            "size" => Ok(SortKey::Size),
+            "compressed" => Ok(SortKey::Compressed),
+            other => Err(format_err!("unknown sort key: {}", other)),
+        }
+    }
+}
+
+pub fn sort(entries: &mut [FileEntry], key: SortKey) {
+    match key {
+        SortKey::Name => entries.sort_by_key(|e| e.name.to_lowercase()),
+        SortKey::Size => entries.sort_by_key(|e| (Reverse(e.file_size), e.name.to_lowercase())),
+        SortKey::Compressed => {
+            entries.sort_by_key(|e| (Reverse(e.compressed_size), e.name.to_lowercase()))
+        }
+    }
+}
+
+/// Short flag letters, in the spirit of MPQ editors
+pub fn flags(flags: FileFlags) -> String {
+    [
+        (FileFlags::IMPLODE, 'I'),
+        (FileFlags::COMPRESS, 'C'),
+        (FileFlags::ENCRYPTED, 'E'),
+        (FileFlags::FIX_KEY, 'K'),
+        (FileFlags::SINGLE_UNIT, 'S'),
+        (FileFlags::SECTOR_CRC, 'X'),
+        (FileFlags::PATCH_FILE, 'P'),
+        (FileFlags::DELETE_MARKER, 'D'),
+    ]
+    .iter()
+    .filter(|(flag, _)| flags.contains(*flag))
+    .map(|(_, letter)| *letter)
+    .collect()
+}
+
+/// Stored size relative to the real size, `None` for empty files
+pub fn ratio(entry: &FileEntry) -> Option<f64> {
+    if entry.file_size == 0 {
+        None
+    } else {
+        Some(entry.compressed_size as f64 / entry.file_size as f64)
+    }
+}
+
+pub fn render(entries: &[FileEntry], format: Format) -> String {
+    match format {
+        Format::Table => render_table(entries),
+        Format::Json => render_json(entries),
+        Format::Csv => render_csv(entries),
+    }
+}
+
+fn render_table(entries: &[FileEntry]) -> String {
+    let header = ["Name", "Size", "Compressed", "Ratio", "Flags", "Locale"];
+    let rows: Vec<[String; 6]> = entries
+        .iter()
+        .map(|e| {
+            [
+                e.name.clone(),
+                e.file_size.to_string(),
+                e.compressed_size.to_string(),
+                ratio(e)
+                    .map(|r| format!("{:.1}%", r * 100.0))
+                    .unwrap_or_else(|| "-".to_string()),
+                flags(e.flags),
+                e.locale.to_string(),
+            ]
+        })
+        .collect();
+
+    let mut widths: Vec<usize> = header.iter().map(|h| h.len()).collect();
+    for row in &rows {
+        for (width, cell) in widths.iter_mut().zip(row.iter()) {
+            *width = (*width).max(cell.chars().count());
+        }
+    }
+
+    let mut out = String::new();
+    let mut push_row = |cells: &[&str]| {
+        let line: Vec<String> = cells
+            .iter()
+            .zip(&widths)
+            .enumerate()
+            .map(|(i, (cell, width))| match i {
+                // numbers are right aligned
+                1..=3 => format!("{:>width$}", cell, width = width),
+                _ => format!("{:<width$}", cell, width = width),
+            })
+            .collect();
+        out.push_str(line.join("  ").trim_end());
+        out.push('\n');
+    };
+    push_row(&header);
+    for row in &rows {
+        push_row(&row.iter().map(String::as_str).collect::<Vec<_>>());
+    }
+
+    let size: u64 = entries.iter().map(|e| e.file_size as u64).sum();
+    let compressed: u64 = entries.iter().map(|e| e.compressed_size as u64).sum();
+    out.push_str(&format!(
+        "{} files, {} bytes, {} bytes stored\n",
+        entries.len(),
+        size,
+        compressed
+    ));
+    out
+}
+
+fn render_json(entries: &[FileEntry]) -> String {
+    let entries: Vec<serde_json::Value> = entries
+        .iter()
+        .map(|e| {
+            json!({
+                "name": e.name,
+                "size": e.file_size,
+                "compressed_size": e.compressed_size,
+                "ratio": ratio(e),
+                "flags": flags(e.flags),
+                "locale": e.locale,
+            })
+        })
+        .collect();
+    let mut out = serde_json::to_string_pretty(&entries).unwrap();
+    out.push('\n');
+    out
+}
+
+fn render_csv(entries: &[FileEntry]) -> String {
+    let mut out = String::from("name,size,compressed_size,ratio,flags,locale\n");
+    for e in entries {
+        out.push_str(&format!(
+            "{},{},{},{},{},{}\n",
+            csv_field(&e.name),
+            e.file_size,
+            e.compressed_size,
+            ratio(e).map(|r| format!("{:.4}", r)).unwrap_or_default(),
+            flags(e.flags),
+            e.locale
+        ));
+    }
+    out
+}
+
+fn csv_field(value: &str) -> String {
+    if value.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_string()
+    }
+}
+
+#[test]
+fn test_render() {
+    let entry = |name: &str, file_size, compressed_size, flags| FileEntry {
+        name: name.to_string(),
+        hash_index: 0,
+        block_index: 0,
+        file_size,
+        compressed_size,
+        flags,
+        file_time: 0,
+        locale: 0,
+    };
+    let mut entries = vec![
+        entry(
+            "war3map.j",
+            1000,
+            250,
+            FileFlags::COMPRESS | FileFlags::EXISTS,
+        ),
+        entry("a,b.txt", 0, 0, FileFlags::EXISTS),
+        entry(
+            "war3mapImported\\big.mdx",
+            5000,
+            4000,
+            FileFlags::COMPRESS | FileFlags::ENCRYPTED | FileFlags::FIX_KEY | FileFlags::EXISTS,
+        ),
+    ];
+    sort(&mut entries, SortKey::Size);
+    assert_eq!(entries[0].name, "war3mapImported\\big.mdx");
+    assert_eq!(flags(entries[0].flags), "CEK");
+
+    let csv = render(&entries, Format::Csv);
+    assert_eq!(csv.lines().nth(2).unwrap(), "war3map.j,1000,250,0.2500,C,0");
+    assert_eq!(csv.lines().nth(3).unwrap(), "\"a,b.txt\",0,0,,,0");
+
+    let table = render(&entries, Format::Table);
+    assert!(table.lines().nth(2).unwrap().contains(" 25.0%"));
+    assert!(table.ends_with("3 files, 6000 bytes, 4250 bytes stored\n"));
+
+    let json: serde_json::Value = serde_json::from_str(&render(&entries, Format::Json)).unwrap();
+    assert_eq!(json[1]["compressed_size"], 250);
+    assert!(json[2]["ratio"].is_null());
+}
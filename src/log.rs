@@ -0,0 +1,226 @@
+//! Progress and error output, as plain text or with `--log-format json`.
+//!
+//! Plain text goes to stdout like it always did. JSON logs are written to
+//! stderr, one object per line, so stdout keeps the output proper of commands
+//! like `cat`, `list` or a `--dry-run` plan:
+//!
+//! ```json
+//! {"event":"file","action":"extract","name":"war3map.j"}
+//! {"event":"file","action":"copy","name":"units\\a.mdx","source":"assets.mpq"}
+//! {"event":"info","message":"exported 12 files to map.zip"}
+//! {"event":"warning","message":"could not compact map.w3x: FileCorrupt"}
+//! {"event":"error","code":"not_found","exit_code":3,"path":"map.w3x","message":"FileNotFound","causes":[]}
+//! ```
+//!
+//! Errors are classified by their causes, each class exits with its own code.
+
+use failure::{Error, Fail};
+use serde_json::{json, Value};
+use stormlib::error::StormError;
+
+use std::fmt;
+use std::io;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+static JSON: AtomicBool = AtomicBool::new(false);
+
+pub fn set_json(json: bool) {
+    JSON.store(json, Ordering::Relaxed);
+}
+
+pub fn is_json() -> bool {
+    JSON.load(Ordering::Relaxed)
+}
+
+fn emit(event: Value) {
+    eprintln!("{}", event);
+}
+
+/// Reports what happened to a file of an archive, e.g. `extract` or `remove`
+pub fn file(action: &str, name: &str) {
+    file_from(action, name, None);
+}
+
+/// Like `file`, for files taken from another archive
+pub fn file_from(action: &str, name: &str, source: Option<&str>) {
+    if is_json() {
+        let mut event = json!({"event": "file", "action": action, "name": name});
+        if let Some(source) = source {
+            event["source"] = json!(source);
+        }
+        emit(event);
+        return;
+    }
+    let text = match action {
+        "keep" => format!("keep existing file {}", name),
+        "skip" => format!("skip existing file {}", name),
+        "missing" => format!("missing file {}", name),
+        "unnamed" => format!("skip unnamed file {}", name),
+        "unreadable" => format!("skip unreadable file {}", name),
+        action => format!("{} file {}", action, name),
+    };
+    match source {
+        Some(source) => println!("{} from {}", text, source),
+        None => println!("{}", text),
+    }
+}
+
+/// Reports a file written by `generate` or `pack`; the text output of those
+/// lists no files, so it is only logged as JSON
+pub fn added(name: &str) {
+    if is_json() {
+        file("add", name);
+    }
+}
+
+pub fn info(message: &str) {
+    if is_json() {
+        emit(json!({"event": "info", "message": message}));
+    } else {
+        println!("{}", message);
+    }
+}
+
+pub fn warn(message: &str) {
+    if is_json() {
+        emit(json!({"event": "warning", "message": message}));
+    } else {
+        println!("{}", message);
+    }
+}
+
+/// Kind of failure, telling which exit code the process ends with
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Class {
+    /// Anything not covered below
+    Other,
+    /// Invalid arguments
+    Usage,
+    /// An archive, a file in it or an input file doesn't exist
+    NotFound,
+    /// An archive or a file in it can't be read
+    Corrupt,
+    /// Reading or writing failed
+    Io,
+}
+
+impl Class {
+    pub fn code(self) -> &'static str {
+        match self {
+            Class::Other => "error",
+            Class::Usage => "usage",
+            Class::NotFound => "not_found",
+            Class::Corrupt => "corrupt",
+            Class::Io => "io",
+        }
+    }
+
+    pub fn exit_code(self) -> i32 {
+        match self {
+            Class::Other => 1,
+            Class::Usage => 2,
+            Class::NotFound => 3,
+            Class::Corrupt => 4,
+            Class::Io => 5,
+        }
+    }
+
+    /// Class of the first cause that has one
+    pub fn of(error: &Error) -> Class {
+        error
+            .iter_chain()
+            .find_map(|cause| {
+                if let Some(failure) = cause.downcast_ref::<Failure>() {
+                    Some(failure.class)
+                } else if let Some(e) = cause.downcast_ref::<StormError>() {
+                    Some(Class::of_storm(e))
+                } else {
+                    cause.downcast_ref::<io::Error>().map(Class::of_io)
+                }
+            })
+            .unwrap_or(Class::Other)
+    }
+
+    fn of_storm(error: &StormError) -> Class {
+        match error {
+            StormError::FileNotFound => Class::NotFound,
+            StormError::BadFormat
+            | StormError::FileCorrupt
+            | StormError::ChecksumError
+            | StormError::UnknownFileKey => Class::Corrupt,
+            StormError::AccessDenied | StormError::DiskFull => Class::Io,
+            StormError::Io(e) => Class::of_io(e),
+            _ => Class::Other,
+        }
+    }
+
+    fn of_io(error: &io::Error) -> Class {
+        match error.kind() {
+            io::ErrorKind::NotFound => Class::NotFound,
+            io::ErrorKind::InvalidData | io::ErrorKind::UnexpectedEof => Class::Corrupt,
+            _ => Class::Io,
+        }
+    }
+}
+
+/// Error raised by the CLI itself, with an explicit class
+#[derive(Debug)]
+pub struct Failure {
+    pub class:   Class,
+    pub message: String,
+}
+
+impl fmt::Display for Failure {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(&self.message)
+    }
+}
+
+impl Fail for Failure {}
+
+pub fn failure<S: Into<String>>(class: Class, message: S) -> Error {
+    Failure {
+        class,
+        message: message.into(),
+    }
+    .into()
+}
+
+/// Reports `error` about the archive at `path` and returns the exit code
+pub fn error(error: &Error, path: Option<&str>) -> i32 {
+    let class = Class::of(error);
+    if is_json() {
+        let causes: Vec<String> = error.iter_causes().map(|c| c.to_string()).collect();
+        emit(json!({
+            "event": "error",
+            "code": class.code(),
+            "exit_code": class.exit_code(),
+            "path": path,
+            "message": error.to_string(),
+            "causes": causes,
+        }));
+    } else {
+        println!("[ERROR] An error has occured. Error chain:");
+        println!("{}", error);
+        for cause in error.iter_causes() {
+            println!("{}", cause);
+        }
+    }
+    class.exit_code()
+}
+
+#[test]
+fn test_class() {
+    use failure::ResultExt;
+
+    let storm: Error = StormError::FileNotFound.into();
+    assert_eq!(Class::of(&storm), Class::NotFound);
+    let corrupt: Error = StormError::Io(io::Error::new(io::ErrorKind::InvalidData, "bad")).into();
+    assert_eq!(Class::of(&corrupt), Class::Corrupt);
+    let denied: Result<(), Error> = Err(io::Error::from(io::ErrorKind::PermissionDenied).into());
+    let wrapped: Error = denied.context("writing map.w3x").unwrap_err().into();
+    assert_eq!(Class::of(&wrapped), Class::Io);
+    assert_eq!(Class::of(&failure(Class::Usage, "bad --top")), Class::Usage);
+    assert_eq!(Class::of(&failure::format_err!("other")), Class::Other);
+    assert_eq!(Class::Corrupt.exit_code(), 4);
+}
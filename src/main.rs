@@ -1,23 +1,53 @@
 extern crate clap;
 use clap::{Arg, App, SubCommand};
 
-use failure::{Error};
-use stormlib::OpenArchiveFlags;
+use failure::{format_err, Error};
+use stormlib::{Compression, FileOptions, OpenArchiveFlags};
 
 use std::collections::HashMap;
 use std::fs;
+use std::io::{self, Read, Write};
+use std::path::Path;
+use std::time::Duration;
 
-struct File {
-    name: String,
-    path: String,
-}
+mod budget;
+mod convert;
+mod diff;
+mod encode;
+mod extract;
+mod filelist;
+mod incremental;
+mod info;
+mod list;
+mod log;
+mod manifest;
+mod merge;
+mod pattern;
+mod plan;
+mod recover;
+mod repair;
+mod temp;
+mod watch;
 
-type FileList = Vec<File>;
+type FileList = Vec<filelist::File>;
 
 fn main() -> Result<(), Error> {
     let matches = App::new("MopaqPack-rs")
         .version("1.0")
         .author("Jai <814683@qq.com>")
+        .after_help(
+            "EXIT CODES:\n    0    success\n    1    other errors\n    2    invalid arguments\n    \
+             3    archive or file not found\n    4    corrupt archive\n    5    I/O error",
+        )
+        .arg(
+            Arg::with_name("log-format")
+                .long("log-format")
+                .value_name("FORMAT")
+                .help("Print progress and errors as text, or as JSON lines on stderr")
+                .possible_values(&["text", "json"])
+                .takes_value(true)
+                .global(true),
+        )
         .subcommand(
             SubCommand::with_name("generate")
                 .about("Generate Warcraft III map file")
@@ -33,7 +63,8 @@ fn main() -> Result<(), Error> {
                     Arg::with_name("filelist")
                         .short("f")
                         .long("filelist")
-                        .help("Generate (filelist)?"),
+                        .help("Generate (filelist)?")
+                        .conflicts_with("base"),
                 )
                 .arg(
                     Arg::with_name("input")
@@ -42,17 +73,117 @@ fn main() -> Result<(), Error> {
                         .value_name("FILE")
                         .help("Input directory or file list")
                         .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("manifest")
+                        .long("manifest")
+                        .value_name("FILE")
+                        .help("Packing rules, defaults to mopaq.toml in the input directory")
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("incremental")
+                        .long("incremental")
+                        .help(
+                            "Reuse unchanged files of the previous output, tracked in \
+                             <FILE>.cache.json",
+                        )
+                        .conflicts_with("base"),
+                )
+                .arg(Arg::with_name("dry-run").short("n").long("dry-run").help(
+                    "Print the files that would be added, replaced and removed, without writing",
+                ))
+                .arg(
+                    Arg::with_name("format")
+                        .long("format")
+                        .value_name("FORMAT")
+                        .help("Format of the plan, text by default")
+                        .possible_values(&["text", "json"])
+                        .requires("dry-run")
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("max-size")
+                        .long("max-size")
+                        .value_name("SIZE")
+                        .help("Fail when the map is larger, e.g. 8MB")
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("base")
+                        .long("base")
+                        .value_name("MAP")
+                        .help("Start from this map, keeping its header and files")
+                        .takes_value(true)
+                        .requires("overlay")
+                        .conflicts_with("incremental"),
+                )
+                .arg(
+                    Arg::with_name("overlay")
+                        .long("overlay")
+                        .value_name("DIR")
+                        .help(
+                            "Directory whose files are added to the base map or replace its files",
+                        )
+                        .takes_value(true)
+                        .requires("base")
+                        .conflicts_with("input"),
                 ),
         )
         .subcommand(
-            SubCommand::with_name("extract")
-                .about("Extract file in MPQ")
+            SubCommand::with_name("watch")
+                .about("Update a map whenever files of its source directory change")
+                .arg(
+                    Arg::with_name("input")
+                        .short("i")
+                        .long("input")
+                        .value_name("DIR")
+                        .help("Source directory")
+                        .takes_value(true)
+                        .required(true),
+                )
                 .arg(
                     Arg::with_name("output")
                         .short("o")
                         .long("output")
                         .value_name("FILE")
-                        .help("Unpack path")
+                        .help("Map to update, generated first if it doesn't exist")
+                        .takes_value(true)
+                        .required(true),
+                )
+                .arg(
+                    Arg::with_name("manifest")
+                        .long("manifest")
+                        .value_name("FILE")
+                        .help("Packing rules, defaults to mopaq.toml in the input directory")
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("debounce")
+                        .long("debounce")
+                        .value_name("MS")
+                        .help("Quiet time before applying a burst of changes")
+                        .takes_value(true)
+                        .default_value("300"),
+                )
+                .arg(
+                    Arg::with_name("no-compact")
+                        .long("no-compact")
+                        .help("Leave the space of replaced and removed files in the map"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("extract")
+                .about("Extract files in MPQ")
+                .arg(
+                    Arg::with_name("output")
+                        .short("o")
+                        .long("output")
+                        .value_name("PATH")
+                        .help(
+                            "Output directory, or the output file when extracting a single \
+                             file name that is not an existing directory",
+                        )
                         .takes_value(true),
                 )
                 .arg(
@@ -68,8 +199,21 @@ fn main() -> Result<(), Error> {
                         .short("f")
                         .long("file")
                         .value_name("FILE")
-                        .help("File name which will extract in MPQ")
-                        .takes_value(true),
+                        .help("File name or glob to extract, extracts everything when omitted")
+                        .takes_value(true)
+                        .multiple(true)
+                        .number_of_values(1),
+                )
+                .arg(
+                    Arg::with_name("overwrite")
+                        .long("overwrite")
+                        .help("Replace files that already exist, the default for a single file")
+                        .conflicts_with("skip-existing"),
+                )
+                .arg(
+                    Arg::with_name("skip-existing")
+                        .long("skip-existing")
+                        .help("Leave files that already exist untouched"),
                 ),
         )
         .subcommand(
@@ -90,52 +234,664 @@ fn main() -> Result<(), Error> {
                         .help("Input directory or file list")
                         .takes_value(true),
                 )
+                .arg(
+                    Arg::with_name("manifest")
+                        .long("manifest")
+                        .value_name("FILE")
+                        .help("Packing rules, defaults to mopaq.toml in the input directory")
+                        .takes_value(true),
+                )
                 .arg(
                     Arg::with_name("remove")
                         .short("r")
                         .long("remove")
                         .help("remove directory or file list")
                         .takes_value(true),
+                )
+                .arg(Arg::with_name("dry-run").short("n").long("dry-run").help(
+                    "Print the files that would be added, replaced and removed, without writing",
+                ))
+                .arg(
+                    Arg::with_name("format")
+                        .long("format")
+                        .value_name("FORMAT")
+                        .help("Format of the plan, text by default")
+                        .possible_values(&["text", "json"])
+                        .requires("dry-run")
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("max-size")
+                        .long("max-size")
+                        .value_name("SIZE")
+                        .help("Fail when the map is larger, e.g. 8MB")
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("listfile")
+                        .short("l")
+                        .long("listfile")
+                        .value_name("FILE")
+                        .help("External listfile used when compacting")
+                        .takes_value(true)
+                        .multiple(true)
+                        .number_of_values(1),
                 ),
         )
-        .get_matches();
-
-    std::process::exit(match run(matches) {
-        Err(error) => {
-            println!("[ERROR] An error has occured. Error chain:");
-            println!("{}", error);
-
-            for cause in error.iter_causes() {
-                println!("{}", cause);
+        .subcommand(
+            SubCommand::with_name("compact")
+                .about("Compact MPQ, optionally into a new file")
+                .arg(
+                    Arg::with_name("mpq")
+                        .short("m")
+                        .long("mpq")
+                        .value_name("FILE")
+                        .help("MPQ file path")
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("output")
+                        .short("o")
+                        .long("output")
+                        .value_name("FILE")
+                        .help("Write the compacted copy here, leaving the MPQ untouched")
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("listfile")
+                        .short("l")
+                        .long("listfile")
+                        .value_name("FILE")
+                        .help("External listfile used when compacting")
+                        .takes_value(true)
+                        .multiple(true)
+                        .number_of_values(1),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("recover")
+                .about("Recover names of unnamed files in MPQ into a listfile")
+                .arg(
+                    Arg::with_name("mpq")
+                        .short("m")
+                        .long("mpq")
+                        .value_name("FILE")
+                        .help("MPQ file path")
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("output")
+                        .short("o")
+                        .long("output")
+                        .value_name("FILE")
+                        .help("Recovered listfile path")
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("dictionary")
+                        .short("d")
+                        .long("dictionary")
+                        .value_name("FILE")
+                        .help("Extra file names to try, one per line")
+                        .takes_value(true)
+                        .multiple(true)
+                        .number_of_values(1),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("repair")
+                .about("Salvage the readable files of a damaged MPQ into a new one")
+                .arg(
+                    Arg::with_name("mpq")
+                        .value_name("IN")
+                        .help("Damaged MPQ file path")
+                        .required(true)
+                        .index(1),
+                )
+                .arg(
+                    Arg::with_name("output")
+                        .value_name("OUT")
+                        .help("Repaired MPQ file path")
+                        .required(true)
+                        .index(2),
+                )
+                .arg(
+                    Arg::with_name("dictionary")
+                        .short("d")
+                        .long("dictionary")
+                        .value_name("FILE")
+                        .help("Extra file names to try, one per line")
+                        .takes_value(true)
+                        .multiple(true)
+                        .number_of_values(1),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("list")
+                .about("List files in MPQ")
+                .arg(
+                    Arg::with_name("mpq")
+                        .value_name("MPQ")
+                        .help("MPQ file path")
+                        .required(true)
+                        .index(1),
+                )
+                .arg(
+                    Arg::with_name("pattern")
+                        .value_name("PATTERN")
+                        .help("Only list files matching these globs, e.g. \"*.mdx\"")
+                        .multiple(true)
+                        .index(2),
+                )
+                .arg(
+                    Arg::with_name("format")
+                        .long("format")
+                        .value_name("FORMAT")
+                        .help("Output format")
+                        .possible_values(&["table", "json", "csv"])
+                        .default_value("table")
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("sort")
+                        .short("s")
+                        .long("sort")
+                        .value_name("KEY")
+                        .help("Sort by name, or by size or compressed size, largest first")
+                        .possible_values(&["name", "size", "compressed"])
+                        .default_value("name")
+                        .takes_value(true),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("rm")
+                .about("Remove files from MPQ")
+                .arg(
+                    Arg::with_name("mpq")
+                        .value_name("MPQ")
+                        .help("MPQ file path")
+                        .required(true)
+                        .index(1),
+                )
+                .arg(
+                    Arg::with_name("pattern")
+                        .value_name("PATTERN")
+                        .help("Names to remove, may contain `*` and `?` wildcards")
+                        .required(true)
+                        .multiple(true)
+                        .index(2),
+                )
+                .arg(
+                    Arg::with_name("dry-run")
+                        .short("n")
+                        .long("dry-run")
+                        .help("Only print the files that would be removed"),
+                )
+                .arg(
+                    Arg::with_name("format")
+                        .long("format")
+                        .value_name("FORMAT")
+                        .help("Format of the plan, text by default")
+                        .possible_values(&["text", "json"])
+                        .requires("dry-run")
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("no-compact")
+                        .long("no-compact")
+                        .help("Leave the space of removed files in the archive"),
+                )
+                .arg(
+                    Arg::with_name("listfile")
+                        .short("l")
+                        .long("listfile")
+                        .value_name("FILE")
+                        .help("External listfile naming files missing from (listfile)")
+                        .takes_value(true)
+                        .multiple(true)
+                        .number_of_values(1),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("cat")
+                .about("Write a file in MPQ to stdout")
+                .arg(
+                    Arg::with_name("mpq")
+                        .value_name("MPQ")
+                        .help("MPQ file path")
+                        .required(true)
+                        .index(1),
+                )
+                .arg(
+                    Arg::with_name("name")
+                        .value_name("NAME")
+                        .help("File name in MPQ")
+                        .required(true)
+                        .index(2),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("put")
+                .about("Write stdin to a file in MPQ, replacing an existing one")
+                .arg(
+                    Arg::with_name("mpq")
+                        .value_name("MPQ")
+                        .help("MPQ file path")
+                        .required(true)
+                        .index(1),
+                )
+                .arg(
+                    Arg::with_name("name")
+                        .value_name("NAME")
+                        .help("File name in MPQ")
+                        .required(true)
+                        .index(2),
+                )
+                .arg(
+                    Arg::with_name("compression")
+                        .long("compression")
+                        .value_name("METHOD")
+                        .help("Compression of the file")
+                        .possible_values(&["none", "zlib", "bzip2", "adpcm-mono", "adpcm-stereo"])
+                        .default_value("zlib")
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("encrypt")
+                        .long("encrypt")
+                        .help("Encrypt the file"),
+                )
+                .arg(
+                    Arg::with_name("locale")
+                        .long("locale")
+                        .value_name("LCID")
+                        .help("Locale of the file, e.g. 1033")
+                        .takes_value(true),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("merge")
+                .about("Merge the files of other MPQs into one")
+                .arg(
+                    Arg::with_name("target")
+                        .value_name("TARGET")
+                        .help("MPQ to merge into, created if it doesn't exist")
+                        .required(true)
+                        .index(1),
+                )
+                .arg(
+                    Arg::with_name("source")
+                        .value_name("SOURCE")
+                        .help("MPQs to take files from, in order")
+                        .required(true)
+                        .multiple(true)
+                        .index(2),
+                )
+                .arg(
+                    Arg::with_name("filter")
+                        .long("filter")
+                        .value_name("GLOB")
+                        .help("Only merge files matching these globs")
+                        .takes_value(true)
+                        .multiple(true)
+                        .number_of_values(1),
+                )
+                .arg(
+                    Arg::with_name("on-conflict")
+                        .long("on-conflict")
+                        .value_name("POLICY")
+                        .help("What to do with files already in the target")
+                        .possible_values(&["keep", "overwrite", "fail"])
+                        .default_value("fail")
+                        .takes_value(true),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("export")
+                .about("Convert MPQ to a zip or tar archive")
+                .arg(
+                    Arg::with_name("mpq")
+                        .value_name("MPQ")
+                        .help("MPQ file path")
+                        .required(true)
+                        .index(1),
+                )
+                .arg(
+                    Arg::with_name("to")
+                        .long("to")
+                        .value_name("FILE")
+                        .help("Output .zip or .tar file")
+                        .takes_value(true)
+                        .required(true),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("import")
+                .about("Convert a zip or tar archive to MPQ")
+                .arg(
+                    Arg::with_name("archive")
+                        .value_name("ARCHIVE")
+                        .help("Input .zip or .tar file")
+                        .required(true)
+                        .index(1),
+                )
+                .arg(
+                    Arg::with_name("to")
+                        .long("to")
+                        .value_name("FILE")
+                        .help("Output MPQ file")
+                        .takes_value(true)
+                        .required(true),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("info")
+                .about("Show map and MPQ metadata")
+                .arg(
+                    Arg::with_name("mpq")
+                        .value_name("MPQ")
+                        .help("Map or MPQ file path")
+                        .required(true)
+                        .index(1),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("budget")
+                .about("Report what takes space in a map and check it against a size budget")
+                .arg(
+                    Arg::with_name("mpq")
+                        .value_name("MPQ")
+                        .help("Map or MPQ file path")
+                        .required(true)
+                        .index(1),
+                )
+                .arg(
+                    Arg::with_name("max-size")
+                        .long("max-size")
+                        .value_name("SIZE")
+                        .help("Fail when the compacted map is larger, e.g. 8MB or 128MB")
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("top")
+                        .long("top")
+                        .value_name("N")
+                        .help("Number of largest files to show")
+                        .takes_value(true)
+                        .default_value("20"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("diff")
+                .about("Compare the files of two MPQs")
+                .arg(
+                    Arg::with_name("old")
+                        .value_name("OLD")
+                        .help("Old MPQ file path")
+                        .required(true)
+                        .index(1),
+                )
+                .arg(
+                    Arg::with_name("new")
+                        .value_name("NEW")
+                        .help("New MPQ file path")
+                        .required(true)
+                        .index(2),
+                ),
+        )
+        .get_matches_safe()
+        .unwrap_or_else(|e| match e.kind {
+            clap::ErrorKind::HelpDisplayed | clap::ErrorKind::VersionDisplayed => e.exit(),
+            _ => {
+                // the arguments couldn't be parsed, look for the log format by hand
+                let args: Vec<String> = std::env::args().collect();
+                log::set_json(
+                    args.windows(2)
+                        .any(|w| w[0] == "--log-format" && w[1] == "json")
+                        || args.iter().any(|a| a == "--log-format=json"),
+                );
+                if log::is_json() {
+                    let error = log::failure(log::Class::Usage, e.message);
+                    std::process::exit(log::error(&error, None));
+                }
+                eprintln!("{}", e.message);
+                std::process::exit(log::Class::Usage.exit_code());
             }
+        });
 
-            1
-        }
+    let log_format = matches
+        .subcommand()
+        .1
+        .and_then(|m| m.value_of("log-format"))
+        .or_else(|| matches.value_of("log-format"));
+    log::set_json(log_format == Some("json"));
+    let path = target(&matches);
+    std::process::exit(match run(matches) {
+        Err(error) => log::error(&error, path.as_deref()),
         Ok(_) => 0,
     });
 }
 
+/// Archive the subcommand works on, reported with errors
+fn target(matches: &clap::ArgMatches) -> Option<String> {
+    let matches = matches.subcommand().1?;
+    ["mpq", "output", "target", "archive", "old"]
+        .iter()
+        .find_map(|arg| matches.value_of(arg))
+        .map(String::from)
+}
+
 fn run(matches: clap::ArgMatches) -> Result<(), Error> {
     if let Some(matches) = matches.subcommand_matches("generate") {
         let output = matches.value_of("output").unwrap();
         let filelist = matches.is_present("filelist");
+        let input = matches
+            .value_of("overlay")
+            .or_else(|| matches.value_of("input"))
+            .ok_or_else(|| log::failure(log::Class::Usage, "--input is required"))?;
+        let max_size = parse_max_size(matches)?;
+        let manifest = load_manifest(input, matches.value_of("manifest"))?;
+        let files = generate_file_list(input, &manifest)?;
+        if matches.is_present("dry-run") {
+            let format = matches.value_of("format").unwrap_or("text").parse()?;
+            let plan = plan_generate(matches.value_of("base"), &files, output, &manifest)?;
+            let max_files = manifest.archive.max_files.unwrap_or(0) as u32;
+            print_plan(&plan, max_files, format, max_size)?;
+        } else {
+            if let Some(base) = matches.value_of("base") {
+                build_within(output, max_size, |path| {
+                    exec_overlay(base, &files, path, &manifest)
+                })?;
+            } else if matches.is_present("incremental") {
+                exec_incremental(&files, output, filelist, &manifest, max_size)?;
+            } else {
+                build_within(output, max_size, |path| {
+                    exec(&files, path, filelist, &manifest)
+                })?;
+            }
+        }
+    } else if let Some(matches) = matches.subcommand_matches("watch") {
         let input = matches.value_of("input").unwrap();
-        let files = generate_file_list(input)?;
-        exec(&files, output, filelist)?;
-    } else if let Some(matches) = matches.subcommand_matches("extract") {
         let output = matches.value_of("output").unwrap();
+        let debounce =
+            matches.value_of("debounce").unwrap().parse().map_err(|e| {
+                log::failure(log::Class::Usage, format!("invalid --debounce: {}", e))
+            })?;
+        let manifest = load_manifest(input, matches.value_of("manifest"))?;
+        if !Path::new(output).is_file() {
+            let files = generate_file_list(input, &manifest)?;
+            exec(&files, output, false, &manifest)?;
+            log::info(&format!("generated {} with {} files", output, files.len()));
+        }
+        let defaults = FileOptions {
+            compression: Compression::None,
+            ..FileOptions::default()
+        };
+        watch::watch(
+            Path::new(input),
+            Path::new(output),
+            &manifest,
+            defaults,
+            Duration::from_millis(debounce),
+            !matches.is_present("no-compact"),
+        )?;
+    } else if let Some(matches) = matches.subcommand_matches("extract") {
+        let output = matches.value_of("output");
         let mpq = matches.value_of("mpq").unwrap();
-        let file = matches.value_of("file").unwrap();
-        extract(mpq, file, output)?;
+        let files: Vec<&str> = matches
+            .values_of("file")
+            .map(|values| values.collect())
+            .unwrap_or_default();
+        let existing = if matches.is_present("overwrite") {
+            Some(extract::Existing::Overwrite)
+        } else if matches.is_present("skip-existing") {
+            Some(extract::Existing::Skip)
+        } else {
+            None
+        };
+        extract(mpq, &files, output, existing)?;
     } else if let Some(matches) = matches.subcommand_matches("pack") {
         let mpq = matches.value_of("mpq").unwrap();
         let input = matches.value_of("input").unwrap();
-        if matches.is_present("remove") {
-            let remove = matches.value_of("remove").unwrap();
-            remove_file(mpq, remove)?;
+        let listfiles = listfiles(matches);
+        let max_size = parse_max_size(matches)?;
+        let remove: Vec<&str> = matches
+            .value_of("remove")
+            .map(|remove| remove.split(';').collect())
+            .unwrap_or_default();
+        let manifest = load_manifest(input, matches.value_of("manifest"))?;
+        let files = generate_file_list(input, &manifest)?;
+        if matches.is_present("dry-run") {
+            let format = matches.value_of("format").unwrap_or("text").parse()?;
+            let mut plan = plan::Plan::open(mpq)?;
+            let (names, _) = matching_files(mpq, &remove, &listfiles)?;
+            for name in &names {
+                plan.remove(name);
+            }
+            add_to_plan(&mut plan, &files, pack_defaults(), &manifest)?;
+            // pack grows the hash table by the number of files before compacting
+            let max_files = (plan.hash_table_size + files.len() as u32)
+                .max(manifest.archive.max_files.unwrap_or(0) as u32);
+            print_plan(&plan, max_files, format, max_size)?;
+        } else if max_size.is_some() {
+            // the map is only replaced by a packed copy that fits the budget
+            build_within(mpq, max_size, |path| {
+                fs::copy(mpq, path)?;
+                pack(path, &files, &remove, &listfiles, &manifest)
+            })?;
+        } else {
+            pack(mpq, &files, &remove, &listfiles, &manifest)?;
+        }
+    } else if let Some(matches) = matches.subcommand_matches("rm") {
+        let mpq = matches.value_of("mpq").unwrap();
+        let patterns: Vec<&str> = matches.values_of("pattern").unwrap().collect();
+        let compact = !matches.is_present("no-compact");
+        let missing = if matches.is_present("dry-run") {
+            let format = matches.value_of("format").unwrap_or("text").parse()?;
+            let (names, missing) = matching_files(mpq, &patterns, &listfiles(matches))?;
+            let mut plan = plan::Plan::open(mpq)?;
+            for name in &names {
+                plan.remove(name);
+            }
+            print_plan(&plan, plan.hash_table_size, format, None)?;
+            if format == plan::Format::Text {
+                for pattern in &missing {
+                    log::file("missing", pattern);
+                }
+            }
+            missing
+        } else {
+            remove_files(mpq, &patterns, compact, &listfiles(matches))?
+        };
+        if !missing.is_empty() {
+            return Err(log::failure(
+                log::Class::NotFound,
+                format!("{} patterns matched no file", missing.len()),
+            ));
         }
-        let files = generate_file_list(input)?;
-        pack(mpq, &files)?;
+    } else if let Some(matches) = matches.subcommand_matches("cat") {
+        let mpq = matches.value_of("mpq").unwrap();
+        let name = matches.value_of("name").unwrap();
+        cat(mpq, name)?;
+    } else if let Some(matches) = matches.subcommand_matches("put") {
+        let mpq = matches.value_of("mpq").unwrap();
+        let name = matches.value_of("name").unwrap();
+        let options = FileOptions {
+            compression: manifest::parse_compression(matches.value_of("compression").unwrap())?,
+            encrypt: matches.is_present("encrypt"),
+            locale: match matches.value_of("locale") {
+                Some(locale) => locale.parse().map_err(|e| {
+                    log::failure(log::Class::Usage, format!("invalid --locale: {}", e))
+                })?,
+                None => 0,
+            },
+            ..FileOptions::default()
+        };
+        put(mpq, name, options)?;
+    } else if let Some(matches) = matches.subcommand_matches("merge") {
+        let target = matches.value_of("target").unwrap();
+        let sources: Vec<&str> = matches.values_of("source").unwrap().collect();
+        let filters: Vec<&str> = matches
+            .values_of("filter")
+            .map(|values| values.collect())
+            .unwrap_or_default();
+        let policy = merge::parse_policy(matches.value_of("on-conflict").unwrap())?;
+        merge_archives(target, &sources, &filters, policy)?;
+    } else if let Some(matches) = matches.subcommand_matches("export") {
+        let mpq = matches.value_of("mpq").unwrap();
+        let to = matches.value_of("to").unwrap();
+        let count = convert::export(Path::new(mpq), Path::new(to))?;
+        log::info(&format!("exported {} files to {}", count, to));
+    } else if let Some(matches) = matches.subcommand_matches("import") {
+        let archive = matches.value_of("archive").unwrap();
+        let to = matches.value_of("to").unwrap();
+        let count = convert::import(Path::new(archive), Path::new(to))?;
+        log::info(&format!("imported {} files to {}", count, to));
+    } else if let Some(matches) = matches.subcommand_matches("compact") {
+        let mpq = matches.value_of("mpq").unwrap();
+        let output = matches.value_of("output");
+        compact(mpq, output, &listfiles(matches))?;
+    } else if let Some(matches) = matches.subcommand_matches("recover") {
+        let mpq = matches.value_of("mpq").unwrap();
+        let output = matches.value_of("output").unwrap();
+        let dictionaries: Vec<&str> = matches
+            .values_of("dictionary")
+            .map(|values| values.collect())
+            .unwrap_or_default();
+        recover_names(mpq, output, &dictionaries)?;
+    } else if let Some(matches) = matches.subcommand_matches("repair") {
+        let mpq = matches.value_of("mpq").unwrap();
+        let output = matches.value_of("output").unwrap();
+        let dictionaries: Vec<&str> = matches
+            .values_of("dictionary")
+            .map(|values| values.collect())
+            .unwrap_or_default();
+        repair_archive(mpq, output, &dictionaries)?;
+    } else if let Some(matches) = matches.subcommand_matches("list") {
+        let mpq = matches.value_of("mpq").unwrap();
+        let patterns: Vec<&str> = matches
+            .values_of("pattern")
+            .map(|values| values.collect())
+            .unwrap_or_default();
+        let format = matches.value_of("format").unwrap().parse()?;
+        let sort = matches.value_of("sort").unwrap().parse()?;
+        list_files(mpq, &patterns, format, sort)?;
+    } else if let Some(matches) = matches.subcommand_matches("info") {
+        let mpq = matches.value_of("mpq").unwrap();
+        print_info(mpq)?;
+    } else if let Some(matches) = matches.subcommand_matches("budget") {
+        let mpq = matches.value_of("mpq").unwrap();
+        let max_size = parse_max_size(matches)?;
+        let top = matches
+            .value_of("top")
+            .unwrap()
+            .parse()
+            .map_err(|e| log::failure(log::Class::Usage, format!("invalid --top: {}", e)))?;
+        print_budget(mpq, max_size, top)?;
+    } else if let Some(matches) = matches.subcommand_matches("diff") {
+        let old = matches.value_of("old").unwrap();
+        let new = matches.value_of("new").unwrap();
+        diff_archives(old, new)?;
     } else {
         println!("{}", matches.usage());
     }
@@ -143,80 +899,688 @@ fn run(matches: clap::ArgMatches) -> Result<(), Error> {
     Ok(())
 }
 
-fn generate_file_list(input: &str) -> Result<FileList, Error> {
+fn listfiles<'a>(matches: &'a clap::ArgMatches) -> Vec<&'a str> {
+    matches
+        .values_of("listfile")
+        .map(|values| values.collect())
+        .unwrap_or_default()
+}
+
+fn parse_max_size(matches: &clap::ArgMatches) -> Result<Option<u64>, Error> {
+    matches
+        .value_of("max-size")
+        .map(|size| {
+            budget::parse_size(size)
+                .map_err(|e| log::failure(log::Class::Usage, format!("--max-size: {}", e)))
+        })
+        .transpose()
+}
+
+/// Loads the manifest given on the command line, or `mopaq.toml` of the input directory
+fn load_manifest(input: &str, manifest: Option<&str>) -> Result<manifest::Manifest, Error> {
+    match manifest {
+        Some(path) => manifest::Manifest::load(path),
+        None if Path::new(input).is_dir() => manifest::Manifest::find(input),
+        None => Ok(manifest::Manifest::default()),
+    }
+}
+
+fn generate_file_list(input: &str, manifest: &manifest::Manifest) -> Result<FileList, Error> {
     let metadata = fs::metadata(input)?;
 
     let mut files = FileList::new();
     if metadata.is_dir() {
-        let walker = globwalk::GlobWalkerBuilder::from_patterns(input, &["*.*"])
+        let walker = globwalk::GlobWalkerBuilder::from_patterns(input, &["**"])
             .build()?
-            .into_iter()
-            .filter_map(Result::ok);
+            .filter_map(Result::ok)
+            .filter(|entry| entry.file_type().is_file());
         for img in walker {
             let p = img.path();
-            files.push(File {
-                name: p.strip_prefix(input).unwrap().to_str().unwrap().to_string(),
-                path: p.to_str().unwrap().to_string(),
-            });
+            let name = p.strip_prefix(input).unwrap().to_str().unwrap().to_string();
+            let relative = name.replace('\\', "/");
+            if relative == manifest::FILE_NAME || !manifest.is_included(&relative) {
+                continue;
+            }
+            files.push(filelist::File::from_path(
+                manifest.rename(&name),
+                p.to_str().unwrap().to_string(),
+            ));
         }
     } else {
         let json = fs::read_to_string(input)?;
+        files = filelist::parse(&json).map_err(|e| format_err!("{}: {}", input, e))?;
+    }
 
-        let data: Vec<Vec<String>> = serde_json::from_str(json.as_str())?;
+    Ok(files)
+}
 
-        for item in data {
-            files.push(File {
-                name: item[0].to_string(),
-                path: item[1].to_string(),
-            });
-        }
+fn exec(
+    files: &FileList,
+    output: &str,
+    filelist: bool,
+    manifest: &manifest::Manifest,
+) -> Result<bool, Error> {
+    // without rules, files are stored as they are
+    let defaults = FileOptions {
+        compression: Compression::None,
+        ..FileOptions::default()
+    };
+    let options = encode::options(files, defaults, manifest);
+    if !encode::is_supported(&options) {
+        return exec_stormlib(files, output, filelist, manifest);
+    }
+    let builder = new_builder(files, filelist, manifest);
+    encode::build(files, &options, Path::new(output), builder)?;
+    for f in files {
+        log::added(&f.name);
     }
 
-    Ok(files)
+    Ok(true)
 }
 
-fn exec(files: &FileList, output: &str, filelist: bool) -> Result<bool, Error> {
+/// Builds `output` with StormLib, compressing one file after the other
+fn exec_stormlib(
+    files: &FileList,
+    output: &str,
+    filelist: bool,
+    manifest: &manifest::Manifest,
+) -> Result<bool, Error> {
     if std::path::Path::new(output).is_file() {
         fs::remove_file(output)?;
     }
-    let ar = stormlib::Archive::create(output, files.len(), filelist)?;
+    let max_files = manifest.archive.max_files.unwrap_or(0).max(files.len());
+    let filelist = manifest.archive.listfile.unwrap_or(filelist);
+    let ar = stormlib::Archive::create(output, max_files, filelist)?;
+    // without rules, files are stored as they are
+    let defaults = FileOptions {
+        compression: Compression::None,
+        ..FileOptions::default()
+    };
     for f in files {
-        let data = fs::read(f.path.as_str())?;
-        ar.write_file(f.name.as_str(), &*data)?;
+        let data = f.read()?;
+        let options = f.overrides.apply(manifest.options(&f.name, defaults));
+        ar.write_file_with(f.name.as_str(), &data, options)?;
+        log::added(&f.name);
     }
 
     Ok(true)
 }
 
-fn extract(mpq: &str, file: &str, output: &str) -> Result<bool, Error> {
+/// Builds `output` from a copy of the `base` map, so its header, `(listfile)`
+/// and the files not in `files` are kept
+fn exec_overlay(
+    base: &str,
+    files: &FileList,
+    output: &str,
+    manifest: &manifest::Manifest,
+) -> Result<bool, Error> {
+    // the output may be the base itself, so it is only replaced once complete
+    let temp = temp::TempFile::next_to(Path::new(output))?;
+    fs::copy(base, temp.path())?;
+    overlay(temp.path(), files, manifest)?;
+    temp.persist(Path::new(output))?;
+    Ok(true)
+}
+
+/// Adds `files` to the map at `map`, replacing the files it already has
+fn overlay(map: &Path, files: &FileList, manifest: &manifest::Manifest) -> Result<(), Error> {
+    let mut ar = stormlib::Archive::open(map, OpenArchiveFlags::MPQ_OPEN_NO_FLAG)?;
+    let count = ar.list()?.len() as u32 + files.len() as u32;
+    let max_files = manifest.archive.max_files.unwrap_or(0) as u32;
+    if count.max(max_files) >= ar.get_max_files()? {
+        ar.set_max_files((count + 1).max(max_files))?;
+    }
+    let defaults = FileOptions {
+        compression: Compression::None,
+        ..FileOptions::default()
+    };
+    for f in files {
+        let options = f.overrides.apply(manifest.options(&f.name, defaults));
+        match &f.source {
+            filelist::Source::Path(path) => ar.add_file_with(f.name.as_str(), path, options)?,
+            filelist::Source::Data(data) => {
+                ar.write_file_with(f.name.as_str(), data, options)?;
+            }
+        }
+        log::file("overlay", &f.name);
+    }
+    // replaced files leave gaps, the map still works if they can't be reclaimed
+    if let Err(e) = ar.compact() {
+        log::warn(&format!("could not compact the overlaid map: {}", e));
+    }
+    Ok(())
+}
+
+fn exec_incremental(
+    files: &FileList,
+    output: &str,
+    filelist: bool,
+    manifest: &manifest::Manifest,
+    max_size: Option<u64>,
+) -> Result<bool, Error> {
+    let builder = new_builder(files, filelist, manifest);
+    let defaults = FileOptions {
+        compression: Compression::None,
+        ..FileOptions::default()
+    };
+    let stats = incremental::build(
+        files,
+        Path::new(output),
+        builder,
+        defaults,
+        manifest,
+        |path| match max_size {
+            Some(max_size) => check_budget(&path.to_string_lossy(), max_size).map(|_| ()),
+            None => Ok(()),
+        },
+    )?;
+    for f in files {
+        log::added(&f.name);
+    }
+    log::info(&format!(
+        "reused {} files, compressed {} files",
+        stats.reused, stats.encoded
+    ));
+
+    Ok(true)
+}
+
+/// Built-in writer set up like `generate` creates archives
+fn new_builder(
+    files: &FileList,
+    filelist: bool,
+    manifest: &manifest::Manifest,
+) -> stormlib::native::ArchiveBuilder {
+    let filelist = manifest.archive.listfile.unwrap_or(filelist);
+    let mut builder = stormlib::native::ArchiveBuilder::new()
+        .listfile(filelist)
+        .attributes(false);
+    if let Some(max_files) = manifest
+        .archive
+        .max_files
+        .filter(|&max| max > files.len() + 1)
+    {
+        builder = builder.hash_table_size(max_files as u32);
+    }
+    builder
+}
+
+fn extract(
+    mpq: &str,
+    files: &[&str],
+    output: Option<&str>,
+    existing: Option<extract::Existing>,
+) -> Result<bool, Error> {
     let mut ar = stormlib::Archive::open(mpq, OpenArchiveFlags::MPQ_OPEN_READ_ONLY)?;
-    let exists = ar.has_file(file).unwrap();
-    if exists {
-        let mut f = ar.open_file(file)?;
-        fs::write(output, f.read_all()?)?;
+    let (selected, missing) = extract::select(&mut ar, files)?;
+
+    let output = Path::new(output.unwrap_or("."));
+    let single_file = files.len() == 1 && !pattern::is_glob(files[0]) && !output.is_dir();
+    let targets = if single_file {
+        // only one locale fits the file asked for, the neutral one comes first
+        selected
+            .into_iter()
+            .take(1)
+            .map(|entry| (entry, output.to_path_buf()))
+            .collect()
+    } else {
+        extract::targets(&selected, output)?
+    };
+    // a single file is written where asked, whole directories are kept safe
+    let existing = existing.unwrap_or(if single_file {
+        extract::Existing::Overwrite
+    } else {
+        extract::Existing::Fail
+    });
+    let extraction = extract::extract(&mut ar, &targets, existing)?;
+
+    for name in &extraction.extracted {
+        log::file("extract", name);
+    }
+    for name in &extraction.skipped {
+        log::file("skip", name);
+    }
+    for name in &missing {
+        log::file("missing", name);
+    }
+    if !missing.is_empty() {
+        return Err(log::failure(
+            log::Class::NotFound,
+            format!("{} requested files were not found", missing.len()),
+        ));
     }
-    println!("extract file {}, {}", file, exists);
     Ok(true)
 }
 
-fn pack(mpq: &str, files: &FileList) -> Result<bool, Error> {
+/// Removes the files matching `remove`, then adds `files`
+fn pack(
+    mpq: &str,
+    files: &FileList,
+    remove: &[&str],
+    listfiles: &[&str],
+    manifest: &manifest::Manifest,
+) -> Result<bool, Error> {
+    if !remove.is_empty() {
+        remove_files(mpq, remove, true, listfiles)?;
+    }
     let mut ar = stormlib::Archive::open(mpq, OpenArchiveFlags::MPQ_OPEN_NO_FLAG)?;
-    let count = ar.get_max_files().unwrap();
-    ar.set_max_files(count + (files.len() as u32));
+    let count = ar.get_max_files().unwrap() + (files.len() as u32);
+    let max_files = manifest.archive.max_files.unwrap_or(0) as u32;
+    ar.set_max_files(count.max(max_files))?;
+    let defaults = pack_defaults();
     for f in files {
-        ar.add_file(f.name.as_str(), f.path.as_str())?;
+        let options = f.overrides.apply(manifest.options(&f.name, defaults));
+        match &f.source {
+            filelist::Source::Path(path) => ar.add_file_with(f.name.as_str(), path, options)?,
+            filelist::Source::Data(data) => {
+                ar.write_file_with(f.name.as_str(), data, options)?;
+            }
+        }
+        log::added(&f.name);
     }
-    ar.compact().unwrap();
+    ar.compact_with_listfiles(listfiles)?;
     Ok(true)
 }
 
-fn remove_file(mpq: &str, file: &str) -> Result<bool, Error> {
+fn pack_defaults() -> FileOptions {
+    FileOptions {
+        encrypt: true,
+        ..FileOptions::default()
+    }
+}
+
+/// Plans `generate`: the output is written again from `files`, or changed
+/// from a copy of `base`
+fn plan_generate(
+    base: Option<&str>,
+    files: &FileList,
+    output: &str,
+    manifest: &manifest::Manifest,
+) -> Result<plan::Plan, Error> {
+    let mut plan = match base {
+        Some(base) => {
+            let mut plan = plan::Plan::open(base)?;
+            plan.target = output.to_string();
+            plan
+        }
+        None => {
+            let mut plan = plan::Plan::open(output)?;
+            plan.prefix = 0;
+            for name in plan.kept() {
+                if !files.iter().any(|f| f.name.eq_ignore_ascii_case(&name)) {
+                    plan.remove(&name);
+                }
+            }
+            plan
+        }
+    };
+    let defaults = FileOptions {
+        compression: Compression::None,
+        ..FileOptions::default()
+    };
+    add_to_plan(&mut plan, files, defaults, manifest)?;
+    Ok(plan)
+}
+
+fn add_to_plan(
+    plan: &mut plan::Plan,
+    files: &FileList,
+    defaults: FileOptions,
+    manifest: &manifest::Manifest,
+) -> Result<(), Error> {
+    let options = encode::options(files, defaults, manifest);
+    let sizes = plan::estimate(files, &options)?;
+    for ((f, options), (size, compressed_size)) in files.iter().zip(&options).zip(sizes) {
+        plan.add(&f.name, options.locale, size, compressed_size);
+    }
+    Ok(())
+}
+
+/// Prints the plan, failing if the estimated archive is larger than `max_size`
+fn print_plan(
+    plan: &plan::Plan,
+    max_files: u32,
+    format: plan::Format,
+    max_size: Option<u64>,
+) -> Result<bool, Error> {
+    let outcome = plan.outcome(max_files);
+    print!("{}", plan.render(&outcome, format));
+    match max_size {
+        Some(max_size) if outcome.estimated_size > max_size => Err(format_err!(
+            "map would be about {} bytes, over the budget of {} bytes",
+            outcome.estimated_size,
+            max_size
+        )),
+        _ => Ok(true),
+    }
+}
+
+/// Names of the files matching `patterns`, and the patterns that matched nothing
+fn matching_files<'a>(
+    mpq: &str,
+    patterns: &[&'a str],
+    listfiles: &[&str],
+) -> Result<(Vec<String>, Vec<&'a str>), Error> {
+    let mut ar = stormlib::Archive::open(mpq, OpenArchiveFlags::MPQ_OPEN_READ_ONLY)?;
+    // names only known from external listfiles can be matched as well
+    for listfile in listfiles {
+        ar.add_listfile(listfile)?;
+    }
+    let entries = ar.list()?;
+    let mut names: Vec<String> = Vec::new();
+    let mut missing = Vec::new();
+    for &pattern in patterns {
+        let matched = pattern::matching(&entries, pattern)?;
+        if matched.is_empty() {
+            missing.push(pattern);
+        }
+        for entry in matched {
+            if !names.iter().any(|n| n.eq_ignore_ascii_case(&entry.name)) {
+                names.push(entry.name);
+            }
+        }
+    }
+    Ok((names, missing))
+}
+
+/// Removes the files matching `patterns`, returning the patterns that matched nothing
+fn remove_files<'a>(
+    mpq: &str,
+    patterns: &[&'a str],
+    compact: bool,
+    listfiles: &[&str],
+) -> Result<Vec<&'a str>, Error> {
     let mut ar = stormlib::Archive::open(mpq, OpenArchiveFlags::MPQ_OPEN_NO_FLAG)?;
-    let spl: Vec<&str> = file.split(";").collect();
-    for _f in spl {
-        ar.remove_file(_f).unwrap();
-        println!("remove file:{}", _f);
+    // names only known from external listfiles can be matched as well
+    for listfile in listfiles {
+        ar.add_listfile(listfile)?;
     }
-    ar.compact().unwrap();
+
+    let mut missing = Vec::new();
+    let mut removed = 0;
+    for &pattern in patterns {
+        let entries = pattern::matching(&ar.list()?, pattern)?;
+        let mut names: Vec<&str> = Vec::new();
+        for entry in &entries {
+            // StormLib only removes the file of the current locale
+            ar.remove_file_locale(&entry.name, entry.locale)?;
+            if !names.iter().any(|n| n.eq_ignore_ascii_case(&entry.name)) {
+                log::file("remove", &entry.name);
+                names.push(&entry.name);
+            }
+        }
+        if entries.is_empty() {
+            missing.push(pattern);
+        }
+        removed += entries.len();
+    }
+    for pattern in &missing {
+        log::file("missing", pattern);
+    }
+    if compact && removed > 0 {
+        ar.compact()?;
+    }
+    Ok(missing)
+}
+
+fn cat(mpq: &str, name: &str) -> Result<bool, Error> {
+    let mut ar = stormlib::Archive::open(mpq, OpenArchiveFlags::MPQ_OPEN_READ_ONLY)?;
+    let mut file = ar.open_file(name)?;
+    let stdout = io::stdout();
+    let mut stdout = stdout.lock();
+    match io::copy(&mut file, &mut stdout).and_then(|_| stdout.flush()) {
+        // the reading end of the pipe has seen enough
+        Err(e) if e.kind() == io::ErrorKind::BrokenPipe => {}
+        result => result.map(|_| ())?,
+    }
+    Ok(true)
+}
+
+fn put(mpq: &str, name: &str, options: FileOptions) -> Result<bool, Error> {
+    // the size is needed before writing, and reading all of stdin first lets
+    // `cat` of the same archive finish before it is opened for writing
+    let mut data = Vec::new();
+    io::stdin().lock().read_to_end(&mut data)?;
+    let ar = stormlib::Archive::open(mpq, OpenArchiveFlags::MPQ_OPEN_NO_FLAG)?;
+    let mut file = ar.create_file(name, data.len() as u64, options)?;
+    file.write_all(&data)?;
+    file.finish()?;
+    Ok(true)
+}
+
+fn merge_archives(
+    target: &str,
+    sources: &[&str],
+    filters: &[&str],
+    policy: stormlib::ConflictPolicy,
+) -> Result<bool, Error> {
+    let paths: Vec<&Path> = sources.iter().map(Path::new).collect();
+    let imports = merge::merge(
+        Path::new(target),
+        &paths,
+        &pattern::Patterns::new(filters)?,
+        policy,
+    )?;
+    for (source, import) in sources.iter().zip(&imports) {
+        for name in &import.copied {
+            log::file_from("copy", name, Some(source));
+        }
+        for name in &import.recompressed {
+            log::file_from("recompress", name, Some(source));
+        }
+        for name in &import.kept {
+            log::file("keep", name);
+        }
+        for name in &import.unnamed {
+            log::file_from("unnamed", name, Some(source));
+        }
+        for name in &import.unreadable {
+            log::file_from("unreadable", name, Some(source));
+        }
+    }
+    Ok(true)
+}
+
+fn compact(mpq: &str, output: Option<&str>, listfiles: &[&str]) -> Result<bool, Error> {
+    match output {
+        Some(output) => {
+            stormlib::Archive::rebuild(mpq, output, listfiles)?;
+            log::info(&format!("rebuild {} to {}", mpq, output));
+        }
+        None => {
+            let mut ar = stormlib::Archive::open(mpq, OpenArchiveFlags::MPQ_OPEN_NO_FLAG)?;
+            ar.compact_with_listfiles(listfiles)?;
+            log::info(&format!("compact {}", mpq));
+        }
+    }
+    Ok(true)
+}
+
+fn recover_names(mpq: &str, output: &str, dictionaries: &[&str]) -> Result<bool, Error> {
+    let mut ar = stormlib::Archive::open(mpq, OpenArchiveFlags::MPQ_OPEN_READ_ONLY)?;
+    let recovery = recover::recover(&mut ar, dictionaries)?;
+    for name in &recovery.recovered {
+        log::file("recover", name);
+    }
+    let mut listfile = recovery.names.join("\r\n");
+    listfile.push_str("\r\n");
+    fs::write(output, listfile)?;
+    log::info(&format!(
+        "recovered {} names, {} files still unnamed",
+        recovery.recovered.len(),
+        recovery.unnamed
+    ));
+    Ok(true)
+}
+
+fn repair_archive(mpq: &str, output: &str, dictionaries: &[&str]) -> Result<bool, Error> {
+    let report = repair::repair(Path::new(mpq), Path::new(output), dictionaries)?;
+    if report.malformed_header {
+        log::warn(&format!("{}: malformed header", mpq));
+    }
+    for table in &report.cut_tables {
+        log::warn(&format!("{}: {} is cut short", mpq, table));
+    }
+    for name in &report.recovered {
+        log::file("recover", name);
+    }
+    for name in &report.copied {
+        log::file("copy", name);
+    }
+    for damaged in &report.damaged {
+        log::warn(&format!(
+            "salvage file {}: sectors {:?} of {} unreadable, filled with zeros",
+            damaged.name, damaged.bad_sectors, damaged.sectors
+        ));
+    }
+    for (name, reason) in &report.lost {
+        log::warn(&format!("lose file {}: {}", name, reason));
+    }
+    log::info(&format!(
+        "repair {} to {}: {} files intact, {} damaged, {} lost, {} still unnamed",
+        mpq,
+        output,
+        report.copied.len(),
+        report.damaged.len(),
+        report.lost.len(),
+        report.unnamed.len()
+    ));
+    Ok(true)
+}
+
+fn list_files(
+    mpq: &str,
+    patterns: &[&str],
+    format: list::Format,
+    sort: list::SortKey,
+) -> Result<bool, Error> {
+    let mut ar = stormlib::Archive::open(mpq, OpenArchiveFlags::MPQ_OPEN_READ_ONLY)?;
+    let patterns = pattern::Patterns::new(patterns)?;
+    let mut entries: Vec<stormlib::FileEntry> = ar
+        .list()?
+        .into_iter()
+        .filter(|e| patterns.is_match(&e.name))
+        .collect();
+    list::sort(&mut entries, sort);
+    print!("{}", list::render(&entries, format));
+    Ok(true)
+}
+
+fn print_info(mpq: &str) -> Result<bool, Error> {
+    let mut file = fs::File::open(mpq)?;
+    let mut prefix = Vec::with_capacity(info::MapHeader::SIZE);
+    (&mut file)
+        .take(info::MapHeader::SIZE as u64)
+        .read_to_end(&mut prefix)?;
+    if let Some(header) = info::MapHeader::parse(&prefix) {
+        println!("Map header");
+        println!("  name:              {}", header.name);
+        println!("  flags:             0x{:08X}", header.flags);
+        println!("  max players:       {}", header.max_players);
+    }
+
+    let (header, user_data) =
+        stormlib::native::MpqHeader::find(&mut file, OpenArchiveFlags::MPQ_OPEN_NO_FLAG)?;
+    println!("MPQ header");
+    println!("  offset:            0x{:X}", header.offset);
+    println!("  format version:    {}", header.format_version + 1);
+    println!("  sector size:       {}", header.sector_size());
+    println!("  hash table size:   {}", header.hash_table_size);
+    println!("  block table size:  {}", header.block_table_size);
+    println!("  archive size:      {}", header.archive_size);
+    if header.malformed {
+        println!("  malformed:         yes, read as v1");
+    }
+    if let Some(user_data) = user_data {
+        println!("  user data:         {} bytes", user_data.content.len());
+    }
+
+    let mut ar = stormlib::Archive::open(mpq, OpenArchiveFlags::MPQ_OPEN_READ_ONLY)?;
+    let entries = ar.list()?;
+    let size: u64 = entries.iter().map(|e| e.file_size as u64).sum();
+    let compressed: u64 = entries.iter().map(|e| e.compressed_size as u64).sum();
+    println!("Files");
+    println!("  count:             {}", entries.len());
+    println!(
+        "  unnamed:           {}",
+        entries.iter().filter(|e| e.is_unnamed()).count()
+    );
+    println!("  total size:        {}", size);
+    println!("  compressed size:   {}", compressed);
+    for special in &["(listfile)", "(attributes)", "(signature)"] {
+        let present = if ar.has_file(special)? { "yes" } else { "no" };
+        println!("  {:<18} {}", format!("{}:", special), present);
+    }
+
+    if ar.has_file("war3map.w3i")? {
+        let data = ar.open_file("war3map.w3i")?.read_all()?;
+        match info::MapInfo::parse(&data) {
+            Some(w3i) => {
+                println!("war3map.w3i");
+                println!("  format version:    {}", w3i.format_version);
+                println!("  name:              {}", w3i.name);
+                println!("  author:            {}", w3i.author);
+                println!("  editor version:    {}", w3i.editor_version);
+                if let Some([major, minor, patch, build]) = w3i.game_version {
+                    println!(
+                        "  game version:      {}.{}.{}.{}",
+                        major, minor, patch, build
+                    );
+                }
+                println!(
+                    "  tileset:           {} ({})",
+                    w3i.tileset,
+                    info::tileset_name(w3i.tileset).unwrap_or("unknown")
+                );
+                println!("  players:           {}", w3i.players);
+            }
+            None => println!("war3map.w3i could not be parsed"),
+        }
+    }
+    Ok(true)
+}
+
+fn print_budget(mpq: &str, max_size: Option<u64>, top: usize) -> Result<bool, Error> {
+    let report = budget::analyze(Path::new(mpq), top)?;
+    print!("{}", budget::render(&report, max_size));
+    if let Some(max_size) = max_size {
+        report.check(max_size)?;
+    }
+    Ok(true)
+}
+
+/// Fails the build with a report of what takes space when `output` is too large
+/// Runs `build` on a temporary file next to `output` when there is a size
+/// budget, so `output` is only replaced by an archive that fits it
+fn build_within<F>(output: &str, max_size: Option<u64>, build: F) -> Result<bool, Error>
+where
+    F: FnOnce(&str) -> Result<bool, Error>,
+{
+    let max_size = match max_size {
+        Some(max_size) => max_size,
+        None => return build(output),
+    };
+    let temp = temp::TempFile::next_to(Path::new(output))?;
+    let path = temp.path().to_string_lossy();
+    build(&path)?;
+    check_budget(&path, max_size)?;
+    temp.persist(Path::new(output))?;
+    Ok(true)
+}
+
+fn check_budget(output: &str, max_size: u64) -> Result<bool, Error> {
+    let report = budget::analyze(Path::new(output), 10)?;
+    if let Err(e) = report.check(max_size) {
+        print!("{}", budget::render(&report, Some(max_size)));
+        return Err(e);
+    }
+    Ok(true)
+}
+
+fn diff_archives(old: &str, new: &str) -> Result<bool, Error> {
+    let mut old_ar = stormlib::Archive::open(old, OpenArchiveFlags::MPQ_OPEN_READ_ONLY)?;
+    let mut new_ar = stormlib::Archive::open(new, OpenArchiveFlags::MPQ_OPEN_READ_ONLY)?;
+    let changes = diff::compare(diff::snapshot(&mut old_ar)?, diff::snapshot(&mut new_ar)?);
+    print!("{}", diff::render(&changes, old, new));
     Ok(true)
 }
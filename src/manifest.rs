@@ -0,0 +1,304 @@
+//! Project manifest, `mopaq.toml`, describing how a directory is packed.
+//!
+//! ```toml
+//! [archive]
+//! max_files = 1024
+//! listfile = true
+//!
+//! [files]
+//! include = ["**"]
+//! exclude = [".git/**", "*.psd"]
+//!
+//! [[rules]]
+//! glob = "*.wav"
+//! compression = "adpcm-stereo"
+//!
+//! [[rules]]
+//! glob = "war3map.*"
+//! encrypt = false
+//!
+//! [[rename]]
+//! from = "imports/"
+//! to = "war3mapImported\\"
+//! ```
+//!
+//! Include and exclude globs match paths relative to the input directory, the
+//! manifest itself is never packed. Without `exclude`, the `.git`, `.svn` and
+//! `.hg` directories are left out.
+//! Renames are applied next, exactly or by prefix when `from` ends with a
+//! separator, and rules match the resulting archive names. Every matching rule
+//! is applied in order, so later rules override the options set by earlier ones.
+
+use failure::{format_err, Error};
+use serde::Deserialize;
+use stormlib::{Compression, FileOptions};
+
+use crate::pattern::Patterns;
+
+use std::fs;
+use std::path::Path;
+
+pub const FILE_NAME: &str = "mopaq.toml";
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ManifestFile {
+    #[serde(default)]
+    archive: ArchiveOptions,
+    #[serde(default)]
+    files:   Selection,
+    #[serde(default)]
+    rules:   Vec<RuleFile>,
+    #[serde(default)]
+    rename:  Vec<Rename>,
+}
+
+/// Options used when creating the archive
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ArchiveOptions {
+    /// Hash table size, defaults to the number of packed files
+    pub max_files: Option<usize>,
+    /// Whether to write `(listfile)`, overriding `--filelist`
+    pub listfile:  Option<bool>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Selection {
+    #[serde(default = "include_all")]
+    include: Vec<String>,
+    #[serde(default = "exclude_vcs")]
+    exclude: Vec<String>,
+}
+
+impl Default for Selection {
+    fn default() -> Self {
+        Selection {
+            include: include_all(),
+            exclude: exclude_vcs(),
+        }
+    }
+}
+
+fn include_all() -> Vec<String> {
+    vec!["**".to_string()]
+}
+
+/// Version control directories, excluded unless `exclude` is set
+fn exclude_vcs() -> Vec<String> {
+    vec![
+        "**/.git/**".to_string(),
+        "**/.svn/**".to_string(),
+        "**/.hg/**".to_string(),
+    ]
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RuleFile {
+    glob:        String,
+    compression: Option<String>,
+    encrypt:     Option<bool>,
+    fix_key:     Option<bool>,
+    single_unit: Option<bool>,
+    locale:      Option<u16>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Rename {
+    pub from: String,
+    pub to:   String,
+}
+
+struct Rule {
+    patterns:    Patterns,
+    compression: Option<Compression>,
+    encrypt:     Option<bool>,
+    fix_key:     Option<bool>,
+    single_unit: Option<bool>,
+    locale:      Option<u16>,
+}
+
+pub struct Manifest {
+    pub archive: ArchiveOptions,
+    include:     Patterns,
+    exclude:     Patterns,
+    rules:       Vec<Rule>,
+    rename:      Vec<Rename>,
+}
+
+impl Default for Manifest {
+    fn default() -> Self {
+        Manifest::parse("").unwrap()
+    }
+}
+
+impl Manifest {
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self, Error> {
+        let path = path.as_ref();
+        let content = fs::read_to_string(path)?;
+        Manifest::parse(&content).map_err(|e| format_err!("{}: {}", path.display(), e))
+    }
+
+    /// Loads `mopaq.toml` from the input directory, or the default manifest
+    /// if there is none
+    pub fn find<P: AsRef<Path>>(dir: P) -> Result<Self, Error> {
+        let path = dir.as_ref().join(FILE_NAME);
+        if path.is_file() {
+            Manifest::load(path)
+        } else {
+            Ok(Manifest::default())
+        }
+    }
+
+    pub fn parse(content: &str) -> Result<Self, Error> {
+        let file: ManifestFile = toml::from_str(content)?;
+        let patterns =
+            |globs: &[String]| Patterns::new(&globs.iter().map(String::as_str).collect::<Vec<_>>());
+        let mut rules = Vec::with_capacity(file.rules.len());
+        for rule in file.rules {
+            rules.push(Rule {
+                patterns:    Patterns::new(&[&rule.glob])?,
+                compression: rule
+                    .compression
+                    .as_deref()
+                    .map(parse_compression)
+                    .transpose()?,
+                encrypt:     rule.encrypt,
+                fix_key:     rule.fix_key,
+                single_unit: rule.single_unit,
+                locale:      rule.locale,
+            });
+        }
+        Ok(Manifest {
+            archive: file.archive,
+            include: patterns(&file.files.include)?,
+            exclude: patterns(&file.files.exclude)?,
+            rules,
+            rename: file.rename,
+        })
+    }
+
+    /// Whether a file of the input directory is packed, `relative` being its
+    /// path relative to the directory
+    pub fn is_included(&self, relative: &str) -> bool {
+        self.include.is_match(relative)
+            && (self.exclude.is_empty() || !self.exclude.is_match(relative))
+    }
+
+    /// Archive name of a file, after the first matching rename rule
+    pub fn rename(&self, name: &str) -> String {
+        let normalized = name.replace('/', "\\");
+        for rename in &self.rename {
+            let from = rename.from.replace('/', "\\");
+            if from.ends_with('\\') {
+                if normalized.len() >= from.len()
+                    && normalized[..from.len()].eq_ignore_ascii_case(&from)
+                {
+                    return format!("{}{}", rename.to, &normalized[from.len()..]);
+                }
+            } else if normalized.eq_ignore_ascii_case(&from) {
+                return rename.to.clone();
+            }
+        }
+        name.to_string()
+    }
+
+    /// Options for the archive name `name`, starting from `defaults`
+    pub fn options(&self, name: &str, defaults: FileOptions) -> FileOptions {
+        let mut options = defaults;
+        for rule in self.rules.iter().filter(|r| r.patterns.is_match(name)) {
+            if let Some(compression) = rule.compression {
+                options.compression = compression;
+            }
+            if let Some(encrypt) = rule.encrypt {
+                options.encrypt = encrypt;
+            }
+            if let Some(fix_key) = rule.fix_key {
+                options.fix_key = fix_key;
+            }
+            if let Some(single_unit) = rule.single_unit {
+                options.single_unit = single_unit;
+            }
+            if let Some(locale) = rule.locale {
+                options.locale = locale;
+            }
+        }
+        options
+    }
+}
+
+pub fn parse_compression(name: &str) -> Result<Compression, Error> {
+    Ok(match name {
+        "none" => Compression::None,
+        "zlib" => Compression::Zlib,
+        "bzip2" => Compression::Bzip2,
+        "adpcm-mono" => Compression::AdpcmMono,
+        "adpcm-stereo" => Compression::AdpcmStereo,
+        other => return Err(format_err!("unknown compression: {}", other)),
+    })
+}
+
+#[test]
+fn test_manifest() {
+    let manifest = Manifest::parse(
+        r#"
+        [archive]
+        max_files = 64
+
+        [files]
+        exclude = ["*.psd", "mopaq.toml"]
+
+        [[rules]]
+        glob = "*"
+        encrypt = true
+
+        [[rules]]
+        glob = "*.wav"
+        compression = "adpcm-stereo"
+
+        [[rules]]
+        glob = "war3map.*"
+        encrypt = false
+        locale = 1033
+
+        [[rename]]
+        from = "imports/"
+        to = "war3mapImported\\"
+
+        [[rename]]
+        from = "src/main.j"
+        to = "war3map.j"
+        "#,
+    )
+    .unwrap();
+    assert_eq!(manifest.archive.max_files, Some(64));
+    assert!(manifest.is_included("war3map.w3i"));
+    assert!(manifest.is_included("README"));
+    assert!(!manifest.is_included("art/Hero.PSD"));
+
+    assert_eq!(
+        manifest.rename("imports/sounds/a.wav"),
+        "war3mapImported\\sounds\\a.wav"
+    );
+    assert_eq!(manifest.rename("src\\main.j"), "war3map.j");
+    assert_eq!(manifest.rename("war3map.w3i"), "war3map.w3i");
+
+    let defaults = FileOptions::default();
+    let wav = manifest.options("war3mapImported\\a.wav", defaults);
+    assert_eq!(wav.compression, Compression::AdpcmStereo);
+    assert!(wav.encrypt);
+    let script = manifest.options("war3map.j", defaults);
+    assert_eq!(script.compression, Compression::Zlib);
+    assert!(!script.encrypt);
+    assert_eq!(script.locale, 1033);
+
+    assert!(Manifest::parse("[[rules]]\nglob = \"*\"\ncompression = \"lz4\"").is_err());
+    assert!(Manifest::parse("unknown = 1").is_err());
+    assert!(Manifest::default().is_included("any/file"));
+    assert!(!Manifest::default().is_included(".git/HEAD"));
+    assert!(!Manifest::default().is_included("lib/.svn/entries"));
+    assert!(manifest.is_included(".hg/store"));
+}
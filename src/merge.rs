@@ -0,0 +1,132 @@
+//! Merging archives for `merge`.
+//!
+//! The target is rebuilt with the built-in writer: its own files come first,
+//! then the files of every source in order, so files are copied as stored
+//! instead of being recompressed whenever possible. Data ahead of the target
+//! archive, like the header of a Warcraft III map, is kept.
+
+use failure::{format_err, Error, Fail};
+use stormlib::native::{Archive, ArchiveBuilder};
+use stormlib::{error::StormError, ConflictPolicy, Import, OpenArchiveFlags};
+
+use crate::pattern::Patterns;
+use crate::temp::TempFile;
+
+use std::fs;
+use std::io::Read;
+use std::path::Path;
+
+pub fn parse_policy(name: &str) -> Result<ConflictPolicy, Error> {
+    Ok(match name {
+        "keep" => ConflictPolicy::Keep,
+        "overwrite" => ConflictPolicy::Overwrite,
+        "fail" => ConflictPolicy::Fail,
+        other => return Err(format_err!("unknown conflict policy: {}", other)),
+    })
+}
+
+/// Merges the files of `sources` matching `patterns` into `target`, which is
+/// created if it doesn't exist, and returns what was imported from each source
+pub fn merge(
+    target: &Path,
+    sources: &[&Path],
+    patterns: &Patterns,
+    policy: ConflictPolicy,
+) -> Result<Vec<Import>, Error> {
+    let mut builder = ArchiveBuilder::new().attributes(false);
+    if target.is_file() {
+        let mut ar = Archive::open(target, OpenArchiveFlags::MPQ_OPEN_NO_FLAG)?;
+        let mut prefix = Vec::new();
+        fs::File::open(target)?
+            .take(ar.header().offset)
+            .read_to_end(&mut prefix)?;
+        builder = builder
+            .prefix(&prefix)
+            .attributes(ar.has_file("(attributes)")?);
+        let own = builder.import_from(&mut ar, |_| true, ConflictPolicy::Overwrite)?;
+        if !own.unnamed.is_empty() {
+            return Err(format_err!(
+                "{}: {} files have no known name and would be lost, add them to (listfile) first",
+                target.display(),
+                own.unnamed.len()
+            ));
+        }
+        if !own.unreadable.is_empty() {
+            return Err(format_err!(
+                "{}: {} files can't be decoded and would be lost: {}",
+                target.display(),
+                own.unreadable.len(),
+                own.unreadable.join(", ")
+            ));
+        }
+    }
+
+    let mut imports = Vec::with_capacity(sources.len());
+    for source in sources {
+        let mut ar = Archive::open(source, OpenArchiveFlags::MPQ_OPEN_NO_FLAG)?;
+        let import = builder
+            .import_from(&mut ar, |entry| patterns.is_match(&entry.name), policy)
+            .map_err(|e| match e {
+                StormError::AlreadyExists => format_err!(
+                    "{}: files already in the target, use --on-conflict to keep or overwrite them",
+                    source.display()
+                ),
+                e => e.context(source.display().to_string()).into(),
+            })?;
+        imports.push(import);
+    }
+
+    // the target is read from until the merged archive is complete
+    let temp = TempFile::next_to(target)?;
+    builder.write(temp.path())?;
+    temp.persist(target)?;
+    Ok(imports)
+}
+
+#[test]
+fn test_merge() {
+    use stormlib::native::testing::TempDir;
+    use stormlib::FileOptions;
+
+    let dir = TempDir::new("mopaq-merge");
+    let write = |name: &str, prefix: &[u8], files: &[(&str, &[u8])]| {
+        let mut builder = ArchiveBuilder::new().prefix(prefix);
+        for (name, data) in files {
+            builder.add(name, data.to_vec(), FileOptions::default());
+        }
+        builder.write(dir.join(name)).unwrap();
+        dir.join(name)
+    };
+    let target = write("map.w3x", b"HM3W", &[("war3map.j", b"main")]);
+    let terrain = write("terrain.mpq", &[], &[("war3map.w3e", b"terrain")]);
+    let assets = write(
+        "assets.mpq",
+        &[],
+        &[("war3map.j", b"other"), ("units\\a.mdx", b"model")],
+    );
+
+    let all = Patterns::new(&[]).unwrap();
+    assert!(merge(&target, &[&assets], &all, ConflictPolicy::Fail).is_err());
+    let imports = merge(
+        &target,
+        &[&terrain, &assets],
+        &Patterns::new(&["*.w3e", "*.j", "units/*"]).unwrap(),
+        ConflictPolicy::Keep,
+    )
+    .unwrap();
+    assert_eq!(imports[0].copied, vec!["war3map.w3e"]);
+    assert_eq!(imports[1].copied, vec!["units\\a.mdx"]);
+    assert_eq!(imports[1].kept, vec!["war3map.j"]);
+
+    assert!(fs::read(&target).unwrap().starts_with(b"HM3W"));
+    let mut ar = Archive::open(&target, OpenArchiveFlags::MPQ_OPEN_NO_FLAG).unwrap();
+    assert_eq!(
+        ar.open_file("war3map.j").unwrap().read_all().unwrap(),
+        b"main"
+    );
+    assert_eq!(
+        ar.open_file("war3map.w3e").unwrap().read_all().unwrap(),
+        b"terrain"
+    );
+    drop(ar);
+}
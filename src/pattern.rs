@@ -0,0 +1,117 @@
+//! Glob patterns matched against archive entry names.
+//!
+//! Matching is case-insensitive like MPQ name lookups, `\` and `/` are treated
+//! as the same separator and `*` also matches across directories, so `*.mdx`
+//! finds models anywhere in the archive.
+
+use failure::Error;
+use globset::{GlobBuilder, GlobSet, GlobSetBuilder};
+use stormlib::FileEntry;
+
+pub struct Patterns {
+    set:   GlobSet,
+    count: usize,
+}
+
+impl Patterns {
+    pub fn new(patterns: &[&str]) -> Result<Self, Error> {
+        let mut builder = GlobSetBuilder::new();
+        for pattern in patterns {
+            builder.add(
+                GlobBuilder::new(&to_slashes(pattern))
+                    .case_insensitive(true)
+                    .literal_separator(false)
+                    .build()?,
+            );
+        }
+        Ok(Patterns {
+            set:   builder.build()?,
+            count: patterns.len(),
+        })
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.count == 0
+    }
+
+    /// Whether `name` matches any pattern, an empty set matches every name
+    pub fn is_match(&self, name: &str) -> bool {
+        self.is_empty() || self.set.is_match(to_slashes(name))
+    }
+}
+
+/// Entries matching `pattern` in every locale; internal files like
+/// `(listfile)` only match when named exactly
+pub fn matching(entries: &[FileEntry], pattern: &str) -> Result<Vec<FileEntry>, Error> {
+    let patterns = Patterns::new(&[pattern])?;
+    let exact = pattern.replace('/', "\\");
+    Ok(entries
+        .iter()
+        .filter(|e| {
+            if e.is_internal() {
+                e.name.eq_ignore_ascii_case(&exact)
+            } else {
+                patterns.is_match(&e.name)
+            }
+        })
+        .cloned()
+        .collect())
+}
+
+/// Whether `pattern` contains glob syntax, or names a single file
+pub fn is_glob(pattern: &str) -> bool {
+    pattern.contains(['*', '?', '[', '{'])
+}
+
+fn to_slashes(name: &str) -> String {
+    name.replace('\\', "/")
+}
+
+#[test]
+fn test_patterns() {
+    let patterns = Patterns::new(&["*.MDX", "war3map.?"]).unwrap();
+    assert!(patterns.is_match("war3mapImported\\Hero.mdx"));
+    assert!(patterns.is_match("war3map.j"));
+    assert!(!patterns.is_match("war3map.w3i"));
+
+    let patterns = Patterns::new(&["war3mapImported/**"]).unwrap();
+    assert!(patterns.is_match("war3mapImported\\sub\\a.blp"));
+    assert!(!patterns.is_match("war3map.j"));
+
+    assert!(Patterns::new(&[]).unwrap().is_match("anything"));
+
+    let entry = |name: &str, locale| FileEntry {
+        name: name.to_string(),
+        hash_index: 0,
+        block_index: 0,
+        file_size: 0,
+        compressed_size: 0,
+        flags: stormlib::FileFlags::EXISTS,
+        file_time: 0,
+        locale,
+    };
+    let entries = vec![
+        entry("(listfile)", 0),
+        entry("war3mapImported\\a.mdx", 0),
+        entry("war3mapImported\\a.mdx", 1031),
+        entry("war3map.j", 0),
+    ];
+    let names = |pattern| -> Vec<(String, u32)> {
+        matching(&entries, pattern)
+            .unwrap()
+            .into_iter()
+            .map(|e| (e.name, e.locale))
+            .collect()
+    };
+    assert_eq!(
+        names("war3mapimported/*.MDX"),
+        vec![
+            ("war3mapImported\\a.mdx".to_string(), 0),
+            ("war3mapImported\\a.mdx".to_string(), 1031),
+        ]
+    );
+    assert_eq!(names("*").len(), 3);
+    assert_eq!(names("(listfile)"), vec![("(listfile)".to_string(), 0)]);
+    assert!(is_glob("*.j"));
+    assert!(!is_glob("war3map.j"));
+}
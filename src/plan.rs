@@ -0,0 +1,324 @@
+//! Plans printed by `--dry-run` instead of changing an archive.
+//!
+//! A plan lists the files a command would add, replace and remove, and
+//! estimates the archive it would leave: the number of files, the hash table
+//! size and the size of the file. Stored sizes of new files are estimated by
+//! compressing them with their options, files StormLib would encode with
+//! ADPCM count with their full size. Internal files like `(listfile)` are
+//! left out.
+
+use failure::{format_err, Error};
+use rayon::prelude::*;
+use serde_json::json;
+use stormlib::native::{ArchiveBuilder, MpqHeader, RawFile};
+use stormlib::{FileEntry, FileOptions, OpenArchiveFlags};
+
+use crate::filelist::File;
+
+use std::fs;
+use std::path::Path;
+use std::str::FromStr;
+
+/// Size of a hash or block table entry
+const TABLE_ENTRY_SIZE: u64 = 16;
+
+/// Size of the MPQ v1 header
+const HEADER_SIZE: u64 = 32;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Format {
+    Text,
+    Json,
+}
+
+impl FromStr for Format {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Error> {
+        match s {
+            "text" => Ok(Format::Text),
+            "json" => Ok(Format::Json),
+            other => Err(format_err!("unknown format: {}", other)),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Action {
+    Add,
+    Replace,
+    Remove,
+}
+
+impl Action {
+    fn name(self) -> &'static str {
+        match self {
+            Action::Add => "add",
+            Action::Replace => "replace",
+            Action::Remove => "remove",
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Change {
+    pub action:          Action,
+    pub name:            String,
+    pub size:            u64,
+    /// Stored size, estimated for added and replaced files
+    pub compressed_size: u64,
+}
+
+#[derive(Debug)]
+pub struct Plan {
+    pub target:          String,
+    pub changes:         Vec<Change>,
+    /// Hash table size of the target, 0 if it doesn't exist yet
+    pub hash_table_size: u32,
+    /// Size of the data ahead of the archive
+    pub prefix:          u64,
+    /// Files of the target left as they are
+    kept:                Vec<FileEntry>,
+}
+
+/// Estimated archive once the plan is carried out
+#[derive(Debug, PartialEq)]
+pub struct Outcome {
+    pub files:           usize,
+    pub hash_table_size: u32,
+    pub estimated_size:  u64,
+}
+
+impl Plan {
+    /// Plan changing the archive at `target`, or creating it if there is none
+    pub fn open(target: &str) -> Result<Plan, Error> {
+        let mut plan = Plan::new(target, Vec::new(), 0);
+        if Path::new(target).is_file() {
+            let mut file = fs::File::open(target)?;
+            let (header, _) = MpqHeader::find(&mut file, OpenArchiveFlags::MPQ_OPEN_NO_FLAG)?;
+            let mut ar = stormlib::Archive::open(target, OpenArchiveFlags::MPQ_OPEN_READ_ONLY)?;
+            plan = Plan::new(target, ar.list()?, header.offset);
+            plan.hash_table_size = header.hash_table_size;
+        }
+        Ok(plan)
+    }
+
+    pub fn new(target: &str, entries: Vec<FileEntry>, prefix: u64) -> Plan {
+        Plan {
+            target: target.to_string(),
+            changes: Vec::new(),
+            hash_table_size: 0,
+            prefix,
+            kept: entries.into_iter().filter(|e| !e.is_internal()).collect(),
+        }
+    }
+
+    /// Names of the files of the target left as they are so far
+    pub fn kept(&self) -> Vec<String> {
+        self.kept.iter().map(|e| e.name.clone()).collect()
+    }
+
+    pub fn remove(&mut self, name: &str) {
+        let removed: Vec<FileEntry> = self.take(name, None);
+        for entry in removed {
+            self.changes.push(Change {
+                action:          Action::Remove,
+                name:            entry.name,
+                size:            entry.file_size as u64,
+                compressed_size: entry.compressed_size as u64,
+            });
+        }
+    }
+
+    pub fn add(&mut self, name: &str, locale: u16, size: u64, compressed_size: u64) {
+        let action = if self.take(name, Some(locale as u32)).is_empty() {
+            Action::Add
+        } else {
+            Action::Replace
+        };
+        self.changes.push(Change {
+            action,
+            name: name.to_string(),
+            size,
+            compressed_size,
+        });
+    }
+
+    /// Takes the kept entries named `name`, of any locale if `locale` is `None`
+    fn take(&mut self, name: &str, locale: Option<u32>) -> Vec<FileEntry> {
+        let name = name.replace('/', "\\");
+        let (taken, kept) = self.kept.drain(..).partition(|e| {
+            e.name.eq_ignore_ascii_case(&name) && locale.is_none_or(|l| e.locale == l)
+        });
+        self.kept = kept;
+        taken
+    }
+
+    pub fn count(&self, action: Action) -> usize {
+        self.changes.iter().filter(|c| c.action == action).count()
+    }
+
+    /// Estimates the archive, with at least `max_files` hash table entries
+    pub fn outcome(&self, max_files: u32) -> Outcome {
+        let new: Vec<&Change> = self
+            .changes
+            .iter()
+            .filter(|c| c.action != Action::Remove)
+            .collect();
+        let files = self.kept.len() + new.len();
+        let hash_table_size = (files as u32 + 1).max(max_files).next_power_of_two().max(4);
+        let stored: u64 = self
+            .kept
+            .iter()
+            .map(|e| e.compressed_size as u64)
+            .chain(new.iter().map(|c| c.compressed_size))
+            .sum();
+        let tables = (hash_table_size as u64 + files as u64) * TABLE_ENTRY_SIZE;
+        Outcome {
+            files,
+            hash_table_size,
+            estimated_size: self.prefix + HEADER_SIZE + stored + tables,
+        }
+    }
+
+    pub fn render(&self, outcome: &Outcome, format: Format) -> String {
+        match format {
+            Format::Text => {
+                let mut out = String::new();
+                for change in &self.changes {
+                    match change.action {
+                        Action::Remove => {
+                            out.push_str(&format!("would remove file {}\n", change.name))
+                        }
+                        action => out.push_str(&format!(
+                            "would {} file {} ({} bytes, about {} stored)\n",
+                            action.name(),
+                            change.name,
+                            change.size,
+                            change.compressed_size
+                        )),
+                    }
+                }
+                out.push_str(&format!(
+                    "{}: {} added, {} replaced, {} removed; {} files, hash table of {} entries, about {} bytes\n",
+                    self.target,
+                    self.count(Action::Add),
+                    self.count(Action::Replace),
+                    self.count(Action::Remove),
+                    outcome.files,
+                    outcome.hash_table_size,
+                    outcome.estimated_size
+                ));
+                out
+            }
+            Format::Json => {
+                let changes: Vec<serde_json::Value> = self
+                    .changes
+                    .iter()
+                    .map(|c| {
+                        json!({
+                            "action": c.action.name(),
+                            "name": c.name,
+                            "size": c.size,
+                            "compressed_size": c.compressed_size,
+                        })
+                    })
+                    .collect();
+                let mut out = serde_json::to_string_pretty(&json!({
+                    "target": self.target,
+                    "changes": changes,
+                    "added": self.count(Action::Add),
+                    "replaced": self.count(Action::Replace),
+                    "removed": self.count(Action::Remove),
+                    "files": outcome.files,
+                    "hash_table_size": outcome.hash_table_size,
+                    "estimated_size": outcome.estimated_size,
+                }))
+                .unwrap();
+                out.push('\n');
+                out
+            }
+        }
+    }
+}
+
+/// Sizes and estimated stored sizes of `files`, compressed on every core
+pub fn estimate(files: &[File], options: &[FileOptions]) -> Result<Vec<(u64, u64)>, Error> {
+    let sector_size = ArchiveBuilder::new().sector_size();
+    files
+        .par_iter()
+        .zip(options)
+        .map(|(f, options)| {
+            let data = f.read()?;
+            // the key doesn't change the size
+            let options = FileOptions {
+                fix_key: false,
+                ..*options
+            };
+            let compressed = RawFile::encode(&f.name, &data, options, sector_size)
+                .map(|raw| raw.data.len())
+                .unwrap_or(data.len());
+            Ok((data.len() as u64, compressed as u64))
+        })
+        .collect()
+}
+
+#[test]
+fn test_plan() {
+    let entry = |name: &str, locale, compressed_size| FileEntry {
+        name: name.to_string(),
+        hash_index: 0,
+        block_index: 0,
+        file_size: compressed_size * 2,
+        compressed_size,
+        flags: stormlib::FileFlags::EXISTS,
+        file_time: 0,
+        locale,
+    };
+    let mut plan = Plan::new(
+        "map.w3x",
+        vec![
+            entry("(listfile)", 0, 10),
+            entry("war3map.j", 0, 100),
+            entry("units.txt", 0, 20),
+            entry("units.txt", 1031, 20),
+            entry("war3mapImported\\old.blp", 0, 500),
+        ],
+        512,
+    );
+    plan.remove("war3mapimported/OLD.blp");
+    plan.remove("units.txt");
+    plan.add("War3map.j", 0, 400, 150);
+    plan.add("war3mapImported\\new.mdx", 0, 1000, 800);
+    assert_eq!(plan.kept(), Vec::<String>::new());
+    assert_eq!(plan.count(Action::Remove), 3);
+    assert_eq!(plan.changes[3].action, Action::Replace);
+    assert_eq!(plan.changes[4].action, Action::Add);
+
+    let outcome = plan.outcome(0);
+    assert_eq!(
+        outcome,
+        Outcome {
+            files:           2,
+            hash_table_size: 4,
+            estimated_size:  512 + 32 + 950 + 6 * 16,
+        }
+    );
+    assert_eq!(plan.outcome(1000).hash_table_size, 1024);
+    let json: serde_json::Value =
+        serde_json::from_str(&plan.render(&outcome, Format::Json)).unwrap();
+    assert_eq!(json["replaced"], 1);
+    assert_eq!(json["changes"][4]["name"], "war3mapImported\\new.mdx");
+    let text = plan.render(&outcome, Format::Text);
+    assert!(text.starts_with("would remove file war3mapImported\\old.blp\n"));
+    assert!(text.ends_with("2 files, hash table of 4 entries, about 1590 bytes\n"));
+
+    let files = vec![File {
+        name:      "war3map.j".to_string(),
+        source:    crate::filelist::Source::Data(b"call Init()\n".repeat(1000)),
+        overrides: Default::default(),
+    }];
+    let sizes = estimate(&files, &[FileOptions::default()]).unwrap();
+    assert_eq!(sizes[0].0, 12_000);
+    assert!(sizes[0].1 < 1000);
+}
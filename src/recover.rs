@@ -0,0 +1,305 @@
+//! Recovers entry names hidden by a stripped `(listfile)`.
+//!
+//! Candidate names come from a built-in dictionary of Warcraft III map files,
+//! user supplied dictionaries and paths scraped from scripts, object data and
+//! models already found in the archive. Each candidate is tested with
+//! `has_file`, and newly found files are scraped again until nothing new turns up.
+
+use failure::Error;
+use stormlib::{Archive, FileEntry};
+
+use std::collections::BTreeSet;
+use std::fs;
+
+/// Files every Warcraft III map or campaign may contain
+const KNOWN_NAMES: &[&str] = &[
+    "(listfile)",
+    "(attributes)",
+    "(signature)",
+    "war3map.j",
+    "scripts\\war3map.j",
+    "war3map.lua",
+    "war3map.w3e",
+    "war3map.w3i",
+    "war3map.wtg",
+    "war3map.wct",
+    "war3map.wts",
+    "war3map.shd",
+    "war3map.mmp",
+    "war3map.wpm",
+    "war3map.doo",
+    "war3mapUnits.doo",
+    "war3map.w3r",
+    "war3map.w3c",
+    "war3map.w3s",
+    "war3map.w3u",
+    "war3map.w3t",
+    "war3map.w3b",
+    "war3map.w3d",
+    "war3map.w3a",
+    "war3map.w3h",
+    "war3map.w3q",
+    "war3map.imp",
+    "war3mapMap.blp",
+    "war3mapMap.tga",
+    "war3mapMap.b00",
+    "war3mapPreview.tga",
+    "war3mapPath.tga",
+    "war3mapExtra.txt",
+    "war3mapMisc.txt",
+    "war3mapSkin.txt",
+    "war3mapSkin.w3u",
+    "war3mapSkin.w3t",
+    "war3mapSkin.w3b",
+    "war3mapSkin.w3d",
+    "war3mapSkin.w3a",
+    "war3mapSkin.w3h",
+    "war3mapSkin.w3q",
+    "conversation.json",
+    "war3campaign.w3u",
+    "war3campaign.w3t",
+    "war3campaign.w3b",
+    "war3campaign.w3d",
+    "war3campaign.w3a",
+    "war3campaign.w3h",
+    "war3campaign.w3q",
+    "war3campaign.w3f",
+    "war3campaign.wts",
+    "war3campaign.imp",
+    "war3campaignSkin.txt",
+    "Scripts\\common.j",
+    "Scripts\\Blizzard.j",
+    "Scripts\\common.ai",
+    "UI\\war3skins.txt",
+    "UI\\MiscData.txt",
+    "UI\\WorldEditStrings.txt",
+    "Units\\UnitData.slk",
+    "Units\\UnitUI.slk",
+    "Units\\AbilityData.slk",
+    "Units\\ItemData.slk",
+    "Units\\UpgradeData.slk",
+    "Units\\CommonAbilityStrings.txt",
+];
+
+/// Folders imports usually live in, tried for every scraped file name
+const IMPORT_FOLDERS: &[&str] = &[
+    "",
+    "war3mapImported\\",
+    "UI\\",
+    "UI\\Widgets\\",
+    "UI\\FrameDef\\",
+    "Textures\\",
+    "Units\\",
+    "Abilities\\",
+    "Abilities\\Spells\\",
+    "Buildings\\",
+    "Doodads\\",
+    "Environment\\",
+    "Objects\\",
+    "Sound\\",
+    "Sound\\Music\\",
+    "Splats\\",
+    "ReplaceableTextures\\CommandButtons\\",
+    "ReplaceableTextures\\CommandButtonsDisabled\\",
+    "ReplaceableTextures\\PassiveButtons\\",
+    "Fonts\\",
+];
+
+/// Extensions a scraped string must end with to be taken as a path
+const EXTENSIONS: &[&str] = &[
+    "j", "lua", "ai", "mdx", "mdl", "blp", "tga", "dds", "jpg", "png", "wav", "mp3", "flac", "ogg",
+    "txt", "slk", "fdf", "toc", "w3u", "w3t", "w3b", "w3d", "w3a", "w3h", "w3q", "imp", "ttf",
+    "otf", "pld", "mpq", "w3x", "w3m",
+];
+
+/// Files whose content is scraped for further names
+const SCRAPED_EXTENSIONS: &[&str] = &[
+    "j", "lua", "ai", "mdx", "mdl", "txt", "slk", "fdf", "toc", "w3u", "w3t", "w3b", "w3d", "w3a",
+    "w3h", "w3q", "imp", "doo", "w3i", "wts", "json",
+];
+
+/// Archive names are looked up in
+pub trait Lookup {
+    fn entries(&mut self) -> Result<Vec<FileEntry>, Error>;
+    fn contains(&mut self, name: &str) -> Result<bool, Error>;
+    fn read(&mut self, name: &str) -> Result<Vec<u8>, Error>;
+}
+
+impl Lookup for Archive {
+    fn entries(&mut self) -> Result<Vec<FileEntry>, Error> {
+        Ok(self.list()?)
+    }
+
+    fn contains(&mut self, name: &str) -> Result<bool, Error> {
+        Ok(self.has_file(name)?)
+    }
+
+    /// Files that can't be read have nothing to scrape
+    fn read(&mut self, name: &str) -> Result<Vec<u8>, Error> {
+        Ok(self
+            .open_file(name)
+            .and_then(|mut f| f.read_all())
+            .unwrap_or_default())
+    }
+}
+
+/// Outcome of a recovery run
+pub struct Recovery {
+    /// Every name known after recovery, including the ones StormLib already knew
+    pub names:     Vec<String>,
+    /// Names found by this run which the archive did not know before
+    pub recovered: Vec<String>,
+    /// Entries that still have no name
+    pub unnamed:   usize,
+}
+
+/// Recovers as many entry names as possible, extending the built-in
+/// dictionary with the names listed in `dictionaries`
+pub fn recover<A: Lookup>(ar: &mut A, dictionaries: &[&str]) -> Result<Recovery, Error> {
+    let entries = ar.entries()?;
+    let unnamed_before = entries.iter().filter(|e| e.is_unnamed()).count();
+    let known: BTreeSet<String> = entries
+        .into_iter()
+        .filter(|e| !e.is_unnamed())
+        .map(|e| e.name)
+        .collect();
+
+    let mut candidates: BTreeSet<String> = KNOWN_NAMES.iter().map(|s| s.to_string()).collect();
+    for dictionary in dictionaries {
+        let content = fs::read_to_string(dictionary)?;
+        candidates.extend(
+            content
+                .lines()
+                .map(str::trim)
+                .filter(|l| !l.is_empty())
+                .map(String::from),
+        );
+    }
+
+    let mut tested = BTreeSet::new();
+    let mut found = BTreeSet::new();
+    let mut scraped = BTreeSet::new();
+    loop {
+        let mut new_found = Vec::new();
+        for candidate in candidates.iter().flat_map(|name| expand(name)) {
+            if tested.insert(candidate.to_lowercase()) && ar.contains(&candidate)? {
+                found.insert(candidate.clone());
+                new_found.push(candidate);
+            }
+        }
+
+        // known names may reference more files even if they were not new to this run
+        let to_scrape: Vec<String> = found
+            .iter()
+            .chain(known.iter())
+            .filter(|name| is_scraped(name) && scraped.insert(name.to_lowercase()))
+            .cloned()
+            .collect();
+        if new_found.is_empty() && to_scrape.is_empty() {
+            break;
+        }
+
+        candidates.clear();
+        for name in to_scrape {
+            let data = ar.read(&name)?;
+            candidates.extend(scrape_names(&data));
+        }
+    }
+
+    let known_lower: BTreeSet<String> = known.iter().map(|n| n.to_lowercase()).collect();
+    let recovered: Vec<String> = found
+        .iter()
+        .filter(|name| !known_lower.contains(&name.to_lowercase()))
+        .cloned()
+        .collect();
+    let mut names: Vec<String> = known.into_iter().chain(found).collect();
+    names.sort_by_key(|name| name.to_lowercase());
+    names.dedup_by(|a, b| a.eq_ignore_ascii_case(b));
+
+    Ok(Recovery {
+        unnamed: unnamed_before.saturating_sub(recovered.len()),
+        names,
+        recovered,
+    })
+}
+
+fn extension(name: &str) -> Option<String> {
+    let file_name = name.rsplit('\\').next()?;
+    let dot = file_name.rfind('.')?;
+    Some(file_name[dot + 1..].to_lowercase())
+}
+
+fn is_scraped(name: &str) -> bool {
+    extension(name).is_some_and(|ext| SCRAPED_EXTENSIONS.contains(&ext.as_str()))
+}
+
+/// Variants of a candidate name worth testing: folder prefixes, model
+/// portraits and the disabled counterpart of command button icons
+fn expand(name: &str) -> Vec<String> {
+    let name = name.trim_start_matches('\\');
+    let file_name = name.rsplit('\\').next().unwrap_or(name);
+    let mut variants = vec![name.to_string()];
+    variants.extend(
+        IMPORT_FOLDERS
+            .iter()
+            .map(|folder| format!("{}{}", folder, file_name)),
+    );
+
+    let lower = name.to_lowercase();
+    if lower.ends_with(".mdl") || lower.ends_with(".mdx") {
+        let stem = &name[..name.len() - 4];
+        variants.push(format!("{}.mdx", stem));
+        variants.push(format!("{}.mdl", stem));
+        variants.push(format!("{}_portrait.mdx", stem));
+    }
+    if lower.ends_with(".blp") || lower.ends_with(".tga") || lower.ends_with(".dds") {
+        let stem = &name[..name.len() - 4];
+        variants.push(format!("{}.blp", stem));
+        variants.push(format!("{}.tga", stem));
+        variants.push(format!("{}.dds", stem));
+    }
+    if file_name.to_lowercase().starts_with("btn") {
+        variants.push(format!(
+            "ReplaceableTextures\\CommandButtonsDisabled\\DIS{}",
+            file_name
+        ));
+    }
+    variants
+}
+
+/// Extracts strings that look like archive paths from text or binary data
+fn scrape_names(data: &[u8]) -> BTreeSet<String> {
+    let mut names = BTreeSet::new();
+    let runs = data
+        .split(|b| !(0x20..0x7f).contains(b))
+        .filter(|run| run.len() >= 4);
+    for run in runs {
+        let run = String::from_utf8_lossy(run).replace("\\\\", "\\");
+        let tokens = run.split(['"', '\'', ',', ';', '|', '(', ')', '=']);
+        for token in tokens {
+            let token = token.trim().replace('/', "\\");
+            if token.is_empty() || token.len() > 260 {
+                continue;
+            }
+            if extension(&token).is_some_and(|ext| EXTENSIONS.contains(&ext.as_str())) {
+                names.insert(token);
+            }
+        }
+    }
+    names
+}
+
+#[test]
+fn test_scrape_names() {
+    let script = br#"call AddSpecialEffect("war3mapImported\\fx.mdx", x, y)
+    set s = "units/human/Footman/Footman.mdl"
+    call DisplayText("hello world")"#;
+    let names = scrape_names(script);
+    assert!(names.contains("war3mapImported\\fx.mdx"));
+    assert!(names.contains("units\\human\\Footman\\Footman.mdl"));
+    assert_eq!(names.len(), 2);
+
+    let mut texs = b"\x00\x00TEXS\x04\x01\x00\x00".to_vec();
+    texs.extend_from_slice(b"Textures\\Skin.blp\x00\x00\x00\x01");
+    assert!(scrape_names(&texs).contains("Textures\\Skin.blp"));
+}
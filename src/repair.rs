@@ -0,0 +1,272 @@
+//! Salvages damaged maps for `repair`.
+//!
+//! The damaged archive is read with the built-in reader, which accepts the
+//! malformed headers map protectors leave behind as well as hash and block
+//! tables cut short by the end of the file. Names missing from `(listfile)`
+//! are recovered like `recover` does, then every entry is copied to a new
+//! archive: intact files as stored, damaged ones decoded with the sectors
+//! that can't be read filled with zeros. Entries that are left without a
+//! name keep the `File00000012.xxx` name they are listed with.
+
+use failure::Error;
+use stormlib::error::StormError;
+use stormlib::native::{self, ArchiveBuilder, RawFile};
+use stormlib::{FileEntry, FileFlags, FileOptions, OpenArchiveFlags};
+
+use crate::recover::{self, Lookup};
+
+use std::fs;
+use std::io::Read;
+use std::path::Path;
+
+/// Size of a hash or block table entry
+const TABLE_ENTRY_SIZE: u64 = 16;
+
+/// File written with some of its sectors lost
+#[derive(Debug, Clone, PartialEq)]
+pub struct Damaged {
+    pub name:        String,
+    /// Indices of the sectors filled with zeros
+    pub bad_sectors: Vec<usize>,
+    pub sectors:     usize,
+}
+
+#[derive(Debug, Default)]
+pub struct Report {
+    /// The header had to be repaired to be read
+    pub malformed_header: bool,
+    /// Tables ending past the end of the file
+    pub cut_tables:       Vec<&'static str>,
+    /// Names recovered for entries missing from `(listfile)`
+    pub recovered:        Vec<String>,
+    /// Files copied intact
+    pub copied:           Vec<String>,
+    pub damaged:          Vec<Damaged>,
+    /// Files written under the name they are listed with
+    pub unnamed:          Vec<String>,
+    /// Files that couldn't be read at all, with the reason
+    pub lost:             Vec<(String, String)>,
+}
+
+/// Looks names up in a damaged archive, scraping what can be read of its files
+struct Salvage<'a>(&'a mut native::Archive);
+
+impl Lookup for Salvage<'_> {
+    fn entries(&mut self) -> Result<Vec<FileEntry>, Error> {
+        Ok(self.0.list()?)
+    }
+
+    fn contains(&mut self, name: &str) -> Result<bool, Error> {
+        Ok(self.0.has_file(name)?)
+    }
+
+    fn read(&mut self, name: &str) -> Result<Vec<u8>, Error> {
+        let mut bad_sectors = Vec::new();
+        Ok(self
+            .0
+            .open_file(name)
+            .and_then(|mut f| f.read_salvaged(&mut bad_sectors))
+            .unwrap_or_default())
+    }
+}
+
+/// Writes what can be read of the archive at `input` to a new archive at
+/// `output`, trying the names listed in `dictionaries` for unnamed entries
+pub fn repair(input: &Path, output: &Path, dictionaries: &[&str]) -> Result<Report, Error> {
+    let file_size = fs::metadata(input)?.len();
+    let mut ar = native::Archive::open(input, OpenArchiveFlags::MPQ_OPEN_CHECK_SECTOR_CRC)?;
+    let header = ar.header().clone();
+    let mut report = Report {
+        malformed_header: header.malformed,
+        ..Report::default()
+    };
+    let tables = [
+        ("hash table", header.hash_table_pos, header.hash_table_size),
+        (
+            "block table",
+            header.block_table_pos,
+            header.block_table_size,
+        ),
+    ];
+    for &(table, pos, size) in &tables {
+        let end = header
+            .offset
+            .saturating_add(pos)
+            .saturating_add(size as u64 * TABLE_ENTRY_SIZE);
+        if size > 0 && end > file_size {
+            report.cut_tables.push(table);
+        }
+    }
+
+    let recovery = recover::recover(&mut Salvage(&mut ar), dictionaries)?;
+    ar.add_known_names(&recovery.names);
+    report.recovered = recovery.recovered;
+
+    let mut prefix = Vec::new();
+    fs::File::open(input)?
+        .take(header.offset)
+        .read_to_end(&mut prefix)?;
+    // a shift too big for the sector size was read as 4 KiB sectors
+    let sector_size_shift = if header.sector_size_shift <= native::MAX_SECTOR_SIZE_SHIFT {
+        header.sector_size_shift
+    } else {
+        native::DEFAULT_SECTOR_SIZE_SHIFT
+    };
+    let mut builder = ArchiveBuilder::new()
+        .prefix(&prefix)
+        .sector_size_shift(sector_size_shift);
+    let entries = ar.list()?;
+    let attributes = entries.iter().any(|e| e.name == "(attributes)");
+    for entry in entries.iter().filter(|e| !e.is_internal()) {
+        let locale = entry.locale as u16;
+        let mut bad_sectors = Vec::new();
+        let salvaged = ar
+            .open_file_locale(&entry.name, entry.locale)
+            .and_then(|mut f| f.read_salvaged(&mut bad_sectors));
+        let sectors = sector_count(entry, header.sector_size());
+        match salvaged {
+            Ok(data) if bad_sectors.is_empty() => {
+                match ar.read_raw_entry(entry) {
+                    Ok(raw) if movable(&raw, &builder) => {
+                        builder.add_raw(&entry.name, raw, locale)?
+                    }
+                    _ => builder.add(
+                        &entry.name,
+                        data,
+                        FileOptions::from_flags(entry.flags, locale),
+                    ),
+                }
+                report.copied.push(entry.name.clone());
+            }
+            Ok(_) if bad_sectors.len() >= sectors => {
+                report
+                    .lost
+                    .push((entry.name.clone(), "no readable sector".to_string()));
+                continue;
+            }
+            Ok(data) => {
+                builder.add(
+                    &entry.name,
+                    data,
+                    FileOptions::from_flags(entry.flags, locale),
+                );
+                report.damaged.push(Damaged {
+                    name: entry.name.clone(),
+                    bad_sectors,
+                    sectors,
+                });
+            }
+            // sounds the built-in reader can't decode are copied as stored
+            Err(StormError::NotSupported) => match ar.read_raw_entry(entry) {
+                Ok(raw) if movable(&raw, &builder) => {
+                    builder.add_raw(&entry.name, raw, locale)?;
+                    report.copied.push(entry.name.clone());
+                }
+                _ => {
+                    report
+                        .lost
+                        .push((entry.name.clone(), StormError::NotSupported.to_string()));
+                    continue;
+                }
+            },
+            Err(e) => {
+                report.lost.push((entry.name.clone(), e.to_string()));
+                continue;
+            }
+        }
+        if entry.is_unnamed() {
+            report.unnamed.push(entry.name.clone());
+        }
+        if entry.file_time != 0 {
+            builder.set_file_time(&entry.name, locale, entry.file_time);
+        }
+    }
+    drop(ar);
+    builder.attributes(attributes).write(output)?;
+    Ok(report)
+}
+
+/// Whether a file can be copied as stored
+fn movable(raw: &RawFile, builder: &ArchiveBuilder) -> bool {
+    raw.fits(builder.sector_size()) && !raw.flags.contains(FileFlags::FIX_KEY)
+}
+
+/// Number of sectors a file is stored in
+fn sector_count(entry: &FileEntry, sector_size: u32) -> usize {
+    if entry.flags.contains(FileFlags::SINGLE_UNIT) {
+        1
+    } else {
+        (entry.file_size as usize).div_ceil(sector_size as usize)
+    }
+}
+
+#[test]
+fn test_repair() {
+    use stormlib::native::testing::{garble_sector, TempDir};
+
+    let dir = TempDir::new("mopaq-repair");
+    let mut script = b"call AddSpecialEffect(\"war3mapImported\\\\fx.mdx\", x, y)\n".to_vec();
+    script.extend((0..10_000u32).map(|i| (i % 251) as u8));
+    let model = b"MDLXVERS".repeat(100);
+    let mut builder = ArchiveBuilder::new().listfile(false).prefix(b"HM3W");
+    builder.add("war3map.j", script.clone(), FileOptions::default());
+    builder.add(
+        "war3mapImported\\fx.mdx",
+        model.clone(),
+        FileOptions::default(),
+    );
+    let input = dir.join("damaged.w3x");
+    builder.write(&input).unwrap();
+
+    garble_sector(&input, "war3map.j", 1);
+
+    let output = dir.join("repaired.w3x");
+    let report = repair(&input, &output, &[]).unwrap();
+    assert!(!report.malformed_header);
+    assert!(report.cut_tables.is_empty());
+    assert_eq!(
+        report.recovered,
+        vec!["war3map.j", "war3mapImported\\fx.mdx"]
+    );
+    assert_eq!(report.copied, vec!["war3mapImported\\fx.mdx"]);
+    assert_eq!(
+        report.damaged,
+        vec![Damaged {
+            name:        "war3map.j".to_string(),
+            bad_sectors: vec![1],
+            sectors:     3,
+        }]
+    );
+    assert!(report.lost.is_empty());
+
+    let mut ar = native::Archive::open(&output, OpenArchiveFlags::MPQ_OPEN_NO_FLAG).unwrap();
+    assert!(ar.has_file("(listfile)").unwrap());
+    let data = ar.open_file("war3map.j").unwrap().read_all().unwrap();
+    assert_eq!(&data[..4096], &script[..4096]);
+    assert!(data[4096..8192].iter().all(|&b| b == 0));
+    assert_eq!(&data[8192..], &script[8192..]);
+    assert_eq!(
+        ar.open_file("war3mapImported\\fx.mdx")
+            .unwrap()
+            .read_all()
+            .unwrap(),
+        model
+    );
+    drop(ar);
+    assert_eq!(&fs::read(&output).unwrap()[..4], b"HM3W");
+
+    // a garbled sector size shift is written back as 4 KiB sectors
+    let offset = native::Archive::open(&input, OpenArchiveFlags::MPQ_OPEN_NO_FLAG)
+        .unwrap()
+        .header()
+        .offset as usize;
+    let mut bytes = fs::read(&input).unwrap();
+    bytes[offset + 14..offset + 16].copy_from_slice(&40u16.to_le_bytes());
+    fs::write(&input, &bytes).unwrap();
+    let report = repair(&input, &output, &[]).unwrap();
+    assert!(report.malformed_header);
+    assert_eq!(report.copied, vec!["war3mapImported\\fx.mdx"]);
+    let ar = native::Archive::open(&output, OpenArchiveFlags::MPQ_OPEN_NO_FLAG).unwrap();
+    assert_eq!(ar.header().sector_size_shift, 3);
+    assert!(!ar.header().malformed);
+}
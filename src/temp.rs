@@ -0,0 +1,87 @@
+//! Temporary files built next to their destination and renamed over it once
+//! complete, so a failed command leaves neither a half-written output nor
+//! a stray file behind.
+
+use std::ffi::OsString;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+/// Temporary files created by this process so far, part of their names
+static CREATED: AtomicUsize = AtomicUsize::new(0);
+
+/// File removed when dropped, unless it was renamed by `persist`
+#[derive(Debug)]
+pub struct TempFile {
+    path:      PathBuf,
+    persisted: bool,
+}
+
+impl TempFile {
+    /// Creates an empty file in the directory of `destination`, named after
+    /// it and this process; an existing file is never reused or replaced
+    pub fn next_to(destination: &Path) -> io::Result<Self> {
+        let name = destination
+            .file_name()
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "not a file path"))?;
+        let mut temp_name = OsString::from(".");
+        temp_name.push(name);
+        temp_name.push(format!(
+            ".{}-{}.tmp",
+            std::process::id(),
+            CREATED.fetch_add(1, Ordering::Relaxed)
+        ));
+        let path = destination.with_file_name(temp_name);
+        fs::OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(&path)?;
+        Ok(TempFile {
+            path,
+            persisted: false,
+        })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Moves the file over `destination`
+    pub fn persist(mut self, destination: &Path) -> io::Result<()> {
+        fs::rename(&self.path, destination)?;
+        self.persisted = true;
+        Ok(())
+    }
+}
+
+impl Drop for TempFile {
+    fn drop(&mut self) {
+        if !self.persisted {
+            fs::remove_file(&self.path).ok();
+        }
+    }
+}
+
+#[test]
+fn test_temp_file() {
+    use stormlib::native::testing::TempDir;
+
+    let dir = TempDir::new("mopaq-temp");
+    let output = dir.join("map.w3x");
+    fs::write(dir.join(".map.w3x.tmp"), b"unrelated").unwrap();
+
+    let temp = TempFile::next_to(&output).unwrap();
+    let other = TempFile::next_to(&output).unwrap();
+    assert_ne!(temp.path(), other.path());
+    assert_eq!(temp.path().parent(), Some(dir.path()));
+    fs::write(temp.path(), b"built").unwrap();
+    let other_path = other.path().to_path_buf();
+    drop(other);
+    assert!(!other_path.exists());
+
+    temp.persist(&output).unwrap();
+    assert_eq!(fs::read(&output).unwrap(), b"built");
+    assert_eq!(fs::read(dir.join(".map.w3x.tmp")).unwrap(), b"unrelated");
+    assert_eq!(fs::read_dir(dir.path()).unwrap().count(), 2);
+}
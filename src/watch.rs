@@ -0,0 +1,259 @@
+//! Live map updates for `watch`.
+//!
+//! The input directory is watched recursively and events are collected until
+//! nothing changed for the debounce delay, so saving several files at once or
+//! an editor writing through a temporary file results in a single update.
+//! Each batch is then applied to the map in place: changed files are added
+//! again, replacing the stored ones, and deleted files are removed. A rename
+//! is seen as a removal followed by an addition. The map is compacted after
+//! each batch unless asked not to, so the space of the replaced files doesn't
+//! pile up over a session.
+
+use failure::Error;
+use notify::{RecursiveMode, Watcher};
+use stormlib::{Archive, FileOptions, OpenArchiveFlags};
+
+use crate::log;
+use crate::manifest::{self, Manifest};
+
+use std::collections::BTreeSet;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{self, RecvTimeoutError};
+use std::time::{Duration, Instant};
+
+/// Change to apply to the map
+#[derive(Debug, PartialEq)]
+pub enum Update {
+    Add {
+        name: String,
+        path: PathBuf,
+    },
+    /// Removes the file, or every file below it when it was a directory
+    Remove {
+        name: String,
+    },
+}
+
+/// Outcome of applying a batch
+#[derive(Debug, Default, PartialEq)]
+pub struct Summary {
+    pub added:   usize,
+    pub removed: usize,
+}
+
+/// Turns the paths reported by the watcher into updates, following the
+/// manifest like `generate` does
+pub fn plan(input: &Path, paths: &BTreeSet<PathBuf>, manifest: &Manifest) -> Vec<Update> {
+    let mut updates = Vec::new();
+    for path in paths {
+        if path.is_dir() {
+            // directories moved into the input carry files no event was sent for
+            let mut files = Vec::new();
+            walk(path, &mut files);
+            for file in files {
+                if let Some(name) = archive_name(input, &file, manifest) {
+                    updates.push(Update::Add { name, path: file });
+                }
+            }
+        } else if let Some(name) = archive_name(input, path, manifest) {
+            if path.is_file() {
+                updates.push(Update::Add {
+                    name,
+                    path: path.clone(),
+                });
+            } else {
+                updates.push(Update::Remove { name });
+            }
+        }
+    }
+    updates
+}
+
+fn archive_name(input: &Path, path: &Path, manifest: &Manifest) -> Option<String> {
+    let name = path.strip_prefix(input).ok()?.to_str()?.to_string();
+    let relative = name.replace('\\', "/");
+    if relative.is_empty() || relative == manifest::FILE_NAME || !manifest.is_included(&relative) {
+        return None;
+    }
+    Some(manifest.rename(&name))
+}
+
+fn walk(dir: &Path, files: &mut Vec<PathBuf>) {
+    if let Ok(entries) = fs::read_dir(dir) {
+        for entry in entries.filter_map(Result::ok) {
+            let path = entry.path();
+            if path.is_dir() {
+                walk(&path, files);
+            } else if path.is_file() {
+                files.push(path);
+            }
+        }
+    }
+}
+
+/// Applies a batch of updates to the map, compacting it afterwards if `compact`
+pub fn apply(
+    map: &Path,
+    updates: &[Update],
+    manifest: &Manifest,
+    defaults: FileOptions,
+    compact: bool,
+) -> Result<Summary, Error> {
+    let mut ar = Archive::open(map, OpenArchiveFlags::MPQ_OPEN_NO_FLAG)?;
+    let added = updates
+        .iter()
+        .filter(|u| matches!(u, Update::Add { .. }))
+        .count() as u32;
+    let max_files = ar.get_max_files()?;
+    let needed = ar.list()?.len() as u32 + added;
+    if needed >= max_files {
+        ar.set_max_files((needed + 1).next_power_of_two())?;
+    }
+
+    let mut summary = Summary::default();
+    for update in updates {
+        match update {
+            Update::Add { name, path } => {
+                let options = manifest.options(name, defaults);
+                ar.add_file_with(name, path.to_str().unwrap(), options)?;
+                log::file("update", name);
+                summary.added += 1;
+            }
+            Update::Remove { name } => {
+                let removed = if ar.has_file(name)? {
+                    vec![name.clone()]
+                } else {
+                    let prefix = format!("{}\\", name.replace('/', "\\").to_lowercase());
+                    ar.list()?
+                        .into_iter()
+                        .map(|entry| entry.name)
+                        .filter(|n| n.replace('/', "\\").to_lowercase().starts_with(&prefix))
+                        .collect()
+                };
+                for name in removed {
+                    ar.remove_file(&name)?;
+                    log::file("remove", &name);
+                    summary.removed += 1;
+                }
+            }
+        }
+    }
+    if compact {
+        ar.compact()?;
+    }
+    Ok(summary)
+}
+
+/// Watches `input` until the process is stopped, applying every change to `map`
+pub fn watch(
+    input: &Path,
+    map: &Path,
+    manifest: &Manifest,
+    defaults: FileOptions,
+    debounce: Duration,
+    compact: bool,
+) -> Result<(), Error> {
+    // the map itself may live in the input directory
+    let map_path = fs::canonicalize(map)?;
+    let input = fs::canonicalize(input)?;
+    let (tx, rx) = mpsc::channel();
+    let mut watcher = notify::recommended_watcher(tx)?;
+    watcher.watch(&input, RecursiveMode::Recursive)?;
+    log::info(&format!("watching {}", input.display()));
+
+    loop {
+        // wait for a first change, then until changes stop for `debounce`
+        let mut paths = BTreeSet::new();
+        let mut received = rx.recv()?;
+        loop {
+            match received {
+                Ok(event) => paths.extend(
+                    event
+                        .paths
+                        .into_iter()
+                        .filter(|p| !p.starts_with(&map_path)),
+                ),
+                Err(e) => log::warn(&format!("watch error: {}", e)),
+            }
+            received = match rx.recv_timeout(debounce) {
+                Ok(received) => received,
+                Err(RecvTimeoutError::Timeout) => break,
+                Err(RecvTimeoutError::Disconnected) => return Ok(()),
+            };
+        }
+
+        if paths.iter().any(|p| p.ends_with(manifest::FILE_NAME)) {
+            log::warn(&format!(
+                "{} changed, restart watch to apply it",
+                manifest::FILE_NAME
+            ));
+        }
+        let updates = plan(&input, &paths, manifest);
+        if updates.is_empty() {
+            continue;
+        }
+        let start = Instant::now();
+        match apply(map, &updates, manifest, defaults, compact) {
+            Ok(summary) => log::info(&format!(
+                "rebuilt {}: {} updated, {} removed in {} ms",
+                map.display(),
+                summary.added,
+                summary.removed,
+                start.elapsed().as_millis()
+            )),
+            // keep watching, the next save may fix it
+            Err(e) => log::warn(&format!("rebuild failed: {}", e)),
+        }
+    }
+}
+
+#[test]
+fn test_plan() {
+    use stormlib::native::testing::TempDir;
+
+    let dir = TempDir::new("mopaq-watch");
+    fs::create_dir_all(dir.join("imports/units")).unwrap();
+    fs::write(dir.join("war3map.j"), "").unwrap();
+    fs::write(dir.join("art.psd"), "").unwrap();
+    fs::write(dir.join("imports/units/a.mdx"), "").unwrap();
+    let manifest = Manifest::parse(
+        r#"
+        [files]
+        exclude = ["*.psd"]
+
+        [[rename]]
+        from = "imports/"
+        to = "war3mapImported\\"
+        "#,
+    )
+    .unwrap();
+
+    let paths: BTreeSet<PathBuf> = vec![
+        dir.join("war3map.j"),
+        dir.join("art.psd"),
+        dir.join("mopaq.toml"),
+        dir.join("imports"),
+        dir.join("war3map.lua"),
+        PathBuf::from("/elsewhere/file"),
+    ]
+    .into_iter()
+    .collect();
+    let updates = plan(dir.path(), &paths, &manifest);
+    assert_eq!(
+        updates,
+        vec![
+            Update::Add {
+                name: "war3mapImported\\units\\a.mdx".to_string(),
+                path: dir.join("imports/units/a.mdx"),
+            },
+            Update::Add {
+                name: "war3map.j".to_string(),
+                path: dir.join("war3map.j"),
+            },
+            Update::Remove {
+                name: "war3map.lua".to_string(),
+            },
+        ]
+    );
+}